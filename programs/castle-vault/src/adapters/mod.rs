@@ -1,5 +1,9 @@
+pub mod orca_legacy;
 pub mod port;
 pub mod solend;
+pub mod whirlpool;
 
+pub use orca_legacy::*;
 pub use port::*;
 pub use solend::*;
+pub use whirlpool::*;
@@ -0,0 +1,144 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_spl::token;
+use boolinator::Boolinator;
+use spl_token_swap::state::{SwapState, SwapVersion};
+
+use crate::{
+    errors::ErrorCode,
+    math::calc_swap_quote,
+    sell_port_reward::{load_dex_markets, DexAdapter, SwapTokenAccounts},
+    state::{Dex, DexMarket, DexMarkets, DexStates},
+};
+
+/// Accounts of a swap through an Orca legacy (constant product) market
+pub struct OrcaLegacySwap<'info> {
+    pub swap_program: AccountInfo<'info>,
+    pub swap_state: AccountInfo<'info>,
+    // DANGER why can we ignore this? because the the Orca program will check this
+    //        and fail the CPI if this account is invalid.
+    // TODO Security audit to ensure it's really ok.
+    pub swap_authority: AccountInfo<'info>,
+    pub pool_source: AccountInfo<'info>,
+    pub pool_destination: AccountInfo<'info>,
+    pub pool_mint: AccountInfo<'info>,
+    pub fee_account: AccountInfo<'info>,
+}
+
+impl<'info> OrcaLegacySwap<'info> {
+    /// Number of accounts of a hop in the remaining accounts of a route:
+    /// the Orca legacy `DexMarkets` registry of the vault, the swap program, the swap state,
+    /// the swap authority, the pool token accounts of the input and output tokens,
+    /// the pool token mint and the fee account
+    pub const ROUTE_ACCOUNTS_LEN: usize = 8;
+
+    /// Loads a hop of a route, checking the market against the registry of the vault
+    pub fn from_route_accounts(
+        vault: &Pubkey,
+        dex_states: &DexStates,
+        market_id: u8,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<(Self, DexMarket)> {
+        (accounts.len() == Self::ROUTE_ACCOUNTS_LEN).ok_or(ErrorCode::InsufficientAccounts)?;
+
+        let registry = load_dex_markets(vault, dex_states, Dex::OrcaLegacy, &accounts[0])?;
+
+        let swap = Self {
+            swap_program: accounts[1].clone(),
+            swap_state: accounts[2].clone(),
+            swap_authority: accounts[3].clone(),
+            pool_source: accounts[4].clone(),
+            pool_destination: accounts[5].clone(),
+            pool_mint: accounts[6].clone(),
+            fee_account: accounts[7].clone(),
+        };
+        let market = swap.check_registered(&registry, market_id)?;
+        Ok((swap, market))
+    }
+
+    /// Checks that the swap goes through the given market of the registry, returns the market
+    pub fn check_registered(&self, registry: &DexMarkets, market_id: u8) -> Result<DexMarket> {
+        let market = *registry.get_market(market_id)?;
+        (self.swap_program.key() == market.swap_program).ok_or(ErrorCode::InvalidAccount)?;
+
+        // No need to check other orca accounts, because those are checked by the swap program.
+        // using data stored in orca_swap_state.
+        // We only have to check the integrity of orca_swap_state
+        (self.swap_state.key() == market.market).ok_or(ErrorCode::InvalidAccount)?;
+
+        Ok(market)
+    }
+
+    /// Mints of the tokens of a swap state, checking that it is an initialized market of the swap program
+    pub fn market_mints(
+        swap_program: &Pubkey,
+        swap_state: &AccountInfo,
+    ) -> Result<(Pubkey, Pubkey)> {
+        (swap_state.owner == swap_program).ok_or(ErrorCode::InvalidAccount)?;
+        let state = SwapVersion::unpack(&swap_state.try_borrow_data()?)
+            .map_err(|_| ErrorCode::InvalidAccount)?;
+        state.is_initialized().ok_or(ErrorCode::InvalidAccount)?;
+        Ok((*state.token_a_mint(), *state.token_b_mint()))
+    }
+}
+
+impl<'info> DexAdapter<'info> for OrcaLegacySwap<'info> {
+    fn quote(&self, source_mint: &Pubkey, amount_in: u64) -> Result<u64> {
+        (token::accessor::mint(&self.pool_source)? == *source_mint)
+            .ok_or(ErrorCode::InvalidAccount)?;
+
+        calc_swap_quote(
+            amount_in,
+            token::accessor::amount(&self.pool_source)?,
+            token::accessor::amount(&self.pool_destination)?,
+        )
+        .ok_or_else(|| ErrorCode::MathError.into())
+    }
+
+    fn swap(
+        &self,
+        accounts: &SwapTokenAccounts<'info>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let ix = spl_token_swap::instruction::swap(
+            &self.swap_program.key(),
+            &accounts.token_program.key(),
+            &self.swap_state.key(),
+            &self.swap_authority.key(),
+            &accounts.authority.key(),
+            &accounts.source.key(),
+            &self.pool_source.key(),
+            &self.pool_destination.key(),
+            &accounts.destination.key(),
+            &self.pool_mint.key(),
+            &self.fee_account.key(),
+            None,
+            spl_token_swap::instruction::Swap {
+                amount_in,
+                minimum_amount_out,
+            },
+        )?;
+
+        let account_infos: Vec<AccountInfo> = vec![
+            self.swap_program.clone(),
+            accounts.token_program.clone(),
+            self.swap_state.clone(),
+            self.swap_authority.clone(),
+            accounts.authority.clone(),
+            accounts.source.clone(),
+            self.pool_source.clone(),
+            self.pool_destination.clone(),
+            accounts.destination.clone(),
+            self.pool_mint.clone(),
+            self.fee_account.clone(),
+        ];
+
+        invoke_signed(&ix, &account_infos, signer_seeds).map_err(Into::into)
+    }
+
+    fn market(&self) -> Pubkey {
+        self.swap_state.key()
+    }
+}
@@ -16,7 +16,7 @@ use crate::{
     impl_has_vault,
     init_yield_source::YieldSourceInitializer,
     reconcile::LendingMarket,
-    refresh::Refresher,
+    refresh::{update_refreshed_allocation, Refresher},
     reserves::{Provider, ReserveAccessor, ReturnCalculator},
     state::{Vault, VaultPortAdditionalState, YieldSourceFlags},
 };
@@ -27,6 +27,7 @@ pub struct PortAccounts<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
         has_one = vault_reserve_token,
         has_one = vault_port_lp_token,
@@ -256,8 +257,8 @@ impl<'info> LendingMarket for PortAccounts<'info> {
         self.vault_reserve_token.amount
     }
 
-    fn lp_tokens_in_vault(&self) -> u64 {
-        self.vault_port_lp_token.amount + self.vault_port_stake_account.deposited_amount
+    fn lp_tokens_in_vault(&self) -> Result<u64> {
+        Ok(self.vault_port_lp_token.amount + self.vault_port_stake_account.deposited_amount)
     }
 
     fn provider(&self) -> Provider {
@@ -377,6 +378,7 @@ impl ReturnCalculator for Reserve {
 pub struct InitializePort<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
         has_one = vault_authority,
     )]
@@ -430,6 +432,7 @@ pub struct RefreshPort<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_port_lp_token,
         has_one = port_reserve,
     )]
@@ -507,7 +510,7 @@ impl<'info> Refresher<'info> for RefreshPort<'info> {
             #[cfg(feature = "debug")]
             msg!("Refresh port reserve token value: {}", port_value);
 
-            self.vault.actual_allocations[Provider::Port].update(port_value, Clock::get()?.slot);
+            update_refreshed_allocation(&mut self.vault, Provider::Port, port_value)?;
         }
 
         Ok(())
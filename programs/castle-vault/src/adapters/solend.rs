@@ -5,25 +5,33 @@ use std::{
 
 use anchor_lang::{prelude::*, solana_program};
 use anchor_spl::token::{Token, TokenAccount};
+use boolinator::Boolinator;
 use solana_maths::{Rate, TryMul};
-use spl_token_lending::state::Reserve;
+use spl_token_lending::state::{Obligation, Reserve};
 
 use crate::{
     errors::ErrorCode,
     impl_has_vault,
     init_yield_source::YieldSourceInitializer,
-    reconcile::LendingMarket,
-    refresh::Refresher,
+    reconcile::{HasVault, LendingMarket},
+    refresh::{update_refreshed_allocation, Refresher},
     reserves::{Provider, ReserveAccessor, ReturnCalculator},
     state::{Vault, YieldSourceFlags},
 };
 
+/// Merkle distributor program that the Solend liquidity mining rewards are claimed from.
+/// Claims are signed by the vault authority, so no other program can be registered.
+pub mod solend_reward_distributor {
+    anchor_lang::declare_id!("MRKGLMizK9XSTaD1d1jbVkdHZbQVCSnPpYiTw9aKQv8");
+}
+
 #[derive(Accounts)]
 pub struct SolendAccounts<'info> {
     /// Vault state account
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
         has_one = vault_reserve_token,
         has_one = vault_solend_lp_token,
@@ -78,87 +86,250 @@ pub struct SolendAccounts<'info> {
 
 impl_has_vault!(SolendAccounts<'_>);
 
-impl<'info> LendingMarket for SolendAccounts<'info> {
+/// Accounts of the vault's solend obligation
+pub struct SolendObligationAccounts<'info> {
+    /// Obligation holding the vault's solend lp tokens
+    pub vault_solend_obligation: AccountInfo<'info>,
+
+    /// Account where the lending program stores the lp tokens deposited into obligations
+    pub solend_collateral_supply: AccountInfo<'info>,
+}
+
+/// Solend accounts of a reconcile, along with the obligation accounts if the vault
+/// deposits through its obligation
+pub struct SolendMarket<'a, 'info> {
+    pub accounts: &'a mut SolendAccounts<'info>,
+    pub obligation: Option<SolendObligationAccounts<'info>>,
+}
+
+impl<'a, 'info> SolendMarket<'a, 'info> {
+    /// Takes the obligation accounts off the front of the remaining accounts
+    /// if the vault deposits through its obligation.
+    /// They are the vault obligation followed by the reserve collateral supply.
+    pub fn load(
+        accounts: &'a mut SolendAccounts<'info>,
+        remaining_accounts: &mut &[AccountInfo<'info>],
+    ) -> Result<Self> {
+        let (vault_solend_obligation, rest) =
+            split_vault_obligation(&accounts.vault, *remaining_accounts)?;
+        let obligation = match vault_solend_obligation {
+            Some(vault_solend_obligation) => {
+                let (solend_collateral_supply, rest) =
+                    rest.split_first().ok_or(ErrorCode::InsufficientAccounts)?;
+                (solend_collateral_supply.key()
+                    == accounts.solend_reserve.collateral.supply_pubkey)
+                    .ok_or(ErrorCode::InvalidAccount)?;
+
+                *remaining_accounts = rest;
+                Some(SolendObligationAccounts {
+                    vault_solend_obligation: vault_solend_obligation.clone(),
+                    solend_collateral_supply: solend_collateral_supply.clone(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            accounts,
+            obligation,
+        })
+    }
+
+    /// Moves all the solend lp tokens held by the vault into its obligation, if it has one
+    fn collateralize(&mut self) -> Result<()> {
+        let obligation = match &self.obligation {
+            Some(obligation) => obligation,
+            None => return Ok(()),
+        };
+
+        self.accounts.vault_solend_lp_token.reload()?;
+        let amount = self.accounts.vault_solend_lp_token.amount;
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let context = CpiContext::new(
+            self.accounts.solend_program.clone(),
+            DepositObligationCollateral {
+                lending_program: self.accounts.solend_program.clone(),
+                source_collateral: self.accounts.vault_solend_lp_token.to_account_info(),
+                destination_collateral: obligation.solend_collateral_supply.clone(),
+                deposit_reserve: self.accounts.solend_reserve.to_account_info(),
+                obligation: obligation.vault_solend_obligation.clone(),
+                lending_market: self.accounts.solend_market.clone(),
+                obligation_owner: self.accounts.vault_authority.clone(),
+                transfer_authority: self.accounts.vault_authority.clone(),
+                clock: self.accounts.clock.to_account_info(),
+                token_program_id: self.accounts.token_program.to_account_info(),
+            },
+        );
+        deposit_obligation_collateral(
+            context.with_signer(&[&self.accounts.vault.authority_seeds()]),
+            amount,
+        )
+    }
+
+    /// Withdraws solend lp tokens from the obligation into the vault, if it has one
+    fn decollateralize(&self, amount: u64) -> Result<()> {
+        let obligation = match &self.obligation {
+            Some(obligation) => obligation,
+            None => return Ok(()),
+        };
+
+        let refresh_context = CpiContext::new(
+            self.accounts.solend_program.clone(),
+            RefreshObligation {
+                lending_program: self.accounts.solend_program.clone(),
+                obligation: obligation.vault_solend_obligation.clone(),
+                clock: self.accounts.clock.to_account_info(),
+            },
+        );
+        refresh_obligation(
+            refresh_context
+                .with_remaining_accounts(vec![self.accounts.solend_reserve.to_account_info()]),
+        )?;
+
+        let context = CpiContext::new(
+            self.accounts.solend_program.clone(),
+            WithdrawObligationCollateral {
+                lending_program: self.accounts.solend_program.clone(),
+                source_collateral: obligation.solend_collateral_supply.clone(),
+                destination_collateral: self.accounts.vault_solend_lp_token.to_account_info(),
+                withdraw_reserve: self.accounts.solend_reserve.to_account_info(),
+                obligation: obligation.vault_solend_obligation.clone(),
+                lending_market: self.accounts.solend_market.clone(),
+                lending_market_authority: self.accounts.solend_market_authority.clone(),
+                obligation_owner: self.accounts.vault_authority.clone(),
+                clock: self.accounts.clock.to_account_info(),
+                token_program_id: self.accounts.token_program.to_account_info(),
+            },
+        );
+        withdraw_obligation_collateral(
+            context.with_signer(&[&self.accounts.vault.authority_seeds()]),
+            amount,
+        )
+    }
+}
+
+impl HasVault for SolendMarket<'_, '_> {
+    fn vault(&self) -> &Vault {
+        self.accounts.vault()
+    }
+
+    fn vault_mut(&mut self) -> &mut Vault {
+        self.accounts.vault_mut()
+    }
+
+    fn vault_key(&self) -> Pubkey {
+        self.accounts.vault_key()
+    }
+}
+
+impl LendingMarket for SolendMarket<'_, '_> {
     fn deposit(&mut self, amount: u64) -> Result<()> {
         let context = CpiContext::new(
-            self.solend_program.clone(),
+            self.accounts.solend_program.clone(),
             DepositReserveLiquidity {
-                lending_program: self.solend_program.clone(),
-                source_liquidity: self.vault_reserve_token.to_account_info(),
-                destination_collateral_account: self.vault_solend_lp_token.to_account_info(),
-                reserve: self.solend_reserve.to_account_info(),
-                reserve_collateral_mint: self.solend_lp_mint.clone(),
-                reserve_liquidity_supply: self.solend_reserve_token.clone(),
-                lending_market: self.solend_market.clone(),
-                lending_market_authority: self.solend_market_authority.clone(),
-                transfer_authority: self.vault_authority.clone(),
-                clock: self.clock.to_account_info(),
-                token_program_id: self.token_program.to_account_info(),
+                lending_program: self.accounts.solend_program.clone(),
+                source_liquidity: self.accounts.vault_reserve_token.to_account_info(),
+                destination_collateral_account: self
+                    .accounts
+                    .vault_solend_lp_token
+                    .to_account_info(),
+                reserve: self.accounts.solend_reserve.to_account_info(),
+                reserve_collateral_mint: self.accounts.solend_lp_mint.clone(),
+                reserve_liquidity_supply: self.accounts.solend_reserve_token.clone(),
+                lending_market: self.accounts.solend_market.clone(),
+                lending_market_authority: self.accounts.solend_market_authority.clone(),
+                transfer_authority: self.accounts.vault_authority.clone(),
+                clock: self.accounts.clock.to_account_info(),
+                token_program_id: self.accounts.token_program.to_account_info(),
             },
         );
         match amount {
             0 => Ok(()),
             _ => deposit_reserve_liquidity(
-                context.with_signer(&[&self.vault.authority_seeds()]),
+                context.with_signer(&[&self.accounts.vault.authority_seeds()]),
                 amount,
             ),
         }?;
 
-        let solend_value = self.vault.actual_allocations[Provider::Solend]
+        self.collateralize()?;
+
+        let solend_value = self.accounts.vault.actual_allocations[Provider::Solend]
             .value
             .checked_add(amount)
             .ok_or(ErrorCode::MathError)?;
-        self.vault.actual_allocations[Provider::Solend].update(solend_value, self.clock.slot);
+        self.accounts.vault.actual_allocations[Provider::Solend]
+            .update(solend_value, self.accounts.clock.slot);
         Ok(())
     }
     fn redeem(&mut self, amount: u64) -> Result<()> {
+        // Lp tokens that are not held by the vault are withdrawn from the obligation first
+        let obligation_amount = amount.saturating_sub(self.accounts.vault_solend_lp_token.amount);
+        if obligation_amount > 0 {
+            self.decollateralize(obligation_amount)?;
+        }
+
         let context = CpiContext::new(
-            self.solend_program.clone(),
+            self.accounts.solend_program.clone(),
             RedeemReserveCollateral {
-                lending_program: self.solend_program.clone(),
-                source_collateral: self.vault_solend_lp_token.to_account_info(),
-                destination_liquidity: self.vault_reserve_token.to_account_info(),
-                reserve: self.solend_reserve.to_account_info(),
-                reserve_collateral_mint: self.solend_lp_mint.clone(),
-                reserve_liquidity_supply: self.solend_reserve_token.clone(),
-                lending_market: self.solend_market.clone(),
-                lending_market_authority: self.solend_market_authority.clone(),
-                transfer_authority: self.vault_authority.clone(),
-                clock: self.clock.to_account_info(),
-                token_program_id: self.token_program.to_account_info(),
+                lending_program: self.accounts.solend_program.clone(),
+                source_collateral: self.accounts.vault_solend_lp_token.to_account_info(),
+                destination_liquidity: self.accounts.vault_reserve_token.to_account_info(),
+                reserve: self.accounts.solend_reserve.to_account_info(),
+                reserve_collateral_mint: self.accounts.solend_lp_mint.clone(),
+                reserve_liquidity_supply: self.accounts.solend_reserve_token.clone(),
+                lending_market: self.accounts.solend_market.clone(),
+                lending_market_authority: self.accounts.solend_market_authority.clone(),
+                transfer_authority: self.accounts.vault_authority.clone(),
+                clock: self.accounts.clock.to_account_info(),
+                token_program_id: self.accounts.token_program.to_account_info(),
             },
         );
         match amount {
             0 => Ok(()),
             _ => redeem_reserve_collateral(
-                context.with_signer(&[&self.vault.authority_seeds()]),
+                context.with_signer(&[&self.accounts.vault.authority_seeds()]),
                 amount,
             ),
         }?;
 
         let vault_reserve_vault_delta = self.convert_amount_lp_to_reserve(amount)?;
-        let solend_value = self.vault.actual_allocations[Provider::Solend]
+        let solend_value = self.accounts.vault.actual_allocations[Provider::Solend]
             .value
             .checked_sub(vault_reserve_vault_delta)
             .ok_or(ErrorCode::MathError)?;
-        self.vault.actual_allocations[Provider::Solend].update(solend_value, self.clock.slot);
+        self.accounts.vault.actual_allocations[Provider::Solend]
+            .update(solend_value, self.accounts.clock.slot);
         Ok(())
     }
     fn convert_amount_reserve_to_lp(&self, amount: u64) -> Result<u64> {
-        let exchange_rate = self.solend_reserve.collateral_exchange_rate()?;
+        let exchange_rate = self.accounts.solend_reserve.collateral_exchange_rate()?;
         Ok(exchange_rate.liquidity_to_collateral(amount)?)
     }
     fn convert_amount_lp_to_reserve(&self, amount: u64) -> Result<u64> {
-        let exchange_rate = self.solend_reserve.collateral_exchange_rate()?;
+        let exchange_rate = self.accounts.solend_reserve.collateral_exchange_rate()?;
         Ok(exchange_rate.collateral_to_liquidity(amount)?)
     }
 
     fn reserve_tokens_in_vault(&self) -> u64 {
-        self.vault_reserve_token.amount
+        self.accounts.vault_reserve_token.amount
     }
 
-    fn lp_tokens_in_vault(&self) -> u64 {
-        self.vault_solend_lp_token.amount
+    fn lp_tokens_in_vault(&self) -> Result<u64> {
+        let obligation_amount = obligation_collateral(
+            self.obligation
+                .as_ref()
+                .map(|obligation| &obligation.vault_solend_obligation),
+            &self.accounts.solend_reserve.key(),
+        )?;
+        Ok(self
+            .accounts
+            .vault_solend_lp_token
+            .amount
+            .checked_add(obligation_amount)
+            .ok_or(ErrorCode::MathError)?)
     }
 
     fn provider(&self) -> Provider {
@@ -269,6 +440,128 @@ pub fn refresh_reserve<'info>(
     Ok(())
 }
 
+pub fn init_obligation<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, InitObligation<'info>>,
+) -> Result<()> {
+    let ix = spl_token_lending::instruction::init_obligation(
+        *ctx.accounts.lending_program.key,
+        *ctx.accounts.obligation.key,
+        *ctx.accounts.lending_market.key,
+        *ctx.accounts.obligation_owner.key,
+    );
+
+    solana_program::program::invoke_signed(
+        &ix,
+        &ToAccountInfos::to_account_infos(&ctx),
+        ctx.signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+pub fn deposit_obligation_collateral<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, DepositObligationCollateral<'info>>,
+    collateral_amount: u64,
+) -> Result<()> {
+    let ix = spl_token_lending::instruction::deposit_obligation_collateral(
+        *ctx.accounts.lending_program.key,
+        collateral_amount,
+        *ctx.accounts.source_collateral.key,
+        *ctx.accounts.destination_collateral.key,
+        *ctx.accounts.deposit_reserve.key,
+        *ctx.accounts.obligation.key,
+        *ctx.accounts.lending_market.key,
+        *ctx.accounts.obligation_owner.key,
+        *ctx.accounts.transfer_authority.key,
+    );
+
+    solana_program::program::invoke_signed(
+        &ix,
+        &ToAccountInfos::to_account_infos(&ctx),
+        ctx.signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+pub fn withdraw_obligation_collateral<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, WithdrawObligationCollateral<'info>>,
+    collateral_amount: u64,
+) -> Result<()> {
+    let ix = spl_token_lending::instruction::withdraw_obligation_collateral(
+        *ctx.accounts.lending_program.key,
+        collateral_amount,
+        *ctx.accounts.source_collateral.key,
+        *ctx.accounts.destination_collateral.key,
+        *ctx.accounts.withdraw_reserve.key,
+        *ctx.accounts.obligation.key,
+        *ctx.accounts.lending_market.key,
+        *ctx.accounts.obligation_owner.key,
+    );
+
+    solana_program::program::invoke_signed(
+        &ix,
+        &ToAccountInfos::to_account_infos(&ctx),
+        ctx.signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+pub fn refresh_obligation<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, RefreshObligation<'info>>,
+) -> Result<()> {
+    let ix = spl_token_lending::instruction::refresh_obligation(
+        *ctx.accounts.lending_program.key,
+        *ctx.accounts.obligation.key,
+        ctx.remaining_accounts.iter().map(|a| a.key()).collect(),
+    );
+
+    solana_program::program::invoke_signed(
+        &ix,
+        &ToAccountInfos::to_account_infos(&ctx),
+        ctx.signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Splits the vault obligation off the front of the accounts if the vault deposits through it
+pub fn split_vault_obligation<'a, 'info>(
+    vault: &Account<Vault>,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<(Option<&'a AccountInfo<'info>>, &'a [AccountInfo<'info>])> {
+    if !vault.solend_obligation_enabled {
+        return Ok((None, accounts));
+    }
+
+    let (vault_solend_obligation, accounts) = accounts
+        .split_first()
+        .ok_or(ErrorCode::InsufficientAccounts)?;
+    let (key, _) = Pubkey::find_program_address(
+        &[vault.key().as_ref(), b"solend_obligation".as_ref()],
+        &crate::ID,
+    );
+    (vault_solend_obligation.key() == key).ok_or(ErrorCode::InvalidAccount)?;
+
+    Ok((Some(vault_solend_obligation), accounts))
+}
+
+/// Solend lp tokens deposited into the vault's obligation,
+/// zero if the vault does not deposit through an obligation
+pub fn obligation_collateral(obligation: Option<&AccountInfo>, reserve: &Pubkey) -> Result<u64> {
+    let obligation = match obligation {
+        Some(obligation) => Account::<SolendObligation>::try_from(obligation)?,
+        None => return Ok(0),
+    };
+
+    Ok(obligation
+        .deposits
+        .iter()
+        .find(|collateral| collateral.deposit_reserve == *reserve)
+        .map_or(0, |collateral| collateral.deposited_amount))
+}
+
 #[derive(Accounts)]
 pub struct DepositReserveLiquidity<'info> {
     // Lending program
@@ -364,6 +657,113 @@ pub struct RefreshReserve<'info> {
     pub clock: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+pub struct InitObligation<'info> {
+    // Lending program
+    /// CHECK: safe
+    pub lending_program: AccountInfo<'info>,
+    // Obligation account, uninitialized
+    /// CHECK: safe
+    pub obligation: AccountInfo<'info>,
+    // Lending market account
+    /// CHECK: safe
+    pub lending_market: AccountInfo<'info>,
+    // Obligation owner
+    /// CHECK: safe
+    pub obligation_owner: AccountInfo<'info>,
+    // Clock
+    /// CHECK: safe
+    pub clock: AccountInfo<'info>,
+    // Rent
+    /// CHECK: safe
+    pub rent: AccountInfo<'info>,
+    // Token program ID
+    /// CHECK: safe
+    pub token_program_id: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositObligationCollateral<'info> {
+    // Lending program
+    /// CHECK: safe
+    pub lending_program: AccountInfo<'info>,
+    // Source token account for reserve collateral token
+    /// CHECK: safe
+    pub source_collateral: AccountInfo<'info>,
+    // Reserve collateral supply SPL token account
+    /// CHECK: safe
+    pub destination_collateral: AccountInfo<'info>,
+    // Refreshed reserve account
+    /// CHECK: safe
+    pub deposit_reserve: AccountInfo<'info>,
+    // Obligation account
+    /// CHECK: safe
+    pub obligation: AccountInfo<'info>,
+    // Lending market account
+    /// CHECK: safe
+    pub lending_market: AccountInfo<'info>,
+    // Obligation owner
+    /// CHECK: safe
+    pub obligation_owner: AccountInfo<'info>,
+    // User transfer authority
+    /// CHECK: safe
+    pub transfer_authority: AccountInfo<'info>,
+    // Clock
+    /// CHECK: safe
+    pub clock: AccountInfo<'info>,
+    // Token program ID
+    /// CHECK: safe
+    pub token_program_id: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawObligationCollateral<'info> {
+    // Lending program
+    /// CHECK: safe
+    pub lending_program: AccountInfo<'info>,
+    // Reserve collateral supply SPL token account
+    /// CHECK: safe
+    pub source_collateral: AccountInfo<'info>,
+    // Destination token account for reserve collateral token
+    /// CHECK: safe
+    pub destination_collateral: AccountInfo<'info>,
+    // Refreshed reserve account
+    /// CHECK: safe
+    pub withdraw_reserve: AccountInfo<'info>,
+    // Refreshed obligation account
+    /// CHECK: safe
+    pub obligation: AccountInfo<'info>,
+    // Lending market account
+    /// CHECK: safe
+    pub lending_market: AccountInfo<'info>,
+    // Lending market authority - PDA
+    /// CHECK: safe
+    pub lending_market_authority: AccountInfo<'info>,
+    // Obligation owner
+    /// CHECK: safe
+    pub obligation_owner: AccountInfo<'info>,
+    // Clock
+    /// CHECK: safe
+    pub clock: AccountInfo<'info>,
+    // Token program ID
+    /// CHECK: safe
+    pub token_program_id: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshObligation<'info> {
+    // Lending program
+    /// CHECK: safe
+    pub lending_program: AccountInfo<'info>,
+    // Obligation account
+    /// CHECK: safe
+    pub obligation: AccountInfo<'info>,
+    // Clock
+    /// CHECK: safe
+    pub clock: AccountInfo<'info>,
+    // Remaining accounts are the reserves of the obligation deposits and borrows
+}
+
 #[derive(Clone)]
 pub struct SolendReserve(Reserve);
 
@@ -398,10 +798,48 @@ impl Deref for SolendReserve {
     }
 }
 
+#[derive(Clone)]
+pub struct SolendObligation(Obligation);
+
+impl anchor_lang::AccountDeserialize for SolendObligation {
+    fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+        SolendObligation::try_deserialize_unchecked(buf)
+    }
+
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+        Ok(
+            <Obligation as solana_program::program_pack::Pack>::unpack(buf)
+                .map(SolendObligation)?,
+        )
+    }
+}
+
+impl anchor_lang::AccountSerialize for SolendObligation {
+    fn try_serialize<W: Write>(&self, _writer: &mut W) -> Result<()> {
+        // no-op
+        Ok(())
+    }
+}
+
+impl anchor_lang::Owner for SolendObligation {
+    fn owner() -> Pubkey {
+        spl_token_lending::id()
+    }
+}
+
+impl Deref for SolendObligation {
+    type Target = Obligation;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 #[derive(Accounts)]
 pub struct InitializeSolend<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
         has_one = vault_authority,
     )]
@@ -455,6 +893,7 @@ pub struct RefreshSolend<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_solend_lp_token,
         has_one = solend_reserve,
     )]
@@ -502,23 +941,34 @@ impl<'info> RefreshSolend<'info> {
 }
 
 impl<'info> Refresher<'info> for RefreshSolend<'info> {
+    /// Remaining accounts are the vault obligation if the vault deposits through it
     fn update_actual_allocation(
         &mut self,
-        _remaining_accounts: &[AccountInfo<'info>],
+        remaining_accounts: &[AccountInfo<'info>],
     ) -> Result<()> {
         #[cfg(feature = "debug")]
         msg!("Refreshing solend");
 
+        let (vault_solend_obligation, _) = split_vault_obligation(&self.vault, remaining_accounts)?;
+
         refresh_reserve(self.solend_refresh_reserve_context())?;
 
+        let lp_token_amount = self
+            .vault_solend_lp_token
+            .amount
+            .checked_add(obligation_collateral(
+                vault_solend_obligation,
+                &self.solend_reserve.key(),
+            )?)
+            .ok_or(ErrorCode::MathError)?;
+
         let solend_exchange_rate = self.solend_reserve.collateral_exchange_rate()?;
-        let solend_value =
-            solend_exchange_rate.collateral_to_liquidity(self.vault_solend_lp_token.amount)?;
+        let solend_value = solend_exchange_rate.collateral_to_liquidity(lp_token_amount)?;
 
         #[cfg(feature = "debug")]
         msg!("Value: {}", solend_value);
 
-        self.vault.actual_allocations[Provider::Solend].update(solend_value, Clock::get()?.slot);
+        update_refreshed_allocation(&mut self.vault, Provider::Solend, solend_value)?;
 
         Ok(())
     }
@@ -0,0 +1,394 @@
+use std::convert::TryFrom;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+};
+use anchor_spl::token;
+use boolinator::Boolinator;
+use spl_math::uint::U256;
+
+use crate::{
+    errors::ErrorCode,
+    sell_port_reward::{load_dex_markets, DexAdapter, SwapTokenAccounts},
+    state::{Dex, DexMarket, DexMarkets, DexStates},
+};
+
+/// Lowest sqrt price that a whirlpool swap can move the price to
+const MIN_SQRT_PRICE_X64: u128 = 4295048016;
+
+/// Highest sqrt price that a whirlpool swap can move the price to
+const MAX_SQRT_PRICE_X64: u128 = 79226673515401279992447579055;
+
+/// Denominator of the whirlpool fee rate, which is in hundredths of a basis point
+const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+
+/// Fields of a whirlpool account used to quote and route swaps
+struct WhirlpoolState {
+    fee_rate: u16,
+    sqrt_price: u128,
+    token_mint_a: Pubkey,
+    token_vault_a: Pubkey,
+    token_mint_b: Pubkey,
+    token_vault_b: Pubkey,
+}
+
+impl WhirlpoolState {
+    // Offsets in the whirlpool account data, including the discriminator
+    const FEE_RATE_OFFSET: usize = 45;
+    const SQRT_PRICE_OFFSET: usize = 65;
+    const TOKEN_MINT_A_OFFSET: usize = 101;
+    const TOKEN_VAULT_A_OFFSET: usize = 133;
+    const TOKEN_MINT_B_OFFSET: usize = 181;
+    const TOKEN_VAULT_B_OFFSET: usize = 213;
+    const MIN_LEN: usize = 245;
+
+    fn unpack(data: &[u8]) -> Result<Self> {
+        (data.len() >= Self::MIN_LEN).ok_or(ErrorCode::InvalidAccount)?;
+
+        let read_pubkey = |offset: usize| Pubkey::new(&data[offset..offset + 32]);
+        Ok(Self {
+            fee_rate: u16::from_le_bytes(
+                <[u8; 2]>::try_from(&data[Self::FEE_RATE_OFFSET..Self::FEE_RATE_OFFSET + 2])
+                    .map_err(|_| ErrorCode::InvalidAccount)?,
+            ),
+            sqrt_price: u128::from_le_bytes(
+                <[u8; 16]>::try_from(&data[Self::SQRT_PRICE_OFFSET..Self::SQRT_PRICE_OFFSET + 16])
+                    .map_err(|_| ErrorCode::InvalidAccount)?,
+            ),
+            token_mint_a: read_pubkey(Self::TOKEN_MINT_A_OFFSET),
+            token_vault_a: read_pubkey(Self::TOKEN_VAULT_A_OFFSET),
+            token_mint_b: read_pubkey(Self::TOKEN_MINT_B_OFFSET),
+            token_vault_b: read_pubkey(Self::TOKEN_VAULT_B_OFFSET),
+        })
+    }
+
+    /// Output at the current price of the whirlpool, less the swap fee.
+    /// Price impact is not taken into account, so this overestimates the actual output
+    /// and only rejects swaps that cannot reach the minimum output even at the current price.
+    /// Slippage is bounded by the minimum output passed to the whirlpool swap.
+    fn quote(&self, a_to_b: bool, amount_in: u64) -> Result<u64> {
+        (self.sqrt_price > 0).ok_or(ErrorCode::InvalidAccount)?;
+
+        let amount_after_fee = amount_in as u128
+            * (FEE_RATE_DENOMINATOR - self.fee_rate as u64) as u128
+            / FEE_RATE_DENOMINATOR as u128;
+
+        // The price of token A in token B is (sqrt_price / 2^64)^2
+        let sqrt_price = U256::from(self.sqrt_price);
+        let amount_out = if a_to_b {
+            ((U256::from(amount_after_fee) * sqrt_price) >> 64) * sqrt_price >> 64
+        } else {
+            (((U256::from(amount_after_fee) << 64) / sqrt_price) << 64) / sqrt_price
+        };
+
+        (amount_out <= U256::from(u64::MAX)).ok_or(ErrorCode::MathError)?;
+        Ok(amount_out.as_u64())
+    }
+}
+
+/// Data of a whirlpool `swap` instruction that sells exactly `amount_in`
+fn swap_instruction_data(
+    amount_in: u64,
+    minimum_amount_out: u64,
+    sqrt_price_limit: u128,
+    a_to_b: bool,
+) -> Vec<u8> {
+    let mut data = hash(b"global:swap").to_bytes()[..8].to_vec();
+    data.extend_from_slice(&amount_in.to_le_bytes());
+    data.extend_from_slice(&minimum_amount_out.to_le_bytes());
+    data.extend_from_slice(&sqrt_price_limit.to_le_bytes());
+    // amount_specified_is_input
+    data.push(1);
+    data.push(a_to_b as u8);
+    data
+}
+
+/// Accounts of a swap through an Orca Whirlpools (concentrated liquidity) pool
+pub struct WhirlpoolSwap<'info> {
+    pub whirlpool_program: AccountInfo<'info>,
+    pub whirlpool: AccountInfo<'info>,
+    pub token_vault_a: AccountInfo<'info>,
+    pub token_vault_b: AccountInfo<'info>,
+    pub tick_array_0: AccountInfo<'info>,
+    pub tick_array_1: AccountInfo<'info>,
+    pub tick_array_2: AccountInfo<'info>,
+    pub oracle: AccountInfo<'info>,
+}
+
+impl<'info> WhirlpoolSwap<'info> {
+    /// Number of accounts of a hop in the remaining accounts of a route:
+    /// the whirlpool `DexMarkets` registry of the vault, the whirlpool program, the whirlpool,
+    /// its token vaults A and B, the three tick arrays crossed by the swap and the whirlpool oracle
+    pub const ROUTE_ACCOUNTS_LEN: usize = 9;
+
+    /// Loads a hop of a route, checking the whirlpool against the registry of the vault
+    pub fn from_route_accounts(
+        vault: &Pubkey,
+        dex_states: &DexStates,
+        market_id: u8,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<(Self, DexMarket)> {
+        (accounts.len() == Self::ROUTE_ACCOUNTS_LEN).ok_or(ErrorCode::InsufficientAccounts)?;
+
+        let registry = load_dex_markets(vault, dex_states, Dex::Whirlpool, &accounts[0])?;
+
+        let swap = Self {
+            whirlpool_program: accounts[1].clone(),
+            whirlpool: accounts[2].clone(),
+            token_vault_a: accounts[3].clone(),
+            token_vault_b: accounts[4].clone(),
+            tick_array_0: accounts[5].clone(),
+            tick_array_1: accounts[6].clone(),
+            tick_array_2: accounts[7].clone(),
+            oracle: accounts[8].clone(),
+        };
+        let market = swap.check_registered(&registry, market_id)?;
+        Ok((swap, market))
+    }
+
+    /// Checks that the swap goes through the given whirlpool of the registry, returns the market
+    pub fn check_registered(&self, registry: &DexMarkets, market_id: u8) -> Result<DexMarket> {
+        let market = *registry.get_market(market_id)?;
+        (self.whirlpool_program.key() == market.swap_program).ok_or(ErrorCode::InvalidAccount)?;
+        (self.whirlpool.key() == market.market).ok_or(ErrorCode::InvalidAccount)?;
+
+        // Tick arrays and the oracle are checked by the whirlpool program
+        Ok(market)
+    }
+
+    /// Mints of the tokens A and B of a whirlpool, checking that it is owned by the whirlpool program
+    pub fn market_mints(
+        whirlpool_program: &Pubkey,
+        whirlpool: &AccountInfo,
+    ) -> Result<(Pubkey, Pubkey)> {
+        (whirlpool.owner == whirlpool_program).ok_or(ErrorCode::InvalidAccount)?;
+        let state = WhirlpoolState::unpack(&whirlpool.try_borrow_data()?)?;
+        Ok((state.token_mint_a, state.token_mint_b))
+    }
+
+    fn state(&self) -> Result<WhirlpoolState> {
+        (self.whirlpool.owner == &self.whirlpool_program.key()).ok_or(ErrorCode::InvalidAccount)?;
+        let state = WhirlpoolState::unpack(&self.whirlpool.try_borrow_data()?)?;
+        (state.token_vault_a == self.token_vault_a.key()
+            && state.token_vault_b == self.token_vault_b.key())
+        .ok_or(ErrorCode::InvalidAccount)?;
+        Ok(state)
+    }
+
+    /// Whether the swap sells token A for token B, given the mint that is sold
+    fn a_to_b(state: &WhirlpoolState, source_mint: &Pubkey) -> Result<bool> {
+        if *source_mint == state.token_mint_a {
+            Ok(true)
+        } else if *source_mint == state.token_mint_b {
+            Ok(false)
+        } else {
+            Err(ErrorCode::InvalidAccount.into())
+        }
+    }
+}
+
+impl<'info> DexAdapter<'info> for WhirlpoolSwap<'info> {
+    fn quote(&self, source_mint: &Pubkey, amount_in: u64) -> Result<u64> {
+        let state = self.state()?;
+        let a_to_b = Self::a_to_b(&state, source_mint)?;
+        state.quote(a_to_b, amount_in)
+    }
+
+    fn swap(
+        &self,
+        accounts: &SwapTokenAccounts<'info>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let state = self.state()?;
+        let a_to_b = Self::a_to_b(&state, &token::accessor::mint(&accounts.source)?)?;
+
+        let (token_owner_account_a, token_owner_account_b, sqrt_price_limit) = if a_to_b {
+            (&accounts.source, &accounts.destination, MIN_SQRT_PRICE_X64)
+        } else {
+            (&accounts.destination, &accounts.source, MAX_SQRT_PRICE_X64)
+        };
+
+        let ix = Instruction {
+            program_id: self.whirlpool_program.key(),
+            accounts: vec![
+                AccountMeta::new_readonly(accounts.token_program.key(), false),
+                AccountMeta::new_readonly(accounts.authority.key(), true),
+                AccountMeta::new(self.whirlpool.key(), false),
+                AccountMeta::new(token_owner_account_a.key(), false),
+                AccountMeta::new(self.token_vault_a.key(), false),
+                AccountMeta::new(token_owner_account_b.key(), false),
+                AccountMeta::new(self.token_vault_b.key(), false),
+                AccountMeta::new(self.tick_array_0.key(), false),
+                AccountMeta::new(self.tick_array_1.key(), false),
+                AccountMeta::new(self.tick_array_2.key(), false),
+                AccountMeta::new_readonly(self.oracle.key(), false),
+            ],
+            data: swap_instruction_data(amount_in, minimum_amount_out, sqrt_price_limit, a_to_b),
+        };
+
+        let account_infos: Vec<AccountInfo> = vec![
+            self.whirlpool_program.clone(),
+            accounts.token_program.clone(),
+            accounts.authority.clone(),
+            self.whirlpool.clone(),
+            token_owner_account_a.clone(),
+            self.token_vault_a.clone(),
+            token_owner_account_b.clone(),
+            self.token_vault_b.clone(),
+            self.tick_array_0.clone(),
+            self.tick_array_1.clone(),
+            self.tick_array_2.clone(),
+            self.oracle.clone(),
+        ];
+
+        invoke_signed(&ix, &account_infos, signer_seeds).map_err(Into::into)
+    }
+
+    fn market(&self) -> Pubkey {
+        self.whirlpool.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Layout of the whirlpool account of the Orca Whirlpools program, up to the reward infos
+    #[derive(AnchorSerialize)]
+    struct WhirlpoolFixture {
+        whirlpools_config: Pubkey,
+        whirlpool_bump: [u8; 1],
+        tick_spacing: u16,
+        tick_spacing_seed: [u8; 2],
+        fee_rate: u16,
+        protocol_fee_rate: u16,
+        liquidity: u128,
+        sqrt_price: u128,
+        tick_current_index: i32,
+        protocol_fee_owed_a: u64,
+        protocol_fee_owed_b: u64,
+        token_mint_a: Pubkey,
+        token_vault_a: Pubkey,
+        fee_growth_global_a: u128,
+        token_mint_b: Pubkey,
+        token_vault_b: Pubkey,
+        fee_growth_global_b: u128,
+        reward_last_updated_timestamp: u64,
+        // Three reward infos of 128 bytes each
+        reward_infos: [[u64; 16]; 3],
+    }
+
+    /// Anchor discriminator of the whirlpool account, `sha256("account:Whirlpool")[..8]`
+    const WHIRLPOOL_DISCRIMINATOR: [u8; 8] = [63, 149, 209, 12, 225, 128, 99, 9];
+
+    /// Anchor discriminator of the whirlpool swap instruction, `sha256("global:swap")[..8]`
+    const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+
+    /// Size of a whirlpool account, including the discriminator
+    const WHIRLPOOL_LEN: usize = 653;
+
+    #[test]
+    fn test_unpack_whirlpool() {
+        // Values in the range of a SOL/USDC whirlpool with a tick spacing of 64
+        let fixture = WhirlpoolFixture {
+            whirlpools_config: Pubkey::new_unique(),
+            whirlpool_bump: [255],
+            tick_spacing: 64,
+            tick_spacing_seed: 64_u16.to_le_bytes(),
+            fee_rate: 3000,
+            protocol_fee_rate: 300,
+            liquidity: 50_000_000_000_000,
+            sqrt_price: 3_169_486_448_565_236_108,
+            tick_current_index: -33_670,
+            protocol_fee_owed_a: 12_345,
+            protocol_fee_owed_b: 67_890,
+            token_mint_a: Pubkey::new_unique(),
+            token_vault_a: Pubkey::new_unique(),
+            fee_growth_global_a: 1 << 70,
+            token_mint_b: Pubkey::new_unique(),
+            token_vault_b: Pubkey::new_unique(),
+            fee_growth_global_b: 1 << 80,
+            reward_last_updated_timestamp: 1_660_000_000,
+            reward_infos: [[u64::MAX; 16]; 3],
+        };
+        let mut data = WHIRLPOOL_DISCRIMINATOR.to_vec();
+        fixture.serialize(&mut data).unwrap();
+        assert_eq!(data.len(), WHIRLPOOL_LEN);
+
+        let state = WhirlpoolState::unpack(&data).unwrap();
+        assert_eq!(state.fee_rate, fixture.fee_rate);
+        assert_eq!(state.sqrt_price, fixture.sqrt_price);
+        assert_eq!(state.token_mint_a, fixture.token_mint_a);
+        assert_eq!(state.token_vault_a, fixture.token_vault_a);
+        assert_eq!(state.token_mint_b, fixture.token_mint_b);
+        assert_eq!(state.token_vault_b, fixture.token_vault_b);
+
+        assert!(WhirlpoolState::unpack(&data[..WhirlpoolState::MIN_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_swap_instruction_data() {
+        /// Arguments of the whirlpool swap instruction
+        #[derive(AnchorSerialize)]
+        struct SwapArgs {
+            amount: u64,
+            other_amount_threshold: u64,
+            sqrt_price_limit: u128,
+            amount_specified_is_input: bool,
+            a_to_b: bool,
+        }
+
+        let mut expected = SWAP_DISCRIMINATOR.to_vec();
+        SwapArgs {
+            amount: 1_000_000,
+            other_amount_threshold: 990_000,
+            sqrt_price_limit: MAX_SQRT_PRICE_X64,
+            amount_specified_is_input: true,
+            a_to_b: false,
+        }
+        .serialize(&mut expected)
+        .unwrap();
+
+        assert_eq!(
+            swap_instruction_data(1_000_000, 990_000, MAX_SQRT_PRICE_X64, false),
+            expected
+        );
+        assert_eq!(
+            swap_instruction_data(1, 2, MIN_SQRT_PRICE_X64, true)[40..],
+            [1, 1]
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let state = |fee_rate, sqrt_price| WhirlpoolState {
+            fee_rate,
+            sqrt_price,
+            token_mint_a: Pubkey::default(),
+            token_vault_a: Pubkey::default(),
+            token_mint_b: Pubkey::default(),
+            token_vault_b: Pubkey::default(),
+        };
+
+        // Price of 1, fee of 0.3%
+        assert_eq!(
+            state(3000, 1 << 64).quote(true, 1_000_000).unwrap(),
+            997_000
+        );
+        assert_eq!(
+            state(3000, 1 << 64).quote(false, 1_000_000).unwrap(),
+            997_000
+        );
+
+        // Price of 4 token B per token A
+        assert_eq!(state(0, 2 << 64).quote(true, 1_000).unwrap(), 4_000);
+        assert_eq!(state(0, 2 << 64).quote(false, 1_000).unwrap(), 250);
+
+        assert!(state(0, 0).quote(true, 1_000).is_err());
+    }
+}
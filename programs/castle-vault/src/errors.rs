@@ -58,4 +58,55 @@ pub enum ErrorCode {
 
     #[msg("Failed to get price feed")]
     PriceFeedError,
+
+    #[msg("Yield source is withdraw-only and cannot receive deposits")]
+    WithdrawOnlyYieldSource,
+
+    #[msg("Vault version is not supported, the vault needs to be migrated")]
+    UnsupportedVaultVersion,
+
+    #[msg("Vault registry is full")]
+    VaultRegistryFull,
+
+    #[msg("Idle buffer band must contain the target and cannot be over 100%")]
+    InvalidIdleBufferConfig,
+
+    #[msg("Depositor is not allowed to deposit into this vault")]
+    DepositorNotAllowed,
+
+    #[msg("User deposit cap reached")]
+    UserDepositCapError,
+
+    #[msg("Depositor account needs to be initialized to deposit into this vault")]
+    DepositorNotInitialized,
+
+    #[msg("Oracle price is too old")]
+    StaleOraclePrice,
+
+    #[msg("Oracle price confidence interval is too wide")]
+    OracleConfidenceTooWide,
+
+    #[msg("Swap quote is below the minimum output derived from the oracle price")]
+    SwapQuoteBelowMinimum,
+
+    #[msg("DEX market is not registered")]
+    DexMarketNotRegistered,
+
+    #[msg("DEX market is already registered, it needs to be replaced instead")]
+    DexMarketAlreadyRegistered,
+
+    #[msg("Mints of the DEX market do not match the swap")]
+    InvalidDexMarketMints,
+
+    #[msg("Not enough time has passed since the last harvest")]
+    HarvestTooSoon,
+
+    #[msg("Oracle price is not currently trading")]
+    OracleNotTrading,
+
+    #[msg("Oracle price is zero or negative")]
+    NonPositiveOraclePrice,
+
+    #[msg("Reward rate is over the yearly supply of the reward token")]
+    RewardRateTooHigh,
 }
@@ -4,7 +4,10 @@ use anchor_spl::token::TokenAccount;
 use boolinator::Boolinator;
 use port_anchor_adaptor::{port_lending_id, port_staking_id, PortStakeAccount, PortStakingPool};
 
-use crate::state::{Vault, VaultPortAdditionalState};
+use crate::{
+    errors::ErrorCode,
+    state::{Vault, VaultFlags, VaultPortAdditionalState},
+};
 
 #[derive(Accounts)]
 pub struct ClaimPortReward<'info> {
@@ -12,6 +15,7 @@ pub struct ClaimPortReward<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
     )]
     pub vault: Box<Account<'info, Vault>>,
@@ -41,7 +45,11 @@ pub struct ClaimPortReward<'info> {
     )]
     pub vault_port_reward_token: Box<Account<'info, TokenAccount>>,
 
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_sub_reward".as_ref()],
+        bump = port_additional_states.vault_port_sub_reward_token_bump
+    )]
     pub vault_port_sub_reward_token: Box<Account<'info, TokenAccount>>,
 
     /// ID of the staking pool
@@ -83,30 +91,73 @@ pub struct ClaimPortReward<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+impl<'info> ClaimPortReward<'info> {
+    fn port_reward_claim(&self) -> PortRewardClaim<'info> {
+        PortRewardClaim {
+            vault_authority: self.vault_authority.clone(),
+            vault_port_stake_account: self.vault_port_stake_account.to_account_info(),
+            vault_port_reward_token: self.vault_port_reward_token.to_account_info(),
+            vault_port_sub_reward_token: self.vault_port_sub_reward_token.to_account_info(),
+            port_staking_pool: self.port_staking_pool.to_account_info(),
+            port_lend_program: self.port_lend_program.clone(),
+            port_stake_program: self.port_stake_program.clone(),
+            port_staking_reward_pool: self.port_staking_reward_pool.clone(),
+            port_staking_sub_reward_pool: self.port_staking_sub_reward_pool.clone(),
+            port_staking_authority: self.port_staking_authority.clone(),
+            clock: self.clock.to_account_info(),
+        }
+    }
+}
+
+/// Accounts used to claim the Port staking rewards of the vault
+pub struct PortRewardClaim<'info> {
+    pub vault_authority: AccountInfo<'info>,
+    pub vault_port_stake_account: AccountInfo<'info>,
+    pub vault_port_reward_token: AccountInfo<'info>,
+    pub vault_port_sub_reward_token: AccountInfo<'info>,
+    pub port_staking_pool: AccountInfo<'info>,
+    pub port_lend_program: AccountInfo<'info>,
+    pub port_stake_program: AccountInfo<'info>,
+    pub port_staking_reward_pool: AccountInfo<'info>,
+    pub port_staking_sub_reward_pool: AccountInfo<'info>,
+    pub port_staking_authority: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+}
+
+impl<'info> PortRewardClaim<'info> {
+    /// Claims the rewards of the vault stake account, and the sub-rewards if the pool has any
+    pub fn claim(&self, vault: &Vault, sub_reward_available: bool) -> Result<()> {
+        // Check that reward claims are not halted
+        (!vault
+            .get_halt_flags()
+            .contains(VaultFlags::HALT_REWARD_CLAIMS))
+        .ok_or(ErrorCode::HaltedVault)?;
+
+        let claim_reward_context = CpiContext::new(
+            self.port_stake_program.clone(),
+            port_anchor_adaptor::ClaimReward {
+                stake_account_owner: self.vault_authority.clone(),
+                stake_account: self.vault_port_stake_account.clone(),
+                staking_pool: self.port_staking_pool.clone(),
+                reward_token_pool: self.port_staking_reward_pool.clone(),
+                reward_dest: self.vault_port_reward_token.clone(),
+                staking_program_authority: self.port_staking_authority.clone(),
+                clock: self.clock.clone(),
+                token_program: self.port_lend_program.clone(),
+            },
+        );
+
+        port_anchor_adaptor::claim_reward(
+            claim_reward_context.with_signer(&[&vault.authority_seeds()]),
+            sub_reward_available.as_some(self.port_staking_sub_reward_pool.clone()),
+            sub_reward_available.as_some(self.vault_port_sub_reward_token.clone()),
+        )
+    }
+}
+
 pub fn handler(ctx: Context<ClaimPortReward>) -> Result<()> {
-    let claim_reward_context = CpiContext::new(
-        ctx.accounts.port_stake_program.clone(),
-        port_anchor_adaptor::ClaimReward {
-            stake_account_owner: ctx.accounts.vault_authority.clone(),
-            stake_account: ctx.accounts.vault_port_stake_account.to_account_info(),
-            staking_pool: ctx.accounts.port_staking_pool.to_account_info(),
-            reward_token_pool: ctx.accounts.port_staking_reward_pool.clone(),
-            reward_dest: ctx.accounts.vault_port_reward_token.to_account_info(),
-            staking_program_authority: ctx.accounts.port_staking_authority.clone(),
-            clock: ctx.accounts.clock.to_account_info(),
-            token_program: ctx.accounts.port_lend_program.clone(),
-        },
-    );
-
-    port_anchor_adaptor::claim_reward(
-        claim_reward_context.with_signer(&[&ctx.accounts.vault.authority_seeds()]),
-        ctx.accounts
-            .port_additional_states
-            .sub_reward_available
-            .as_some(ctx.accounts.port_staking_sub_reward_pool.clone()),
-        ctx.accounts
-            .port_additional_states
-            .sub_reward_available
-            .as_some(ctx.accounts.vault_port_sub_reward_token.to_account_info()),
+    ctx.accounts.port_reward_claim().claim(
+        &ctx.accounts.vault,
+        ctx.accounts.port_additional_states.sub_reward_available,
     )
 }
@@ -0,0 +1,131 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+};
+use anchor_spl::token::{Token, TokenAccount};
+use boolinator::Boolinator;
+
+use crate::{
+    adapters::solend_reward_distributor,
+    errors::ErrorCode,
+    state::{Vault, VaultFlags, VaultSolendAdditionalState},
+};
+
+#[derive(Accounts)]
+pub struct ClaimSolendReward<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: safe
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"solend_additional_state".as_ref()],
+        bump = vault.vault_solend_additional_state_bump,
+        has_one = solend_reward_distributor,
+        has_one = solend_reward_distributor_program,
+    )]
+    pub solend_additional_states: Box<Account<'info, VaultSolendAdditionalState>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"solend_reward".as_ref()],
+        bump = solend_additional_states.vault_solend_reward_token_bump
+    )]
+    pub vault_solend_reward_token: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: address is checked
+    #[account(mut)]
+    pub solend_reward_distributor: AccountInfo<'info>,
+
+    /// CHECK: address is checked
+    #[account(
+        executable,
+        address = solend_reward_distributor::ID,
+    )]
+    pub solend_reward_distributor_program: AccountInfo<'info>,
+
+    // NOTE safe to ignore claim status and distributor token account
+    // because they are checked by the distributor program
+    /// CHECK: safe
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub solend_reward_claim_status: AccountInfo<'info>,
+
+    /// CHECK: safe
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub solend_reward_distributor_token: AccountInfo<'info>,
+
+    /// Pays for the claim status account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Claims the Solend liquidity mining rewards of the vault obligation from the merkle distributor
+///
+/// The claim index, amount and proof are published by Solend for each distribution
+pub fn handler(
+    ctx: Context<ClaimSolendReward>,
+    claim_status_bump: u8,
+    index: u64,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> Result<()> {
+    // Check that reward claims are not halted
+    (!ctx
+        .accounts
+        .vault
+        .get_halt_flags()
+        .contains(VaultFlags::HALT_REWARD_CLAIMS))
+    .ok_or(ErrorCode::HaltedVault)?;
+
+    let mut data = hash(b"global:claim").to_bytes()[..8].to_vec();
+    data.push(claim_status_bump);
+    data.extend_from_slice(&index.to_le_bytes());
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&(proof.len() as u32).to_le_bytes());
+    proof.iter().for_each(|node| data.extend_from_slice(node));
+
+    let accounts = &ctx.accounts;
+    let ix = Instruction {
+        program_id: accounts.solend_reward_distributor_program.key(),
+        accounts: vec![
+            AccountMeta::new(accounts.solend_reward_distributor.key(), false),
+            AccountMeta::new(accounts.solend_reward_claim_status.key(), false),
+            AccountMeta::new(accounts.solend_reward_distributor_token.key(), false),
+            AccountMeta::new(accounts.vault_solend_reward_token.key(), false),
+            AccountMeta::new_readonly(accounts.vault_authority.key(), true),
+            AccountMeta::new(accounts.payer.key(), true),
+            AccountMeta::new_readonly(accounts.system_program.key(), false),
+            AccountMeta::new_readonly(accounts.token_program.key(), false),
+        ],
+        data,
+    };
+
+    let account_infos: Vec<AccountInfo> = vec![
+        accounts.solend_reward_distributor_program.clone(),
+        accounts.solend_reward_distributor.clone(),
+        accounts.solend_reward_claim_status.clone(),
+        accounts.solend_reward_distributor_token.clone(),
+        accounts.vault_solend_reward_token.to_account_info(),
+        accounts.vault_authority.clone(),
+        accounts.payer.to_account_info(),
+        accounts.system_program.to_account_info(),
+        accounts.token_program.to_account_info(),
+    ];
+
+    invoke_signed(&ix, &account_infos, &[&accounts.vault.authority_seeds()]).map_err(Into::into)
+}
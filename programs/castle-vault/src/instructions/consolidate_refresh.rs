@@ -4,23 +4,31 @@
 use boolinator::Boolinator;
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount};
-use port_anchor_adaptor::{port_lending_id, PortReserve};
+use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount, Transfer};
+use port_anchor_adaptor::{port_lending_id, PortReserve, PortStakeAccount};
 
 use crate::{
     adapters::{solend, SolendReserve},
     errors::ErrorCode,
+    math::{calc_oracle_amount_out, ONE_AS_BPS},
+    oracle::load_oracle_price,
     reserves::Provider,
-    state::{SlotTrackedValue, Vault, VaultFlags},
+    state::{SlotTrackedValue, Vault, VaultConfig, VaultFlags, VaultPortAdditionalState},
 };
 use strum::IntoEnumIterator;
 
+/// Number of accounts used to value the Port rewards of the vault:
+/// the `VaultPortAdditionalState`, the vault stake account, the reward token account,
+/// its mint and oracle, the sub-reward token account, its mint and oracle, and the reserve token mint
+pub const REWARD_VALUATION_ACCOUNTS_LEN: usize = 9;
+
 #[derive(Accounts)]
 pub struct ConsolidateRefresh<'info> {
     /// Vault state account
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
         has_one = vault_reserve_token,
         has_one = lp_token_mint,
@@ -41,59 +49,362 @@ pub struct ConsolidateRefresh<'info> {
     pub token_program: Program<'info, Token>,
 }
 
-impl<'info> ConsolidateRefresh<'info> {
-    /// CpiContext for collecting fees by minting new vault lp tokens
-    #[cfg(feature = "fees")]
-    fn mint_to_context(
-        &self,
-        fee_receiver: &AccountInfo<'info>,
-    ) -> CpiContext<'_, '_, '_, 'info, MintTo<'info>> {
-        CpiContext::new(
-            self.token_program.to_account_info(),
-            MintTo {
-                mint: self.lp_token_mint.to_account_info(),
-                to: fee_receiver.clone(),
-                authority: self.vault_authority.clone(),
-            },
-        )
-    }
+#[event]
+pub struct InsuranceCoverEvent {
+    vault: Pubkey,
+    /// Reserve tokens moved from the insurance reserve to the vault
+    amount: u64,
+    /// Realized losses that are still not covered
+    uncovered_losses: u64,
 }
 
 /// updates the vault total value, and collects fees
+///
+/// Remaining accounts are the fee receiver, the referral fee receiver,
+/// the insurance reserve if the vault has one,
+/// and the reward valuation accounts if the vault values its rewards
 pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, ConsolidateRefresh<'info>>) -> Result<()> {
     #[cfg(feature = "debug")]
     msg!("Consolidate vault refreshing");
 
+    let (fee_receivers, remaining_accounts) = ctx
+        .remaining_accounts
+        .split_at(ctx.remaining_accounts.len().min(2));
+    let (insurance_reserve, remaining_accounts) =
+        split_insurance_reserve(&ctx.accounts.vault, remaining_accounts)?;
+    let (reward_accounts, _) = split_reward_accounts(&ctx.accounts.vault, remaining_accounts)?;
+
+    consolidate_vault_value(
+        &mut ctx.accounts.vault,
+        &ctx.accounts.vault_authority,
+        &ctx.accounts.vault_reserve_token,
+        &ctx.accounts.lp_token_mint,
+        &ctx.accounts.token_program.to_account_info(),
+        fee_receivers,
+        insurance_reserve,
+        reward_accounts,
+    )
+}
+
+/// Splits the insurance reserve off the front of the accounts if the vault has one
+pub fn split_insurance_reserve<'a, 'info>(
+    vault: &Vault,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<(Option<&'a AccountInfo<'info>>, &'a [AccountInfo<'info>])> {
+    if !vault.has_insurance_reserve() {
+        return Ok((None, accounts));
+    }
+
+    let (insurance_reserve, accounts) = accounts
+        .split_first()
+        .ok_or(ErrorCode::InsufficientAccounts)?;
+    (insurance_reserve.key() == vault.insurance_reserve).ok_or(ErrorCode::InvalidAccount)?;
+
+    Ok((Some(insurance_reserve), accounts))
+}
+
+/// Splits the reward valuation accounts off the front of the accounts if the vault values its rewards
+pub fn split_reward_accounts<'a, 'info>(
+    vault: &Vault,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<(Option<&'a [AccountInfo<'info>]>, &'a [AccountInfo<'info>])> {
+    if !vault.config.value_rewards {
+        return Ok((None, accounts));
+    }
+
+    (accounts.len() >= REWARD_VALUATION_ACCOUNTS_LEN).ok_or(ErrorCode::InsufficientAccounts)?;
+    let (reward_accounts, accounts) = accounts.split_at(REWARD_VALUATION_ACCOUNTS_LEN);
+
+    Ok((Some(reward_accounts), accounts))
+}
+
+/// Value of the Port rewards of the vault in reserve tokens: rewards accrued to the stake account
+/// but not claimed yet, plus claimed rewards and sub-rewards that have not been sold yet.
+/// Sub-rewards that have not been claimed yet are not counted.
+///
+/// See `REWARD_VALUATION_ACCOUNTS_LEN` for the accounts
+pub fn calc_reward_value(vault: &Account<Vault>, accounts: &[AccountInfo]) -> Result<u64> {
+    (accounts.len() == REWARD_VALUATION_ACCOUNTS_LEN).ok_or(ErrorCode::InsufficientAccounts)?;
+
+    let vault_key = vault.key();
+    let check_pda = |account: &AccountInfo, seed: &[u8], bump: u8| -> Result<()> {
+        let key = Pubkey::create_program_address(&[vault_key.as_ref(), seed, &[bump]], &crate::ID)
+            .map_err(|_| ErrorCode::InvalidAccount)?;
+        (account.key() == key).ok_or_else(|| ErrorCode::InvalidAccount.into())
+    };
+
+    check_pda(
+        &accounts[0],
+        b"port_additional_state",
+        vault.vault_port_additional_state_bump,
+    )?;
+    let port_additional_states = Account::<VaultPortAdditionalState>::try_from(&accounts[0])?;
+
+    check_pda(
+        &accounts[1],
+        b"port_stake",
+        port_additional_states.vault_port_stake_account_bump,
+    )?;
+    let stake_account = Account::<PortStakeAccount>::try_from(&accounts[1])?;
+    let unclaimed_reward = stake_account.unclaimed_reward_wads.try_floor_u64()?;
+
+    let reserve_token_mint = Account::<Mint>::try_from(&accounts[8])?;
+    (reserve_token_mint.key() == vault.reserve_token_mint).ok_or(ErrorCode::InvalidAccount)?;
+
+    check_pda(
+        &accounts[2],
+        b"port_reward",
+        port_additional_states.vault_port_reward_token_bump,
+    )?;
+    let reward_amount = token::accessor::amount(&accounts[2])?
+        .checked_add(unclaimed_reward)
+        .ok_or(ErrorCode::OverflowError)?;
+    let reward_value = calc_reward_token_value(
+        &vault.config,
+        reward_amount,
+        &accounts[2],
+        &accounts[3],
+        &accounts[4],
+        &port_additional_states.port_reward_token_oracle,
+        reserve_token_mint.decimals,
+    )?;
+
+    let sub_reward_value = if port_additional_states.sub_reward_available {
+        check_pda(
+            &accounts[5],
+            b"port_sub_reward",
+            port_additional_states.vault_port_sub_reward_token_bump,
+        )?;
+        calc_reward_token_value(
+            &vault.config,
+            token::accessor::amount(&accounts[5])?,
+            &accounts[5],
+            &accounts[6],
+            &accounts[7],
+            &port_additional_states.port_sub_reward_token_oracle,
+            reserve_token_mint.decimals,
+        )?
+    } else {
+        0
+    };
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Reward value: {}, sub-reward value: {}",
+        reward_value,
+        sub_reward_value
+    );
+
+    reward_value
+        .checked_add(sub_reward_value)
+        .ok_or_else(|| ErrorCode::OverflowError.into())
+}
+
+/// Conservative value of `amount` reward tokens of the mint of `reward_token`:
+/// the oracle price less its confidence interval, less the configured haircut
+fn calc_reward_token_value(
+    config: &VaultConfig,
+    amount: u64,
+    reward_token: &AccountInfo,
+    reward_token_mint: &AccountInfo,
+    reward_token_oracle: &AccountInfo,
+    expected_oracle: &Pubkey,
+    reserve_decimals: u8,
+) -> Result<u64> {
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    let reward_token_mint = Account::<Mint>::try_from(reward_token_mint)?;
+    (reward_token_mint.key() == token::accessor::mint(reward_token)?
+        && reward_token_oracle.key() == *expected_oracle)
+        .ok_or(ErrorCode::InvalidAccount)?;
+
+    let oracle_price = load_oracle_price(config, reward_token_oracle)?;
+    let value = calc_oracle_amount_out(
+        amount,
+        oracle_price.price.saturating_sub(oracle_price.conf),
+        oracle_price.expo,
+        reward_token_mint.decimals,
+        reserve_decimals,
+    )
+    .ok_or(ErrorCode::MathError)?;
+
+    Ok(
+        (value as u128 * (ONE_AS_BPS - config.reward_value_haircut_bps as u64) as u128
+            / ONE_AS_BPS as u128) as u64,
+    )
+}
+
+/// CpiContext for collecting fees by minting new vault lp tokens
+#[cfg(feature = "fees")]
+fn mint_to_context<'a, 'b, 'c, 'info>(
+    token_program: &AccountInfo<'info>,
+    lp_token_mint: &Account<'info, Mint>,
+    vault_authority: &AccountInfo<'info>,
+    fee_receiver: &AccountInfo<'info>,
+) -> CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
+    CpiContext::new(
+        token_program.clone(),
+        MintTo {
+            mint: lp_token_mint.to_account_info(),
+            to: fee_receiver.clone(),
+            authority: vault_authority.clone(),
+        },
+    )
+}
+
+/// Splits fees denominated in lp tokens between the primary and referral fee receivers,
+/// and mints them
+///
+/// Fee receivers are the primary fee receiver followed by the referral fee receiver
+#[cfg(feature = "fees")]
+pub fn mint_fees<'info>(
+    vault: &mut Account<'info, Vault>,
+    vault_authority: &AccountInfo<'info>,
+    lp_token_mint: &Account<'info, Mint>,
+    token_program: &AccountInfo<'info>,
+    fee_receivers: &[AccountInfo<'info>],
+    total_fees_converted: u64,
+) -> Result<()> {
+    let primary_fees_converted = total_fees_converted
+        .checked_mul(100 - vault.config.referral_fee_pct as u64)
+        .and_then(|val| val.checked_div(100))
+        .ok_or(ErrorCode::MathError)?;
+
+    let referral_fees_converted = total_fees_converted
+        .checked_mul(vault.config.referral_fee_pct as u64)
+        .and_then(|val| val.checked_div(100))
+        .ok_or(ErrorCode::MathError)?;
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Collecting primary fees: {} lp tokens",
+        primary_fees_converted
+    );
+
+    if fee_receivers.len() < 2 {
+        msg!("Not enough accounts passed in to collect fees");
+        return Err(ErrorCode::InsufficientAccounts.into());
+    }
+
+    let primary_fee_receiver = &fee_receivers[0];
+    if primary_fee_receiver.key() != vault.fee_receiver {
+        msg!("Fee receivers do not match");
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+
+    token::mint_to(
+        mint_to_context(
+            token_program,
+            lp_token_mint,
+            vault_authority,
+            primary_fee_receiver,
+        )
+        .with_signer(&[&vault.authority_seeds()]),
+        primary_fees_converted,
+    )?;
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Collecting referral fees: {} lp tokens",
+        referral_fees_converted
+    );
+
+    let referral_fee_receiver = &fee_receivers[1];
+    if referral_fee_receiver.key() != vault.referral_fee_receiver {
+        msg!("Referral fee receivers do not match");
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+
+    token::mint_to(
+        mint_to_context(
+            token_program,
+            lp_token_mint,
+            vault_authority,
+            referral_fee_receiver,
+        )
+        .with_signer(&[&vault.authority_seeds()]),
+        referral_fees_converted,
+    )?;
+
+    // increment token supply
+    vault.lp_token_supply = vault
+        .lp_token_supply
+        .checked_add(primary_fees_converted)
+        .and_then(|val| val.checked_add(referral_fees_converted))
+        .ok_or(ErrorCode::MathError)?;
+
+    Ok(())
+}
+
+/// CpiContext for moving reserve tokens between the vault and the insurance reserve
+fn transfer_context<'a, 'b, 'c, 'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
+    CpiContext::new(
+        token_program.clone(),
+        Transfer {
+            from: from.clone(),
+            to: to.clone(),
+            authority: vault_authority.clone(),
+        },
+    )
+}
+
+/// Updates the vault total value from the reserve tokens it holds, the refreshed allocations
+/// and the value of its rewards if provided, covers realized losses from the insurance reserve,
+/// and collects fees by minting lp tokens to the fee receivers
+///
+/// Shared by all instructions that consolidate the vault value
+#[allow(unused_variables, unused_assignments)]
+pub fn consolidate_vault_value<'info>(
+    vault: &mut Account<'info, Vault>,
+    vault_authority: &AccountInfo<'info>,
+    vault_reserve_token: &Account<'info, TokenAccount>,
+    lp_token_mint: &Account<'info, Mint>,
+    token_program: &AccountInfo<'info>,
+    fee_receivers: &[AccountInfo<'info>],
+    insurance_reserve: Option<&AccountInfo<'info>>,
+    reward_accounts: Option<&[AccountInfo<'info>]>,
+) -> Result<()> {
     // Check that refreshes are not halted
-    (!ctx
-        .accounts
-        .vault
-        .get_halt_flags()
-        .contains(VaultFlags::HALT_REFRESHES))
-    .ok_or(ErrorCode::HaltedVault)?;
+    (!vault.get_halt_flags().contains(VaultFlags::HALT_REFRESHES)).ok_or(ErrorCode::HaltedVault)?;
 
     let clock_slot = Clock::get()?.slot;
 
     // Calculate new vault value
-    let vault_reserve_token_amount = ctx.accounts.vault_reserve_token.amount;
-    let vault_value = Provider::iter().try_fold(vault_reserve_token_amount, |acc: u64, p| {
-        let allocation: SlotTrackedValue = ctx.accounts.vault.actual_allocations[p];
-        if ctx.accounts.vault.get_yield_source_availability(p) {
-            // We skip pools where we have zero allocation
-            if allocation.value == 0 {
-                return Ok(acc);
+    let vault_reserve_token_amount = vault_reserve_token.amount;
+    let mut vault_value =
+        Provider::iter().try_fold(vault_reserve_token_amount, |acc: u64, p| {
+            let allocation: SlotTrackedValue = vault.actual_allocations[p];
+            if vault.get_yield_source_availability(p) {
+                // We skip pools where we have zero allocation
+                if allocation.value == 0 {
+                    return Ok(acc);
+                }
+
+                // Ensure that we refreshed all the lending pools where we have non-zero allocation in the same slot
+                (allocation.last_update.slots_elapsed(clock_slot)? == 0).as_result::<u64, Error>(
+                    acc.checked_add(allocation.value)
+                        .ok_or(ErrorCode::OverflowError)?,
+                    ErrorCode::AllocationIsNotUpdated.into(),
+                )
+            } else {
+                Ok(acc)
             }
-
-            // Ensure that we refreshed all the lending pools where we have non-zero allocation in the same slot
-            (allocation.last_update.slots_elapsed(clock_slot)? == 0).as_result::<u64, Error>(
-                acc.checked_add(allocation.value)
-                    .ok_or(ErrorCode::OverflowError)?,
-                ErrorCode::AllocationIsNotUpdated.into(),
-            )
-        } else {
-            Ok(acc)
-        }
-    })?;
+        })?;
+
+    // Rewards that have not been sold yet, so that depositors who exit before a harvest
+    // get their share of them
+    if let Some(reward_accounts) = reward_accounts {
+        vault_value = vault_value
+            .checked_add(calc_reward_value(vault, reward_accounts)?)
+            .ok_or(ErrorCode::OverflowError)?;
+    }
 
     #[cfg(feature = "debug")]
     {
@@ -101,21 +412,95 @@ pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, ConsolidateRefresh<'info>>
         msg!("Vault value: {}", vault_value);
     }
 
+    // Fees are not charged on the value restored by the insurance reserve
+    let fee_basis_value = vault_value;
+
+    // Vault reserve tokens available after transfers to and from the insurance reserve
+    let mut vault_reserve_token_amount = vault_reserve_token_amount;
+
+    let insurance_reserve = insurance_reserve
+        .map(Account::<TokenAccount>::try_from)
+        .transpose()?;
+
+    if let Some(insurance_reserve) = &insurance_reserve {
+        let covered_losses = vault.uncovered_losses.min(insurance_reserve.amount);
+        if covered_losses > 0 {
+            #[cfg(feature = "debug")]
+            msg!("Covering {} reserve tokens of losses", covered_losses);
+
+            token::transfer(
+                transfer_context(
+                    token_program,
+                    &insurance_reserve.to_account_info(),
+                    &vault_reserve_token.to_account_info(),
+                    vault_authority,
+                )
+                .with_signer(&[&vault.authority_seeds()]),
+                covered_losses,
+            )?;
+
+            vault.uncovered_losses -= covered_losses;
+            vault_value = vault_value
+                .checked_add(covered_losses)
+                .ok_or(ErrorCode::OverflowError)?;
+            vault_reserve_token_amount = vault_reserve_token_amount
+                .checked_add(covered_losses)
+                .ok_or(ErrorCode::OverflowError)?;
+
+            emit!(InsuranceCoverEvent {
+                vault: vault.key(),
+                amount: covered_losses,
+                uncovered_losses: vault.uncovered_losses,
+            });
+        }
+    }
+
     #[cfg(not(feature = "fees"))]
-    if ctx.accounts.vault.config.fee_carry_bps > 0 || ctx.accounts.vault.config.fee_mgmt_bps > 0 {
+    if vault.config.fee_carry_bps > 0 || vault.config.fee_mgmt_bps > 0 {
         msg!("WARNING: Fees are non-zero but the fee feature is deactivated");
     }
 
     #[cfg(feature = "fees")]
     {
-        let vault = &ctx.accounts.vault;
-
         // Calculate fees
-        let total_fees = vault.calculate_fees(vault_value, clock_slot)?;
+        let total_fees = vault.calculate_fees(fee_basis_value, clock_slot)?;
+
+        // Set aside a share of the fees in reserve tokens, limited by the idle reserve tokens
+        let insurance_fees = match &insurance_reserve {
+            Some(_) => total_fees
+                .checked_mul(vault.config.insurance_fee_pct as u64)
+                .and_then(|val| val.checked_div(100))
+                .ok_or(ErrorCode::MathError)?
+                .min(vault_reserve_token_amount),
+            None => 0,
+        };
+
+        if let Some(insurance_reserve) = insurance_reserve.as_ref().filter(|_| insurance_fees > 0) {
+            #[cfg(feature = "debug")]
+            msg!(
+                "Collecting insurance fees: {} reserve tokens",
+                insurance_fees
+            );
+
+            token::transfer(
+                transfer_context(
+                    token_program,
+                    &vault_reserve_token.to_account_info(),
+                    &insurance_reserve.to_account_info(),
+                    vault_authority,
+                )
+                .with_signer(&[&vault.authority_seeds()]),
+                insurance_fees,
+            )?;
+
+            vault_value = vault_value
+                .checked_sub(insurance_fees)
+                .ok_or(ErrorCode::MathError)?;
+        }
 
         let total_fees_converted = crate::math::calc_reserve_to_lp(
-            total_fees,
-            ctx.accounts.lp_token_mint.supply,
+            total_fees - insurance_fees,
+            lp_token_mint.supply,
             vault_value,
         )
         .ok_or(ErrorCode::MathError)?;
@@ -127,71 +512,18 @@ pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, ConsolidateRefresh<'info>>
             total_fees_converted
         );
 
-        let primary_fees_converted = total_fees_converted
-            .checked_mul(100 - ctx.accounts.vault.config.referral_fee_pct as u64)
-            .and_then(|val| val.checked_div(100))
-            .ok_or(ErrorCode::MathError)?;
-
-        let referral_fees_converted = total_fees_converted
-            .checked_mul(ctx.accounts.vault.config.referral_fee_pct as u64)
-            .and_then(|val| val.checked_div(100))
-            .ok_or(ErrorCode::MathError)?;
-
-        #[cfg(feature = "debug")]
-        msg!(
-            "Collecting primary fees: {} lp tokens",
-            primary_fees_converted
-        );
-
-        if ctx.remaining_accounts.len() < 2 {
-            msg!("Not enough accounts passed in to collect fees");
-            return Err(ErrorCode::InsufficientAccounts.into());
-        }
-
-        let primary_fee_receiver = &ctx.remaining_accounts[0];
-        if primary_fee_receiver.key() != ctx.accounts.vault.fee_receiver {
-            msg!("Fee receivers do not match");
-            return Err(ErrorCode::InvalidAccount.into());
-        }
-
-        token::mint_to(
-            ctx.accounts
-                .mint_to_context(primary_fee_receiver)
-                .with_signer(&[&vault.authority_seeds()]),
-            primary_fees_converted,
+        mint_fees(
+            vault,
+            vault_authority,
+            lp_token_mint,
+            token_program,
+            fee_receivers,
+            total_fees_converted,
         )?;
-
-        #[cfg(feature = "debug")]
-        msg!(
-            "Collecting referral fees: {} lp tokens",
-            referral_fees_converted
-        );
-
-        let referral_fee_receiver = &ctx.remaining_accounts[1];
-        if referral_fee_receiver.key() != ctx.accounts.vault.referral_fee_receiver {
-            msg!("Referral fee receivers do not match");
-            return Err(ErrorCode::InvalidAccount.into());
-        }
-
-        token::mint_to(
-            ctx.accounts
-                .mint_to_context(referral_fee_receiver)
-                .with_signer(&[&vault.authority_seeds()]),
-            referral_fees_converted,
-        )?;
-
-        // increment token supply
-        ctx.accounts.vault.lp_token_supply = ctx
-            .accounts
-            .vault
-            .lp_token_supply
-            .checked_add(primary_fees_converted)
-            .and_then(|val| val.checked_add(referral_fees_converted))
-            .ok_or(ErrorCode::MathError)?;
     }
 
     // Update vault total value
-    ctx.accounts.vault.value.update(vault_value, clock_slot);
+    vault.value.update(vault_value, clock_slot);
 
     Ok(())
 }
@@ -1,15 +1,19 @@
-use std::convert::Into;
+use std::{collections::BTreeMap, convert::Into};
 
 use boolinator::Boolinator;
 
-use anchor_lang::prelude::*;
+use anchor_lang::{prelude::*, Discriminator};
 use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount, Transfer};
 
 use crate::{
+    adapters::{PortAccounts, SolendAccounts, SolendMarket},
     errors::ErrorCode,
-    state::{Vault, VaultFlags},
+    reserves::Provider,
+    state::{Depositor, Vault, VaultFlags},
 };
 
+use super::{refresh_and_consolidate, LendingMarket};
+
 #[event]
 pub struct DepositEvent {
     vault: Pubkey,
@@ -20,11 +24,10 @@ pub struct DepositEvent {
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     /// Vault state account
-    /// Checks that the refresh has been called in the same slot
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
-        constraint = !vault.value.last_update.is_stale(clock.slot)? @ ErrorCode::VaultIsNotRefreshed,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = lp_token_mint,
         has_one = vault_authority,
         has_one = vault_reserve_token,
@@ -85,79 +88,277 @@ impl<'info> Deposit<'info> {
             },
         )
     }
-}
 
-/// Deposit to the vault
-///
-/// Transfers reserve tokens from user to vault and mints their share of lp tokens
-pub fn handler(ctx: Context<Deposit>, reserve_token_amount: u64) -> Result<()> {
-    #[cfg(feature = "debug")]
-    msg!("Depositing {} reserve tokens", reserve_token_amount);
+    /// Deposit to the vault
+    ///
+    /// Transfers reserve tokens from user to vault and mints their share of lp tokens
+    fn deposit(
+        &mut self,
+        depositor: Option<Account<'info, Depositor>>,
+        reserve_token_amount: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "debug")]
+        msg!("Depositing {} reserve tokens", reserve_token_amount);
 
-    // Check that deposits are not halted
-    (!ctx
-        .accounts
-        .vault
-        .get_halt_flags()
-        .contains(VaultFlags::HALT_DEPOSITS_WITHDRAWS))
-    .ok_or(ErrorCode::HaltedVault)?;
+        // Check that deposits are not halted
+        (!self
+            .vault
+            .get_halt_flags()
+            .contains(VaultFlags::HALT_DEPOSITS))
+        .ok_or(ErrorCode::HaltedVault)?;
 
-    let vault = &ctx.accounts.vault;
+        let vault = &self.vault;
 
-    // Use vault token supply
-    let lp_tokens_to_mint = crate::math::calc_reserve_to_lp(
-        reserve_token_amount,
-        ctx.accounts.vault.lp_token_supply,
-        vault.value.value,
-    )
-    .ok_or(ErrorCode::MathError)?;
+        // Use vault token supply
+        let lp_tokens_to_mint = crate::math::calc_reserve_to_lp(
+            reserve_token_amount,
+            self.vault.lp_token_supply,
+            vault.value.value,
+        )
+        .ok_or(ErrorCode::MathError)?;
 
-    let total_value = ctx
-        .accounts
-        .vault
-        .value
-        .value
-        .checked_add(reserve_token_amount)
-        .ok_or(ErrorCode::OverflowError)?;
+        let total_value = self
+            .vault
+            .value
+            .value
+            .checked_add(reserve_token_amount)
+            .ok_or(ErrorCode::OverflowError)?;
+
+        if total_value > self.vault.config.deposit_cap {
+            msg!("Deposit cap reached");
+            return Err(ErrorCode::DepositCapError.into());
+        }
+
+        match depositor {
+            Some(mut depositor) => {
+                depositor.record_deposit(
+                    &self.vault.config,
+                    reserve_token_amount,
+                    lp_tokens_to_mint,
+                    self.clock.unix_timestamp,
+                )?;
+                depositor.exit(&crate::ID)?;
+            }
+            None => {
+                (!self.vault.config.requires_depositor())
+                    .ok_or(ErrorCode::DepositorNotInitialized)?;
+            }
+        }
 
-    if total_value > ctx.accounts.vault.config.deposit_cap {
-        msg!("Deposit cap reached");
-        return Err(ErrorCode::DepositCapError.into());
+        token::transfer(self.transfer_context(), reserve_token_amount)?;
+
+        #[cfg(feature = "debug")]
+        msg!("Minting {} LP tokens", lp_tokens_to_mint);
+
+        token::mint_to(
+            self.mint_to_context()
+                .with_signer(&[&vault.authority_seeds()]),
+            lp_tokens_to_mint,
+        )?;
+
+        self.vault.lp_token_supply = self
+            .vault
+            .lp_token_supply
+            .checked_add(lp_tokens_to_mint)
+            .ok_or(ErrorCode::MathError)?;
+
+        // This is so that the SDK can read an up-to-date total value without calling refresh
+        self.vault.value.value = self
+            .vault
+            .value
+            .value
+            .checked_add(reserve_token_amount)
+            .ok_or(ErrorCode::MathError)?;
+
+        emit!(DepositEvent {
+            vault: self.vault.key(),
+            user: self.user_authority.key(),
+            amount: reserve_token_amount,
+        });
+
+        Ok(())
     }
 
-    token::transfer(ctx.accounts.transfer_context(), reserve_token_amount)?;
+    /// Deposits the reserve tokens into the yield source that is the most underweight
+    /// compared to its strategy weight from the last rebalance, up to the shortfall.
+    ///
+    /// The tokens are left idle if auto-deploy is disabled, the deposit is above the size threshold,
+    /// reconciles are halted, the last rebalance is too old, or no yield source is underweight.
+    fn auto_deploy(
+        &mut self,
+        program_id: &Pubkey,
+        remaining_accounts: &[AccountInfo<'info>],
+        reserve_token_amount: u64,
+    ) -> Result<()> {
+        let vault = &self.vault;
 
-    #[cfg(feature = "debug")]
-    msg!("Minting {} LP tokens", lp_tokens_to_mint);
+        if reserve_token_amount == 0
+            || reserve_token_amount > vault.config.auto_deploy_max
+            || vault.get_halt_flags().contains(VaultFlags::HALT_RECONCILES)
+        {
+            #[cfg(feature = "debug")]
+            msg!("Leaving deposit idle");
 
-    token::mint_to(
-        ctx.accounts
-            .mint_to_context()
-            .with_signer(&[&vault.authority_seeds()]),
-        lp_tokens_to_mint,
-    )?;
+            return Ok(());
+        }
 
-    ctx.accounts.vault.lp_token_supply = ctx
-        .accounts
-        .vault
-        .lp_token_supply
-        .checked_add(lp_tokens_to_mint)
-        .ok_or(ErrorCode::MathError)?;
+        let (provider, shortfall) = match vault.most_underweight(self.clock.slot)? {
+            Some(most_underweight) => most_underweight,
+            None => return Ok(()),
+        };
+        let idle_buffer_floor = vault.idle_buffer_floor();
+
+        // Deserializing the adapter accounts struct runs all of its account checks
+        let mut remaining_accounts = remaining_accounts;
+        let mut bumps = BTreeMap::new();
+        let allocation = match provider {
+            Provider::Solend => {
+                let mut accounts = SolendAccounts::try_accounts(
+                    program_id,
+                    &mut remaining_accounts,
+                    &[],
+                    &mut bumps,
+                )?;
+                (accounts.vault.key() == self.vault.key()).ok_or(ErrorCode::InvalidAccount)?;
+                deploy(
+                    &mut SolendMarket::load(&mut accounts, &mut remaining_accounts)?,
+                    reserve_token_amount,
+                    shortfall,
+                    idle_buffer_floor,
+                )?;
+                accounts.vault.actual_allocations[provider]
+            }
+            Provider::Port => {
+                let mut accounts = PortAccounts::try_accounts(
+                    program_id,
+                    &mut remaining_accounts,
+                    &[],
+                    &mut bumps,
+                )?;
+                (accounts.vault.key() == self.vault.key()).ok_or(ErrorCode::InvalidAccount)?;
+                deploy(
+                    &mut accounts,
+                    reserve_token_amount,
+                    shortfall,
+                    idle_buffer_floor,
+                )?;
+                accounts.vault.actual_allocations[provider]
+            }
+        };
+        self.vault.actual_allocations[provider] = allocation;
 
-    // This is so that the SDK can read an up-to-date total value without calling refresh
-    ctx.accounts.vault.value.value = ctx
+        Ok(())
+    }
+}
+
+/// Splits the depositor account of the wallet off the front of the remaining accounts,
+/// or returns `None` if the first remaining account is not a depositor account.
+///
+/// Depositor accounts are only created at the PDA of the vault and wallet they store,
+/// so checking the stored keys is enough to tell that it belongs to the wallet.
+pub fn split_depositor<'a, 'info>(
+    vault: &Account<'info, Vault>,
+    wallet: &Pubkey,
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(Option<Account<'info, Depositor>>, &'a [AccountInfo<'info>])> {
+    let (account, rest) = match remaining_accounts.split_first() {
+        Some((account, rest))
+            if account.owner == &crate::ID
+                && account
+                    .try_borrow_data()?
+                    .starts_with(&Depositor::discriminator()) =>
+        {
+            (account, rest)
+        }
+        _ => return Ok((None, remaining_accounts)),
+    };
+
+    let depositor = Account::<Depositor>::try_from(account)?;
+    (depositor.vault == vault.key() && depositor.wallet == *wallet)
+        .ok_or(ErrorCode::InvalidAccount)?;
+
+    Ok((Some(depositor), rest))
+}
+
+fn deploy<T: LendingMarket>(
+    accounts: &mut T,
+    reserve_token_amount: u64,
+    shortfall: u64,
+    idle_buffer_floor: u64,
+) -> Result<()> {
+    let amount = reserve_token_amount.min(shortfall).min(
+        accounts
+            .reserve_tokens_in_vault()
+            .saturating_sub(idle_buffer_floor),
+    );
+
+    #[cfg(feature = "debug")]
+    msg!("Deploying {} to {:?}", amount, accounts.provider());
+
+    accounts.deposit(amount)
+}
+
+/// Deposit to the vault once it has been refreshed in the same slot
+///
+/// Remaining accounts are optional. The depositor account of the user goes first,
+/// and is required by vaults that track deposits, see `VaultConfig::requires_depositor`.
+/// It is followed by the accounts of the reconcile instruction of the yield source
+/// that the deposit is deployed to and its remaining accounts, see `auto_deploy`
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+    reserve_token_amount: u64,
+) -> Result<()> {
+    // Check that the vault value has been refreshed in the same slot
+    (!ctx
         .accounts
         .vault
         .value
-        .value
-        .checked_add(reserve_token_amount)
-        .ok_or(ErrorCode::MathError)?;
+        .last_update
+        .is_stale(ctx.accounts.clock.slot)?)
+    .ok_or(ErrorCode::VaultIsNotRefreshed)?;
+
+    let (depositor, remaining_accounts) = split_depositor(
+        &ctx.accounts.vault,
+        ctx.accounts.user_authority.key,
+        ctx.remaining_accounts,
+    )?;
 
-    emit!(DepositEvent {
-        vault: ctx.accounts.vault.key(),
-        user: ctx.accounts.user_authority.key(),
-        amount: reserve_token_amount,
-    });
+    ctx.accounts.deposit(depositor, reserve_token_amount)?;
+
+    if !remaining_accounts.is_empty() {
+        ctx.accounts
+            .auto_deploy(ctx.program_id, remaining_accounts, reserve_token_amount)?;
+    }
 
     Ok(())
 }
+
+/// Refreshes the vault value, then deposits in the same instruction
+///
+/// Remaining accounts are the optional depositor account of the user, as in `handler`,
+/// followed by the accounts expected by `refresh_and_consolidate`.
+/// The deposit is not auto-deployed, since the optional Port oracle leaves no way to tell
+/// where the refresh accounts end and the reconcile accounts would start
+pub fn refresh_and_deposit_handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+    reserve_token_amount: u64,
+) -> Result<()> {
+    let accounts = ctx.accounts;
+    let (depositor, remaining_accounts) = split_depositor(
+        &accounts.vault,
+        accounts.user_authority.key,
+        ctx.remaining_accounts,
+    )?;
+
+    refresh_and_consolidate(
+        &mut accounts.vault,
+        &accounts.vault_authority,
+        &accounts.vault_reserve_token,
+        &accounts.lp_token_mint,
+        &accounts.token_program.to_account_info(),
+        ctx.program_id,
+        remaining_accounts,
+    )?;
+
+    accounts.deposit(depositor, reserve_token_amount)
+}
@@ -0,0 +1,402 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
+use boolinator::Boolinator;
+use port_anchor_adaptor::{port_lending_id, port_staking_id, PortStakeAccount, PortStakingPool};
+
+use crate::{
+    adapters::OrcaLegacySwap,
+    errors::ErrorCode,
+    state::{DexMarkets, DexStates, Vault, VaultFlags, VaultPortAdditionalState},
+};
+
+#[cfg(feature = "fees")]
+use crate::math::{calc_carry_fees, calc_reserve_to_lp};
+
+#[cfg(feature = "fees")]
+use super::mint_fees;
+use super::{PortRewardClaim, RewardSwapAccounts, SwapHop};
+
+#[event]
+pub struct HarvestEvent {
+    vault: Pubkey,
+    /// Reward tokens sold
+    reward_amount: u64,
+    /// Sub-reward tokens sold
+    sub_reward_amount: u64,
+    /// Reserve tokens received for the rewards and sub-rewards, added to the vault value
+    harvested: u64,
+    /// Carry fee charged on the harvested yield, denominated in the reserve token
+    carry_fee: u64,
+}
+
+#[derive(Accounts)]
+pub struct Harvest<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
+        has_one = lp_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"port_additional_state".as_ref()],
+        bump = vault.vault_port_additional_state_bump,
+        has_one = port_staking_pool,
+    )]
+    pub port_additional_states: Box<Account<'info, VaultPortAdditionalState>>,
+
+    /// Price of the Port reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = port_additional_states.port_reward_token_oracle)]
+    pub port_reward_token_oracle: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_stake".as_ref()],
+        bump = port_additional_states.vault_port_stake_account_bump
+    )]
+    pub vault_port_stake_account: Box<Account<'info, PortStakeAccount>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_reward".as_ref()],
+        bump = port_additional_states.vault_port_reward_token_bump
+    )]
+    pub vault_port_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_sub_reward".as_ref()],
+        bump = port_additional_states.vault_port_sub_reward_token_bump
+    )]
+    pub vault_port_sub_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = vault_port_reward_token.mint)]
+    pub port_reward_token_mint: Box<Account<'info, Mint>>,
+
+    /// ID of the staking pool
+    #[account(mut)]
+    pub port_staking_pool: Box<Account<'info, PortStakingPool>>,
+
+    /// CHECK: safe
+    #[account(
+        executable,
+        address = port_lending_id(),
+    )]
+    pub port_lend_program: AccountInfo<'info>,
+
+    /// CHECK: safe
+    #[account(
+        executable,
+        address = port_staking_id(),
+    )]
+    pub port_stake_program: AccountInfo<'info>,
+
+    // NOTE safe to ignore port_staking_reward_pool and  port_staking_sub_reward_pool
+    // because they are checked by port_stake_program
+    /// CHECK: safe
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub port_staking_reward_pool: AccountInfo<'info>,
+
+    /// CHECK: safe
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub port_staking_sub_reward_pool: AccountInfo<'info>,
+
+    /// CHECK: safe
+    //#[soteria(ignore)]
+    pub port_staking_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_orca_legacy_markets".as_ref()],
+        bump = dex_states.orca_legacy_markets_bump,
+    )]
+    pub orca_legacy_markets: Box<Account<'info, DexMarkets>>,
+
+    /// CHECK: checked against the registered markets
+    pub orca_swap_state: AccountInfo<'info>,
+
+    /// CHECK: checked by the swap program
+    //#[soteria(ignore)]
+    pub orca_swap_authority: AccountInfo<'info>,
+
+    /// Pool account of the input token, checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_input_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// Pool account of the output token, checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_output_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_swap_token_mint: AccountInfo<'info>,
+
+    /// CHECK: checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_fee_account: AccountInfo<'info>,
+
+    /// CHECK: checked against the registered market
+    #[account(executable)]
+    pub orca_swap_program: AccountInfo<'info>,
+
+    /// Token account for the vault's reserve tokens
+    #[account(mut)]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    /// Mint for the vault lp token
+    #[account(mut)]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> Harvest<'info> {
+    fn port_reward_claim(&self) -> PortRewardClaim<'info> {
+        PortRewardClaim {
+            vault_authority: self.vault_authority.clone(),
+            vault_port_stake_account: self.vault_port_stake_account.to_account_info(),
+            vault_port_reward_token: self.vault_port_reward_token.to_account_info(),
+            vault_port_sub_reward_token: self.vault_port_sub_reward_token.to_account_info(),
+            port_staking_pool: self.port_staking_pool.to_account_info(),
+            port_lend_program: self.port_lend_program.clone(),
+            port_stake_program: self.port_stake_program.clone(),
+            port_staking_reward_pool: self.port_staking_reward_pool.clone(),
+            port_staking_sub_reward_pool: self.port_staking_sub_reward_pool.clone(),
+            port_staking_authority: self.port_staking_authority.clone(),
+            clock: self.clock.to_account_info(),
+        }
+    }
+
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_reward_token.to_account_info(),
+            reward_token_oracle: self.port_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+
+    fn orca_legacy_swap(&self) -> OrcaLegacySwap<'info> {
+        OrcaLegacySwap {
+            swap_program: self.orca_swap_program.clone(),
+            swap_state: self.orca_swap_state.clone(),
+            swap_authority: self.orca_swap_authority.clone(),
+            pool_source: self.orca_input_token_account.to_account_info(),
+            pool_destination: self.orca_output_token_account.to_account_info(),
+            pool_mint: self.orca_swap_token_mint.clone(),
+            fee_account: self.orca_fee_account.clone(),
+        }
+    }
+
+    /// Sells the Port rewards held by the vault, returns the rewards sold and the reserve tokens received
+    fn sell_port_reward(&self, market_id: u8) -> Result<(u64, u64)> {
+        let reward_amount =
+            token::accessor::amount(&self.vault_port_reward_token.to_account_info())?;
+        if reward_amount == 0 {
+            return Ok((0, 0));
+        }
+
+        let orca_legacy_swap = self.orca_legacy_swap();
+        let market = orca_legacy_swap.check_registered(&self.orca_legacy_markets, market_id)?;
+
+        let reward_swap_accounts = self.reward_swap_accounts();
+        let harvested = reward_swap_accounts.sell(
+            &self.vault,
+            &reward_swap_accounts.direct_leg(orca_legacy_swap, market),
+            &self.port_reward_token_mint,
+            &self.reserve_token_mint,
+        )?;
+
+        Ok((reward_amount, harvested))
+    }
+
+    /// Sells the Port sub-rewards held by the vault through the given route,
+    /// returns the sub-rewards sold and the reserve tokens received
+    ///
+    /// Remaining accounts are the sub-reward token oracle, the sub-reward token mint,
+    /// followed by the accounts of the route, see `RewardSwapAccounts::route_legs`
+    fn sell_port_sub_reward(
+        &self,
+        hops: &[SwapHop],
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        (remaining_accounts.len() >= 2).ok_or(ErrorCode::InsufficientAccounts)?;
+        let (sub_reward_accounts, route_accounts) = remaining_accounts.split_at(2);
+
+        let sub_reward_token_oracle = &sub_reward_accounts[0];
+        (sub_reward_token_oracle.key() == self.port_additional_states.port_sub_reward_token_oracle)
+            .ok_or(ErrorCode::InvalidAccount)?;
+
+        let sub_reward_token_mint = Account::<Mint>::try_from(&sub_reward_accounts[1])?;
+        (sub_reward_token_mint.key() == self.vault_port_sub_reward_token.mint)
+            .ok_or(ErrorCode::InvalidAccount)?;
+
+        let sub_reward_amount =
+            token::accessor::amount(&self.vault_port_sub_reward_token.to_account_info())?;
+        if sub_reward_amount == 0 {
+            return Ok((0, 0));
+        }
+
+        let reward_swap_accounts = RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_sub_reward_token.to_account_info(),
+            reward_token_oracle: sub_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        };
+        let legs = reward_swap_accounts.route_legs(
+            &self.vault.key(),
+            &self.dex_states,
+            hops,
+            route_accounts,
+        )?;
+        let harvested = reward_swap_accounts.sell(
+            &self.vault,
+            &legs,
+            &sub_reward_token_mint,
+            &self.reserve_token_mint,
+        )?;
+
+        Ok((sub_reward_amount, harvested))
+    }
+}
+
+/// Claims the Port staking rewards, sells them for the reserve token and adds the proceeds
+/// to the vault value, charging the carry fee on them. Can be called by anyone,
+/// at most once per `min_harvest_interval`.
+///
+/// If the vault values its rewards, they are already in the vault value,
+/// so the proceeds are left for the next consolidation to book instead.
+///
+/// If the staking pool has sub-rewards, they are sold through the registered markets
+/// of `sub_reward_hops`, which has to be empty otherwise.
+///
+/// Remaining accounts are the fee receiver and the referral fee receiver,
+/// followed by the accounts of `Harvest::sell_port_sub_reward` if the pool has sub-rewards
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Harvest<'info>>,
+    market_id: u8,
+    sub_reward_hops: Vec<SwapHop>,
+) -> Result<()> {
+    // Harvested yield is added to the vault value, which refreshes would otherwise do
+    (!ctx
+        .accounts
+        .vault
+        .get_halt_flags()
+        .contains(VaultFlags::HALT_REFRESHES))
+    .ok_or(ErrorCode::HaltedVault)?;
+
+    let now = ctx.accounts.clock.unix_timestamp;
+    (now >= ctx
+        .accounts
+        .vault
+        .last_harvest_timestamp
+        .saturating_add(ctx.accounts.vault.config.min_harvest_interval as i64))
+    .ok_or(ErrorCode::HarvestTooSoon)?;
+
+    ctx.accounts.port_reward_claim().claim(
+        &ctx.accounts.vault,
+        ctx.accounts.port_additional_states.sub_reward_available,
+    )?;
+
+    (ctx.remaining_accounts.len() >= 2).ok_or(ErrorCode::InsufficientAccounts)?;
+    let (fee_receivers, sub_reward_accounts) = ctx.remaining_accounts.split_at(2);
+
+    let (reward_amount, reward_harvested) = ctx.accounts.sell_port_reward(market_id)?;
+    let (sub_reward_amount, sub_reward_harvested) =
+        if ctx.accounts.port_additional_states.sub_reward_available {
+            ctx.accounts
+                .sell_port_sub_reward(&sub_reward_hops, sub_reward_accounts)?
+        } else {
+            (sub_reward_hops.is_empty() && sub_reward_accounts.is_empty())
+                .ok_or(ErrorCode::InvalidArgument)?;
+            (0, 0)
+        };
+    let harvested = reward_harvested
+        .checked_add(sub_reward_harvested)
+        .ok_or(ErrorCode::OverflowError)?;
+
+    let booked = if ctx.accounts.vault.config.value_rewards {
+        0
+    } else {
+        harvested
+    };
+
+    #[cfg(feature = "fees")]
+    let carry_fee = calc_carry_fees(booked, ctx.accounts.vault.config.fee_carry_bps as u64)?;
+    #[cfg(not(feature = "fees"))]
+    let carry_fee = 0;
+
+    let vault = &mut ctx.accounts.vault;
+    vault.last_harvest_timestamp = now;
+
+    // The harvested tokens are already idle in the vault, so the next consolidation
+    // won't count them as yield again
+    vault.value.value = vault
+        .value
+        .value
+        .checked_add(booked)
+        .ok_or(ErrorCode::OverflowError)?;
+
+    #[cfg(feature = "fees")]
+    if carry_fee > 0 {
+        let carry_fee_converted = calc_reserve_to_lp(
+            carry_fee,
+            ctx.accounts.lp_token_mint.supply,
+            vault.value.value,
+        )
+        .ok_or(ErrorCode::MathError)?;
+
+        #[cfg(feature = "debug")]
+        msg!(
+            "Harvest carry fee: {} reserve tokens, {} lp tokens",
+            carry_fee,
+            carry_fee_converted
+        );
+
+        mint_fees(
+            vault,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.lp_token_mint,
+            &ctx.accounts.token_program.to_account_info(),
+            fee_receivers,
+            carry_fee_converted,
+        )?;
+    }
+
+    emit!(HarvestEvent {
+        vault: vault.key(),
+        reward_amount,
+        sub_reward_amount,
+        harvested,
+        carry_fee,
+    });
+
+    Ok(())
+}
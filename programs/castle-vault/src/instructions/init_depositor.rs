@@ -0,0 +1,40 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ErrorCode, state::*};
+
+#[derive(Accounts)]
+pub struct InitializeDepositor<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Deposits of the wallet into the vault
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 160,
+        seeds = [vault.key().as_ref(), b"depositor".as_ref(), wallet.key().as_ref()],
+        bump,
+    )]
+    pub depositor: Box<Account<'info, Depositor>>,
+
+    /// Wallet that deposits into the vault
+    /// CHECK: safe
+    pub wallet: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Creates the account that tracks the deposits of a wallet,
+/// can be called by the wallet itself or by the vault owner
+pub fn handler(ctx: Context<InitializeDepositor>) -> Result<()> {
+    let depositor = &mut ctx.accounts.depositor;
+    depositor.vault = ctx.accounts.vault.key();
+    depositor.wallet = ctx.accounts.wallet.key();
+    depositor.bump = *ctx.bumps.get("depositor").ok_or(ErrorCode::BumpError)?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use anchor_lang::prelude::*;
+use std::convert::Into;
+
+use crate::{errors::ErrorCode, state::*};
+
+#[derive(Accounts)]
+#[instruction(dex: Dex)]
+pub struct InitializeDexMarkets<'info> {
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        init,
+        space = 2560 + 8,
+        payer = payer,
+        seeds = [vault.key().as_ref(), dex.markets_seed()],
+        bump
+    )]
+    pub dex_markets: Box<Account<'info, DexMarkets>>,
+
+    /// Account that pays for above account inits
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Create a PDA that stores the markets of a DEX that the vault swaps through (should only do it once per DEX)
+pub fn handler(ctx: Context<InitializeDexMarkets>, dex: Dex) -> Result<()> {
+    let bump = *ctx.bumps.get("dex_markets").ok_or(ErrorCode::BumpError)?;
+    ctx.accounts.dex_states.set_markets_bump(dex, bump);
+    Ok(())
+}
@@ -7,6 +7,7 @@ use crate::{errors::ErrorCode, state::*};
 pub struct InitializeDexOrcaLegacy<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
     )]
     pub vault: Box<Account<'info, Vault>>,
@@ -8,6 +8,7 @@ use crate::{errors::ErrorCode, state::*};
 pub struct InitializeDexStates<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner
     )]
     pub vault: Box<Account<'info, Vault>>,
@@ -0,0 +1,56 @@
+use anchor_lang::prelude::*;
+use std::convert::Into;
+
+use crate::{errors::ErrorCode, state::*};
+
+#[derive(Accounts)]
+pub struct InitializeDexWhirlpool<'info> {
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        init,
+        space = 672 + 8,
+        payer = payer,
+        seeds = [vault.key().as_ref(), b"dex_whirlpool".as_ref()],
+        bump
+    )]
+    pub whirlpool_accounts: Box<Account<'info, WhirlpoolAccounts>>,
+
+    /// CHECK: safe
+    //#[soteria(ignore)]
+    #[account(executable)]
+    pub whirlpool_program: AccountInfo<'info>,
+
+    /// Account that pays for above account inits
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Create a PDA that stores Orca Whirlpools information (should only do it once)
+pub fn handler(ctx: Context<InitializeDexWhirlpool>) -> Result<()> {
+    ctx.accounts.dex_states.whirlpool_accounts_bump = *ctx
+        .bumps
+        .get("whirlpool_accounts")
+        .ok_or(ErrorCode::BumpError)?;
+    // All whirlpools are owned by the same program
+    ctx.accounts.whirlpool_accounts.whirlpool_program = ctx.accounts.whirlpool_program.key();
+    Ok(())
+}
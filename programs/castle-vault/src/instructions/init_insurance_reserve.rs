@@ -0,0 +1,51 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+
+use std::convert::Into;
+
+use crate::{errors::ErrorCode, state::*};
+
+#[derive(Accounts)]
+pub struct InitializeInsuranceReserve<'info> {
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+        has_one = vault_authority,
+        has_one = reserve_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    /// Token account for reserve tokens that cover realized losses
+    #[account(
+        init,
+        payer = payer,
+        seeds = [vault.key().as_ref(), b"insurance_reserve".as_ref()],
+        bump,
+        token::authority = vault_authority,
+        token::mint = reserve_token_mint,
+    )]
+    pub insurance_reserve: Box<Account<'info, TokenAccount>>,
+
+    /// Mint of the token that the vault accepts and stores
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+pub fn handler(ctx: Context<InitializeInsuranceReserve>) -> Result<()> {
+    ctx.accounts.vault.insurance_reserve = ctx.accounts.insurance_reserve.key();
+    Ok(())
+}
@@ -15,6 +15,7 @@ use crate::{errors::ErrorCode, state::*};
 pub struct InitializePortRewardAccounts<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
         has_one = vault_authority,
     )]
@@ -0,0 +1,148 @@
+use anchor_lang::{prelude::*, solana_program::program_pack::Pack};
+use anchor_spl::token::{Mint, Token, TokenAccount};
+use spl_token_lending::state::Obligation;
+
+use crate::{
+    adapters::{init_obligation, solend_reward_distributor, InitObligation},
+    errors::ErrorCode,
+    state::*,
+};
+
+use super::set_solend_rewards;
+
+#[derive(Accounts)]
+pub struct InitializeSolendRewardAccounts<'info> {
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+        has_one = vault_authority,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 256,
+        seeds = [vault.key().as_ref(), b"solend_additional_state".as_ref()],
+        bump,
+    )]
+    pub solend_additional_states: Box<Account<'info, VaultSolendAdditionalState>>,
+
+    /// CHECK: safe
+    #[account(
+        init,
+        payer = payer,
+        seeds = [vault.key().as_ref(), b"solend_obligation".as_ref()],
+        bump,
+        space = Obligation::LEN,
+        owner = solend_program.key(),
+    )]
+    pub vault_solend_obligation: AccountInfo<'info>,
+
+    /// Token account for storing Solend liquidity mining reward
+    #[account(
+        init,
+        payer = payer,
+        seeds = [vault.key().as_ref(), b"solend_reward".as_ref()],
+        bump,
+        token::authority = vault_authority,
+        token::mint = solend_reward_token_mint,
+    )]
+    pub vault_solend_reward_token: Box<Account<'info, TokenAccount>>,
+
+    /// Mint of the Solend liquidity mining reward
+    pub solend_reward_token_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: safe
+    pub solend_reward_token_oracle: AccountInfo<'info>,
+
+    /// CHECK: owner is checked, data is checked by the distributor program when claiming
+    #[account(owner = solend_reward_distributor::ID)]
+    pub solend_reward_distributor: AccountInfo<'info>,
+
+    /// CHECK: address is checked
+    #[account(
+        executable,
+        address = solend_reward_distributor::ID,
+    )]
+    pub solend_reward_distributor_program: AccountInfo<'info>,
+
+    /// CHECK: safe
+    #[account(
+        executable,
+        address = spl_token_lending::ID,
+    )]
+    pub solend_program: AccountInfo<'info>,
+
+    /// CHECK: checked by the lending program
+    pub solend_market: AccountInfo<'info>,
+
+    /// Account that pays for above account inits
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    /// Acts as authority of the fee receiver account
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+/// Creates the obligation the solend lp tokens are deposited into, and the accounts
+/// used to claim and value the Solend liquidity mining rewards.
+///
+/// Following deposits into Solend go through the obligation.
+pub fn handler(
+    ctx: Context<InitializeSolendRewardAccounts>,
+    reward_rate_per_slot: u64,
+) -> Result<()> {
+    let init_obligation_ctx = CpiContext::new(
+        ctx.accounts.solend_program.clone(),
+        InitObligation {
+            lending_program: ctx.accounts.solend_program.clone(),
+            obligation: ctx.accounts.vault_solend_obligation.clone(),
+            lending_market: ctx.accounts.solend_market.clone(),
+            obligation_owner: ctx.accounts.vault_authority.clone(),
+            clock: ctx.accounts.clock.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            token_program_id: ctx.accounts.token_program.to_account_info(),
+        },
+    );
+    init_obligation(init_obligation_ctx.with_signer(&[&ctx.accounts.vault.authority_seeds()]))?;
+
+    let solend_additional_states = &mut ctx.accounts.solend_additional_states;
+    solend_additional_states.vault_solend_obligation_bump = *ctx
+        .bumps
+        .get("vault_solend_obligation")
+        .ok_or(ErrorCode::BumpError)?;
+    solend_additional_states.vault_solend_reward_token_bump = *ctx
+        .bumps
+        .get("vault_solend_reward_token")
+        .ok_or(ErrorCode::BumpError)?;
+
+    ctx.accounts.vault.vault_solend_additional_state_bump = *ctx
+        .bumps
+        .get("solend_additional_states")
+        .ok_or(ErrorCode::BumpError)?;
+    ctx.accounts.vault.solend_obligation_enabled = true;
+
+    set_solend_rewards(
+        ctx.accounts.vault.key(),
+        &mut ctx.accounts.solend_additional_states,
+        ctx.accounts.solend_reward_token_oracle.key(),
+        ctx.accounts.solend_reward_distributor.key(),
+        ctx.accounts.solend_reward_token_mint.supply,
+        reward_rate_per_slot,
+    )
+}
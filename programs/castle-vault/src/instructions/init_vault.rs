@@ -17,6 +17,26 @@ pub struct VaultConfigArg {
     pub allocation_cap_pct: u8,
     pub rebalance_mode: RebalanceMode,
     pub strategy_type: StrategyType,
+    pub loss_halt_bps: u16,
+    pub insurance_fee_pct: u8,
+    pub insurance_target: u64,
+    pub idle_buffer_pct: u8,
+    pub idle_buffer_min_pct: u8,
+    pub idle_buffer_max_pct: u8,
+    pub auto_deploy_max: u64,
+    pub max_reconcile_move: u64,
+    pub min_reconcile_move: u64,
+    pub min_reconcile_move_bps: u16,
+    pub permissioned: bool,
+    pub per_user_deposit_cap: u64,
+    pub lockup_seconds: u64,
+    pub early_withdraw_fee_bps: u16,
+    pub swap_slippage_bps: u16,
+    pub oracle_max_confidence_bps: u16,
+    pub oracle_max_age: u64,
+    pub min_harvest_interval: u64,
+    pub value_rewards: bool,
+    pub reward_value_haircut_bps: u16,
 }
 
 #[derive(Accounts)]
@@ -96,21 +116,56 @@ pub struct Initialize<'info> {
 }
 
 impl<'info> Initialize<'info> {
+    fn init_accounts(&self) -> VaultInitAccounts<'info> {
+        VaultInitAccounts {
+            vault_authority: self.vault_authority.clone(),
+            lp_token_mint: self.lp_token_mint.to_account_info(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            reserve_token_mint: self.reserve_token_mint.to_account_info(),
+            fee_receiver: self.fee_receiver.clone(),
+            referral_fee_receiver: self.referral_fee_receiver.clone(),
+            referral_fee_owner: self.referral_fee_owner.clone(),
+            payer: self.payer.to_account_info(),
+            owner: self.owner.clone(),
+            system_program: self.system_program.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+            rent: self.rent.to_account_info(),
+        }
+    }
+}
+
+/// Accounts used to set up a vault, regardless of how the vault state account was created
+pub struct VaultInitAccounts<'info> {
+    pub vault_authority: AccountInfo<'info>,
+    pub lp_token_mint: AccountInfo<'info>,
+    pub vault_reserve_token: AccountInfo<'info>,
+    pub reserve_token_mint: AccountInfo<'info>,
+    pub fee_receiver: AccountInfo<'info>,
+    pub referral_fee_receiver: AccountInfo<'info>,
+    pub referral_fee_owner: AccountInfo<'info>,
+    pub payer: AccountInfo<'info>,
+    pub owner: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+}
+
+impl<'info> VaultInitAccounts<'info> {
     fn init_fee_receiver_create_context(
         &self,
         fee_token_account: AccountInfo<'info>,
         token_authority: AccountInfo<'info>,
     ) -> CpiContext<'_, '_, '_, 'info, Create<'info>> {
         CpiContext::new(
-            self.token_program.to_account_info(),
+            self.token_program.clone(),
             Create {
-                payer: self.payer.to_account_info(),
+                payer: self.payer.clone(),
                 associated_token: fee_token_account,
                 authority: token_authority,
-                mint: self.lp_token_mint.to_account_info(),
-                system_program: self.system_program.to_account_info(),
-                token_program: self.token_program.to_account_info(),
-                rent: self.rent.to_account_info(),
+                mint: self.lp_token_mint.clone(),
+                system_program: self.system_program.clone(),
+                token_program: self.token_program.clone(),
+                rent: self.rent.clone(),
             },
         )
     }
@@ -127,48 +182,57 @@ impl<'info> Initialize<'info> {
 
         Ok(())
     }
+
+    /// Writes the initial vault state and creates the fee receiver accounts
+    pub fn initialize_vault(
+        &self,
+        vault: &mut Account<'info, Vault>,
+        authority_bump: u8,
+        config: VaultConfigArg,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+
+        // Validating referral token address
+        self.validate_referral_token()?;
+
+        vault.version = get_version_arr();
+        vault.owner = self.owner.key();
+        vault.vault_authority = self.vault_authority.key();
+        vault.authority_seed = vault.key();
+        vault.authority_bump = [authority_bump];
+        vault.vault_reserve_token = self.vault_reserve_token.key();
+        vault.lp_token_mint = self.lp_token_mint.key();
+        vault.reserve_token_mint = self.reserve_token_mint.key();
+        vault.fee_receiver = self.fee_receiver.key();
+        vault.referral_fee_receiver = self.referral_fee_receiver.key();
+        vault.value = SlotTrackedValue {
+            value: 0,
+            last_update: LastUpdate::new(clock.slot),
+        };
+        vault.config = VaultConfig::new(config)?;
+        vault.lp_token_supply = 0;
+
+        // Initialize fee receiver account
+        associated_token::create(
+            self.init_fee_receiver_create_context(self.fee_receiver.clone(), self.owner.clone()),
+        )?;
+
+        // Initialize referral fee receiver account
+        associated_token::create(self.init_fee_receiver_create_context(
+            self.referral_fee_receiver.clone(),
+            self.referral_fee_owner.clone(),
+        ))?;
+
+        Ok(())
+    }
 }
 
 pub fn handler(ctx: Context<Initialize>, authority_bump: u8, config: VaultConfigArg) -> Result<()> {
-    let clock = Clock::get()?;
-
-    // Validating referral token address
-    ctx.accounts.validate_referral_token()?;
-
-    let vault = &mut ctx.accounts.vault;
-    vault.version = get_version_arr();
-    vault.owner = ctx.accounts.owner.key();
-    vault.vault_authority = ctx.accounts.vault_authority.key();
-    vault.authority_seed = vault.key();
-    vault.authority_bump = [authority_bump];
-    vault.vault_reserve_token = ctx.accounts.vault_reserve_token.key();
-    vault.lp_token_mint = ctx.accounts.lp_token_mint.key();
-    vault.reserve_token_mint = ctx.accounts.reserve_token_mint.key();
-    vault.fee_receiver = ctx.accounts.fee_receiver.key();
-    vault.referral_fee_receiver = ctx.accounts.referral_fee_receiver.key();
-    vault.value = SlotTrackedValue {
-        value: 0,
-        last_update: LastUpdate::new(clock.slot),
-    };
-    vault.config = VaultConfig::new(config)?;
-    vault.lp_token_supply = 0;
-
-    // Initialize fee receiver account
-    associated_token::create(ctx.accounts.init_fee_receiver_create_context(
-        ctx.accounts.fee_receiver.to_account_info(),
-        ctx.accounts.owner.to_account_info(),
-    ))?;
-
-    // Initialize referral fee receiver account
-    associated_token::create(ctx.accounts.init_fee_receiver_create_context(
-        ctx.accounts.referral_fee_receiver.to_account_info(),
-        ctx.accounts.referral_fee_owner.to_account_info(),
-    ))?;
-
-    Ok(())
+    let init_accounts = ctx.accounts.init_accounts();
+    init_accounts.initialize_vault(&mut ctx.accounts.vault, authority_bump, config)
 }
 
-fn get_version_arr() -> [u8; 3] {
+pub fn get_version_arr() -> [u8; 3] {
     [
         env!("CARGO_PKG_VERSION_MAJOR")
             .parse::<u8>()
@@ -0,0 +1,142 @@
+use std::mem::size_of;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{Mint, Token, TokenAccount},
+};
+
+use crate::state::*;
+
+use super::{VaultConfigArg, VaultInitAccounts};
+
+#[derive(Accounts)]
+#[instruction(authority_bump: u8, nonce: u64)]
+pub struct InitializePda<'info> {
+    /// Vault state account
+    /// Derived from the reserve token mint, the owner and a nonce so that clients can find it
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + size_of::<Vault>(),
+        seeds = [
+            b"vault".as_ref(),
+            reserve_token_mint.key().as_ref(),
+            owner.key().as_ref(),
+            nonce.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [b"vault_registry".as_ref(), reserve_token_mint.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub vault_registry: Box<Account<'info, VaultRegistry>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"authority".as_ref()],
+        bump = authority_bump,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+
+    /// Mint for vault lp token
+    #[account(
+        init,
+        payer = payer,
+        seeds = [vault.key().as_ref(), b"lp_mint".as_ref()],
+        bump,
+        mint::authority = vault_authority,
+        mint::decimals = reserve_token_mint.decimals,
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    /// Token account for vault reserve tokens
+    #[account(
+        init,
+        payer = payer,
+        seeds = [vault.key().as_ref(), reserve_token_mint.key().as_ref()],
+        bump,
+        token::authority = vault_authority,
+        token::mint = reserve_token_mint,
+    )]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    /// Mint of the token that the vault accepts and stores
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    /// Token account that receives the primary ratio of fees from the vault
+    /// denominated in vault lp tokens
+    /// CHECK: safe
+    #[account(mut)]
+    pub fee_receiver: AccountInfo<'info>,
+
+    /// Token account that receives the secondary ratio of fees from the vault
+    /// denominated in vault lp tokens
+    /// CHECK: safe
+    #[account(mut)]
+    pub referral_fee_receiver: AccountInfo<'info>,
+
+    /// Owner of the referral fee reciever token account
+    /// CHECK: safe
+    pub referral_fee_owner: AccountInfo<'info>,
+
+    /// Account that pays for above account inits
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    /// Acts as authority of the fee receiver account
+    /// Must sign so that only the owner can add vaults to its registry
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+impl<'info> InitializePda<'info> {
+    fn init_accounts(&self) -> VaultInitAccounts<'info> {
+        VaultInitAccounts {
+            vault_authority: self.vault_authority.clone(),
+            lp_token_mint: self.lp_token_mint.to_account_info(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            reserve_token_mint: self.reserve_token_mint.to_account_info(),
+            fee_receiver: self.fee_receiver.clone(),
+            referral_fee_receiver: self.referral_fee_receiver.clone(),
+            referral_fee_owner: self.referral_fee_owner.clone(),
+            payer: self.payer.to_account_info(),
+            owner: self.owner.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+            rent: self.rent.to_account_info(),
+        }
+    }
+}
+
+/// Initialize a vault at a PDA address and add it to the owner's registry for the reserve token
+pub fn handler(
+    ctx: Context<InitializePda>,
+    authority_bump: u8,
+    nonce: u64,
+    config: VaultConfigArg,
+) -> Result<()> {
+    #[cfg(feature = "debug")]
+    msg!("Initializing vault with nonce {}", nonce);
+
+    let init_accounts = ctx.accounts.init_accounts();
+    init_accounts.initialize_vault(&mut ctx.accounts.vault, authority_bump, config)?;
+
+    let vault_key = ctx.accounts.vault.key();
+    ctx.accounts.vault_registry.add_vault(vault_key)
+}
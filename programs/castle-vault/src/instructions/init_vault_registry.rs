@@ -0,0 +1,35 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::Mint;
+
+use crate::state::*;
+
+#[derive(Accounts)]
+pub struct InitializeVaultRegistry<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 648,
+        seeds = [b"vault_registry".as_ref(), reserve_token_mint.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub vault_registry: Box<Account<'info, VaultRegistry>>,
+
+    /// Mint of the token that the registered vaults accept and store
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    /// Account that pays for above account inits
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Owner of the registered vaults
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Create a PDA that keeps track of the PDA vaults of an owner for a given reserve token (should only do it once)
+pub fn handler(ctx: Context<InitializeVaultRegistry>) -> Result<()> {
+    ctx.accounts.vault_registry.reserve_token_mint = ctx.accounts.reserve_token_mint.key();
+    ctx.accounts.vault_registry.owner = ctx.accounts.owner.key();
+    Ok(())
+}
@@ -0,0 +1,343 @@
+use std::{convert::TryFrom, mem::size_of};
+
+use boolinator::Boolinator;
+
+use anchor_lang::{prelude::*, system_program, Discriminator};
+use anchor_spl::token::Mint;
+
+use crate::{
+    errors::ErrorCode,
+    state::{
+        Allocations, SlotTrackedValue, Vault, VaultConfig, DEFAULT_ORACLE_MAX_AGE,
+        DEFAULT_ORACLE_MAX_CONFIDENCE_BPS, DEFAULT_SWAP_SLIPPAGE_BPS,
+    },
+};
+
+use super::get_version_arr;
+
+/// Offset of `VaultConfig` in the vault account data, which is the same in every layout
+const VAULT_CONFIG_OFFSET: usize = 8 // discriminator
+    + size_of::<[u8; 3]>() // version
+    + 14 * size_of::<Pubkey>() // owner to referral_fee_receiver
+    + size_of::<[u8; 1]>() // authority_bump
+    + 2 * size_of::<u16>() // halt_flags, yield_source_flags
+    + size_of::<SlotTrackedValue>() // value
+    + size_of::<Allocations>(); // target_allocations
+
+/// Size of `VaultConfig` before 3.7.0
+const VAULT_CONFIG_LEN_V3_6: usize = 32;
+
+/// Offset of the end of `VaultConfig` in the vault account data before 3.7.0
+const VAULT_CONFIG_END_V3_6: usize = VAULT_CONFIG_OFFSET + VAULT_CONFIG_LEN_V3_6;
+
+/// Number of bytes `VaultConfig` grew by in 3.7.0
+const VAULT_CONFIG_GROWTH_V3_7: usize = size_of::<VaultConfig>() - VAULT_CONFIG_LEN_V3_6;
+
+/// Size of the vault account data before 3.7.0, including the discriminator.
+/// The fields added after the config in 3.7.0 took the place of reserved space.
+const VAULT_LEN_V3_6: usize = 8 + size_of::<Vault>() - VAULT_CONFIG_GROWTH_V3_7;
+
+#[event]
+pub struct MigrateEvent {
+    vault: Pubkey,
+    from_version: [u8; 3],
+    to_version: [u8; 3],
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    /// Vault state account
+    /// Only deserialized after its data has been migrated to the current layout
+    /// CHECK: owner of the vault is checked in the handler
+    #[account(
+        mut,
+        owner = crate::ID,
+    )]
+    pub vault: AccountInfo<'info>,
+
+    /// Mint for the vault's lp token
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    /// Account that pays for any additional space required by the vault
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> Migrate<'info> {
+    /// Resizes the vault account, topping up its rent-exempt balance from the payer
+    fn realloc_vault(&self, new_len: usize) -> Result<()> {
+        let required_lamports = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(self.vault.lamports());
+
+        if required_lamports > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    self.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: self.payer.to_account_info(),
+                        to: self.vault.clone(),
+                    },
+                ),
+                required_lamports,
+            )?;
+        }
+
+        self.vault.realloc(new_len, true).map_err(Into::into)
+    }
+}
+
+/// An upgrade of the vault account to a given version
+struct MigrationStep {
+    /// Version of the vault after this step is applied
+    version: [u8; 3],
+
+    /// Rewrites the raw account data, before it is deserialized using the current layout
+    migrate_layout: Option<fn(&Migrate) -> Result<()>>,
+
+    /// Updates fields of the vault once it is deserialized using the current layout
+    migrate_state: Option<fn(&mut Vault, &Migrate) -> Result<()>>,
+}
+
+/// Steps are applied in order to vaults whose version is older than the step's version
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: [3, 7, 0],
+    migrate_layout: Some(grow_vault_config_v3_7),
+    migrate_state: Some(migrate_state_v3_7),
+}];
+
+// VaultConfig grew from 32 to 256 bytes, which shifts every field after it
+fn grow_vault_config_v3_7(accounts: &Migrate) -> Result<()> {
+    (accounts.vault.data_len() == VAULT_LEN_V3_6).ok_or(ErrorCode::InvalidAccount)?;
+
+    accounts.realloc_vault(VAULT_LEN_V3_6 + VAULT_CONFIG_GROWTH_V3_7)?;
+
+    let mut data = accounts.vault.try_borrow_mut_data()?;
+    shift_vault_config_v3_7(&mut data);
+
+    Ok(())
+}
+
+/// Moves the fields after the config to their 3.7.0 offsets in the resized account data,
+/// and zeroes the new config fields
+fn shift_vault_config_v3_7(data: &mut [u8]) {
+    data.copy_within(
+        VAULT_CONFIG_END_V3_6..VAULT_LEN_V3_6,
+        VAULT_CONFIG_END_V3_6 + VAULT_CONFIG_GROWTH_V3_7,
+    );
+    data[VAULT_CONFIG_END_V3_6..VAULT_CONFIG_END_V3_6 + VAULT_CONFIG_GROWTH_V3_7].fill(0);
+}
+
+fn migrate_state_v3_7(vault: &mut Vault, accounts: &Migrate) -> Result<()> {
+    (accounts.lp_token_mint.key() == vault.lp_token_mint).ok_or(ErrorCode::InvalidAccount)?;
+    update_state_v3_7(vault, accounts.lp_token_mint.supply)
+}
+
+// Halt flags were split per operation, the vault-tracked lp token supply
+// can be outdated on vaults that were upgraded from older versions,
+// and new config fields that cannot be 0 need a default
+fn update_state_v3_7(vault: &mut Vault, lp_token_supply: u64) -> Result<()> {
+    vault.set_halt_flags(vault.get_halt_flags().bits())?;
+    vault.lp_token_supply = lp_token_supply;
+    vault.config.swap_slippage_bps = DEFAULT_SWAP_SLIPPAGE_BPS;
+    vault.config.oracle_max_confidence_bps = DEFAULT_ORACLE_MAX_CONFIDENCE_BPS;
+    vault.config.oracle_max_age = DEFAULT_ORACLE_MAX_AGE;
+
+    Ok(())
+}
+
+/// Upgrades a vault account to the layout and version of the current program
+pub fn handler(ctx: Context<Migrate>) -> Result<()> {
+    // Version and owner are at the same offsets in every layout
+    let (from_version, owner) = {
+        let data = ctx.accounts.vault.try_borrow_data()?;
+        (data.len() >= 8 + 3 + 32 && data[..8] == Vault::discriminator())
+            .ok_or(ErrorCode::InvalidAccount)?;
+        (
+            <[u8; 3]>::try_from(&data[8..11]).map_err(|_| ErrorCode::InvalidAccount)?,
+            Pubkey::new(&data[11..43]),
+        )
+    };
+    let to_version = get_version_arr();
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Migrating vault from {:?} to {:?}",
+        from_version,
+        to_version
+    );
+
+    (owner == ctx.accounts.owner.key()).ok_or(ErrorCode::InvalidAccount)?;
+    (from_version <= to_version).ok_or(ErrorCode::UnsupportedVaultVersion)?;
+
+    let steps = MIGRATION_STEPS
+        .iter()
+        .filter(|step| step.version > from_version);
+
+    for migrate_layout in steps.clone().flat_map(|step| step.migrate_layout) {
+        migrate_layout(ctx.accounts)?;
+    }
+
+    (ctx.accounts.vault.data_len() == 8 + size_of::<Vault>()).ok_or(ErrorCode::InvalidAccount)?;
+
+    let mut vault = Account::<Vault>::try_from(&ctx.accounts.vault)?;
+    for migrate_state in steps.flat_map(|step| step.migrate_state) {
+        migrate_state(&mut vault, ctx.accounts)?;
+    }
+    vault.version = to_version;
+    vault.exit(ctx.program_id)?;
+
+    emit!(MigrateEvent {
+        vault: ctx.accounts.vault.key(),
+        from_version,
+        to_version,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::state::{RebalanceMode, StrategyType, VaultFlags, YieldSourceFlags};
+
+    #[derive(AnchorSerialize)]
+    struct VaultConfigV3_6 {
+        deposit_cap: u64,
+        fee_carry_bps: u32,
+        fee_mgmt_bps: u32,
+        referral_fee_pct: u8,
+        allocation_cap_pct: u8,
+        rebalance_mode: RebalanceMode,
+        strategy_type: StrategyType,
+        _padding: [u32; 3],
+    }
+
+    #[derive(AnchorSerialize)]
+    struct VaultV3_6 {
+        version: [u8; 3],
+        owner: Pubkey,
+        vault_authority: Pubkey,
+        authority_seed: Pubkey,
+        authority_bump: [u8; 1],
+        // solend_reserve to referral_fee_receiver
+        accounts: [Pubkey; 11],
+        halt_flags: u16,
+        yield_source_flags: u16,
+        value: SlotTrackedValue,
+        target_allocations: Allocations,
+        config: VaultConfigV3_6,
+        actual_allocations: Allocations,
+        lp_token_supply: u64,
+        vault_port_additional_state_bump: u8,
+        dex_states_bump: u8,
+        _reserved0: [u8; 2],
+        _reserved1: [u32; 25],
+    }
+
+    #[test]
+    fn test_migrate_v3_6_layout() {
+        let owner = Pubkey::new_unique();
+        let accounts = [(); 11].map(|_| Pubkey::new_unique());
+
+        let mut value = SlotTrackedValue::default();
+        value.update(3000, 10);
+        let mut actual_allocations = Allocations::default();
+        actual_allocations.solend.update(1000, 10);
+        actual_allocations.port.update(2000, 10);
+
+        let old_vault = VaultV3_6 {
+            version: [3, 6, 0],
+            owner,
+            vault_authority: Pubkey::new_unique(),
+            authority_seed: Pubkey::new_unique(),
+            authority_bump: [255],
+            accounts,
+            halt_flags: VaultFlags::HALT_DEPOSITS_WITHDRAWS_LEGACY.bits(),
+            yield_source_flags: YieldSourceFlags::all().bits(),
+            value,
+            target_allocations: Allocations::default(),
+            config: VaultConfigV3_6 {
+                deposit_cap: 1_000_000,
+                fee_carry_bps: 1000,
+                fee_mgmt_bps: 100,
+                referral_fee_pct: 20,
+                allocation_cap_pct: 60,
+                rebalance_mode: RebalanceMode::ProofChecker,
+                strategy_type: StrategyType::EqualAllocation,
+                _padding: [0; 3],
+            },
+            actual_allocations,
+            lp_token_supply: 2900,
+            vault_port_additional_state_bump: 254,
+            dex_states_bump: 253,
+            _reserved0: [0; 2],
+            _reserved1: [0; 25],
+        };
+
+        let mut data = Vault::discriminator().to_vec();
+        old_vault.serialize(&mut data).unwrap();
+        assert_eq!(data.len(), VAULT_LEN_V3_6);
+
+        // Resized accounts are zero-initialized
+        data.resize(VAULT_LEN_V3_6 + VAULT_CONFIG_GROWTH_V3_7, 0);
+        shift_vault_config_v3_7(&mut data);
+        assert_eq!(data.len(), 8 + size_of::<Vault>());
+
+        let mut vault = Vault::try_deserialize(&mut &data[..]).unwrap();
+        update_state_v3_7(&mut vault, 3000).unwrap();
+
+        assert_eq!(vault.version, [3, 6, 0]);
+        assert_eq!(vault.owner, owner);
+        assert_eq!(vault.authority_bump, [255]);
+        assert_eq!(vault.solend_reserve, accounts[0]);
+        assert_eq!(vault.referral_fee_receiver, accounts[10]);
+        assert_eq!(vault.get_halt_flags(), VaultFlags::HALT_DEPOSITS_WITHDRAWS);
+        assert_eq!(vault.get_yield_source_flags(), YieldSourceFlags::all());
+        assert_eq!(vault.value.value, 3000);
+
+        assert_eq!(vault.config.deposit_cap, 1_000_000);
+        assert_eq!(vault.config.fee_carry_bps, 1000);
+        assert_eq!(vault.config.fee_mgmt_bps, 100);
+        assert_eq!(vault.config.referral_fee_pct, 20);
+        assert_eq!(vault.config.allocation_cap_pct, 60);
+        assert!(matches!(
+            vault.config.rebalance_mode,
+            RebalanceMode::ProofChecker
+        ));
+        assert!(matches!(
+            vault.config.strategy_type,
+            StrategyType::EqualAllocation
+        ));
+        assert_eq!(vault.config.loss_halt_bps, 0);
+        assert_eq!(vault.config.idle_buffer_pct, 0);
+        assert_eq!(vault.config.lockup_seconds, 0);
+        assert_eq!(vault.config.swap_slippage_bps, DEFAULT_SWAP_SLIPPAGE_BPS);
+        assert_eq!(
+            vault.config.oracle_max_confidence_bps,
+            DEFAULT_ORACLE_MAX_CONFIDENCE_BPS
+        );
+        assert_eq!(vault.config.oracle_max_age, DEFAULT_ORACLE_MAX_AGE);
+
+        assert_eq!(vault.actual_allocations.solend.value, 1000);
+        assert_eq!(vault.actual_allocations.port.value, 2000);
+        assert_eq!(vault.actual_allocations.port.last_update.slot, 10);
+        assert_eq!(vault.lp_token_supply, 3000);
+        assert_eq!(vault.vault_port_additional_state_bump, 254);
+        assert_eq!(vault.dex_states_bump, 253);
+
+        assert_eq!(vault.get_withdraw_only_flags(), YieldSourceFlags::empty());
+        assert_eq!(vault.vault_solend_additional_state_bump, 0);
+        assert!(!vault.solend_obligation_enabled);
+        assert_eq!(vault.realized_losses.solend, 0);
+        assert_eq!(vault.realized_losses.port, 0);
+        assert_eq!(vault.insurance_reserve, Pubkey::default());
+        assert_eq!(vault.uncovered_losses, 0);
+        assert_eq!(vault.last_harvest_timestamp, 0);
+    }
+}
@@ -1,39 +1,75 @@
 pub mod claim_port_reward;
+pub mod claim_solend_reward;
 pub mod consolidate_refresh;
 pub mod deposit;
+pub mod harvest;
+pub mod init_depositor;
+pub mod init_dex_markets;
 pub mod init_dex_orca_legacy;
-pub mod init_dex_orca_legacy_market;
 pub mod init_dex_states;
+pub mod init_dex_whirlpool;
+pub mod init_insurance_reserve;
 pub mod init_port_additional_state;
 pub mod init_port_reward_accounts;
+pub mod init_solend_reward_accounts;
 pub mod init_vault;
+pub mod init_vault_pda;
+pub mod init_vault_registry;
 pub mod init_yield_source;
+pub mod migrate;
 pub mod rebalance;
 pub mod reconcile;
+pub mod reconcile_all;
 pub mod refresh;
+pub mod refresh_all;
 pub mod sell_port_reward;
+pub mod sell_port_sub_reward;
+pub mod sell_solend_reward;
 pub mod sync_lp_token_supply;
 pub mod update_config;
+pub mod update_depositor;
+pub mod update_dex_market;
 pub mod update_halt_flags;
+pub mod update_solend_rewards;
+pub mod update_withdraw_only_flags;
 pub mod update_yield_source_flags;
 pub mod withdraw;
+pub mod withdraw_insurance_surplus;
 
 pub use claim_port_reward::*;
+pub use claim_solend_reward::*;
 pub use consolidate_refresh::*;
 pub use deposit::*;
+pub use harvest::*;
+pub use init_depositor::*;
+pub use init_dex_markets::*;
 pub use init_dex_orca_legacy::*;
-pub use init_dex_orca_legacy_market::*;
 pub use init_dex_states::*;
+pub use init_dex_whirlpool::*;
+pub use init_insurance_reserve::*;
 pub use init_port_additional_state::*;
 pub use init_port_reward_accounts::*;
+pub use init_solend_reward_accounts::*;
 pub use init_vault::*;
+pub use init_vault_pda::*;
+pub use init_vault_registry::*;
 pub use init_yield_source::*;
+pub use migrate::*;
 pub use rebalance::*;
 pub use reconcile::*;
+pub use reconcile_all::*;
 pub use refresh::*;
+pub use refresh_all::*;
 pub use sell_port_reward::*;
+pub use sell_port_sub_reward::*;
+pub use sell_solend_reward::*;
 pub use sync_lp_token_supply::*;
 pub use update_config::*;
+pub use update_depositor::*;
+pub use update_dex_market::*;
 pub use update_halt_flags::*;
+pub use update_solend_rewards::*;
+pub use update_withdraw_only_flags::*;
 pub use update_yield_source_flags::*;
 pub use withdraw::*;
+pub use withdraw_insurance_surplus::*;
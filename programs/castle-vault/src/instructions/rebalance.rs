@@ -1,7 +1,6 @@
 use std::{convert::TryFrom, ops::Deref};
 
 use boolinator::Boolinator;
-use pyth_sdk_solana::PriceStatus;
 use strum::IntoEnumIterator;
 
 use anchor_lang::prelude::*;
@@ -10,15 +9,14 @@ use port_anchor_adaptor::PortStakingPool;
 use solana_maths::Rate;
 use solana_program::pubkey::Pubkey;
 
-use pyth_sdk_solana::load_price_feed_from_account_info;
-
 use crate::{
     adapters::SolendReserve,
     asset_container::AssetContainer,
     errors::ErrorCode,
     impl_provider_index,
     math::SLOTS_PER_YEAR,
-    reserves::{PortReserveWrapper, Provider, Reserves},
+    oracle::load_oracle_price,
+    reserves::{PortReserveWrapper, Provider, Reserves, SolendReserveWrapper},
     state::*,
 };
 
@@ -52,6 +50,7 @@ pub struct Rebalance<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         constraint = !vault.value.last_update.is_stale(Clock::get()?.slot)? @ ErrorCode::VaultIsNotRefreshed,
     )]
     pub vault: Box<Account<'info, Vault>>,
@@ -80,31 +79,83 @@ impl TryFrom<&Context<'_, '_, '_, '_, Rebalance<'_>>> for AssetContainer<Reserve
         // which means that the lifetimes are shared, preventing any other borrows
         // (in particular the mutable borrow required at the end to save state)
 
-        // TODO is there a way to eliminate duplicate code here?
-        let solend = flags
-            .contains(YieldSourceFlags::SOLEND)
-            .as_option()
-            .map(|()| {
-                r.solend_reserve.key.eq(&r.vault.solend_reserve).as_result(
-                    Ok::<_, Error>(Reserves::Solend(Box::new(
-                        Account::<SolendReserve>::try_from(&r.solend_reserve)?
-                            .deref()
-                            .clone(),
-                    ))),
-                    ErrorCode::InvalidAccount,
-                )?
-            })
-            .transpose()?;
+        // Remaining accounts are the Solend additional state and reward oracle if the obligation
+        // is enabled, followed by the Port additional state, reward oracle, sub-reward oracle
+        // if the staking pool has a sub-reward, and staking pool
+        let mut remaining_accounts = ctx.remaining_accounts;
 
-        let mut port: Option<Reserves> = None;
-        if flags.contains(YieldSourceFlags::PORT) {
-            if ctx.remaining_accounts.len() != 3 {
+        let mut solend: Option<Reserves> = None;
+        if flags.contains(YieldSourceFlags::SOLEND) {
+            if r.solend_reserve.key.ne(&r.vault.solend_reserve) {
                 return Err(ErrorCode::InvalidAccount.into());
             }
 
-            let port_additional_states = &ctx.remaining_accounts[0];
-            let port_reward_token_oracle = &ctx.remaining_accounts[1];
-            let port_staking_pool = &ctx.remaining_accounts[2];
+            let solend_reserve = Box::new(
+                Account::<SolendReserve>::try_from(&r.solend_reserve)?
+                    .deref()
+                    .clone(),
+            );
+            let pool_size = solend_reserve.liquidity.total_supply()?.try_floor_u64()?;
+
+            // Liquidity mining rewards are only earned through the obligation
+            let mut solend_reward_per_year = 0;
+            if r.vault.solend_obligation_enabled {
+                if remaining_accounts.len() < 2 {
+                    return Err(ErrorCode::InvalidAccount.into());
+                }
+
+                let solend_additional_states = &remaining_accounts[0];
+                let solend_reward_token_oracle = &remaining_accounts[1];
+                remaining_accounts = &remaining_accounts[2..];
+
+                let solend_additional_states_key = Pubkey::create_program_address(
+                    &[
+                        r.vault.key().as_ref(),
+                        b"solend_additional_state".as_ref(),
+                        &[r.vault.vault_solend_additional_state_bump],
+                    ],
+                    ctx.program_id,
+                )
+                .map_err(|_| ErrorCode::InvalidAccount)?;
+
+                let solend_additional_states_data = Box::new(
+                    Account::<VaultSolendAdditionalState>::try_from(solend_additional_states)?,
+                );
+
+                if solend_additional_states_key.ne(solend_additional_states.key)
+                    || solend_additional_states_data
+                        .solend_reward_token_oracle
+                        .ne(solend_reward_token_oracle.key)
+                {
+                    return Err(ErrorCode::InvalidAccount.into());
+                }
+
+                // Unlike Port, the rate is set by the owner and not read from a staking pool
+                solend_reward_per_year = calc_reward_per_year(
+                    &r.vault.config,
+                    solend_additional_states_data.reward_rate_per_slot,
+                    solend_reward_token_oracle,
+                )?;
+            }
+
+            #[cfg(feature = "debug")]
+            {
+                msg!("Solend pool_size: {}", pool_size);
+                msg!("Solend reward per year: {}", solend_reward_per_year);
+            }
+
+            solend = Some(Reserves::Solend(SolendReserveWrapper {
+                reserve: solend_reserve,
+                reward_per_year: solend_reward_per_year,
+                pool_size,
+            }));
+        }
+
+        let mut port: Option<Reserves> = None;
+        if flags.contains(YieldSourceFlags::PORT) {
+            let (port_additional_states, remaining_accounts) = remaining_accounts
+                .split_first()
+                .ok_or(ErrorCode::InvalidAccount)?;
 
             let (port_additional_states_key, _) = Pubkey::find_program_address(
                 &[r.vault.key().as_ref(), b"port_additional_state".as_ref()],
@@ -115,12 +166,30 @@ impl TryFrom<&Context<'_, '_, '_, '_, Rebalance<'_>>> for AssetContainer<Reserve
                 Account::<VaultPortAdditionalState>::try_from(port_additional_states)?,
             );
 
+            // The sub-reward oracle is only passed if the staking pool has a sub-reward
+            let (port_reward_token_oracle, port_sub_reward_token_oracle, port_staking_pool) = match (
+                port_additional_states_data.sub_reward_available,
+                remaining_accounts,
+            ) {
+                (true, [reward_oracle, sub_reward_oracle, staking_pool]) => {
+                    (reward_oracle, Some(sub_reward_oracle), staking_pool)
+                }
+                (false, [reward_oracle, staking_pool]) => (reward_oracle, None, staking_pool),
+                _ => return Err(ErrorCode::InvalidAccount.into()),
+            };
+
             if r.port_reserve.key.ne(&r.vault.port_reserve)
                 || port_additional_states_key.ne(port_additional_states.key)
                 || port_additional_states_data
                     .port_reward_token_oracle
                     .key()
                     .ne(port_reward_token_oracle.key)
+                || port_sub_reward_token_oracle.map_or(false, |oracle| {
+                    port_additional_states_data
+                        .port_sub_reward_token_oracle
+                        .key()
+                        .ne(oracle.key)
+                })
                 || port_additional_states_data
                     .port_staking_pool
                     .key()
@@ -140,39 +209,37 @@ impl TryFrom<&Context<'_, '_, '_, '_, Rebalance<'_>>> for AssetContainer<Reserve
             let port_exchange_rate = port_reserve.collateral_exchange_rate()?;
             let pool_size = port_exchange_rate.collateral_to_liquidity(pool_data.pool_size)?;
             let rate_per_slot = pool_data.rate_per_slot.try_floor_u64()?;
-            let price_feed = load_price_feed_from_account_info(port_reward_token_oracle)
-                .map_err(|_| ErrorCode::PriceFeedError)?;
-            if price_feed.status != PriceStatus::Trading {
-                return Err(ErrorCode::PriceFeedError.into());
-            }
-            let current_price = price_feed
-                .get_current_price()
-                .ok_or(ErrorCode::PriceFeedError)?;
-            let price_raw = current_price.price as u64;
-            let oracle_factor = (10_u64).pow(current_price.expo.unsigned_abs());
-            let port_reward_per_year = rate_per_slot
-                .checked_mul(price_raw)
-                .ok_or(ErrorCode::MathError)?
-                .checked_mul(SLOTS_PER_YEAR)
-                .ok_or(ErrorCode::MathError)?
-                .checked_div(oracle_factor)
-                .ok_or(ErrorCode::MathError)?;
+            let port_reward_per_year =
+                calc_reward_per_year(&r.vault.config, rate_per_slot, port_reward_token_oracle)?;
+
+            // Pools without a sub-reward do not pass the sub-reward oracle
+            let sub_rate_per_slot = match pool_data.rate_per_slot.sub_reward {
+                Some(sub_rate) if port_sub_reward_token_oracle.is_some() => {
+                    sub_rate.try_floor_u64()?
+                }
+                _ => 0,
+            };
+            let port_sub_reward_per_year = match port_sub_reward_token_oracle {
+                Some(oracle) if sub_rate_per_slot > 0 => {
+                    calc_reward_per_year(&r.vault.config, sub_rate_per_slot, oracle)?
+                }
+                _ => 0,
+            };
 
             #[cfg(feature = "debug")]
             {
-                msg!("price_raw: {}", price_raw);
                 msg!("rate_per_slot: {}", rate_per_slot);
-                msg!("oracle_factor: {}", oracle_factor);
-                msg!("SLOTS_PER_YEAR: {}", SLOTS_PER_YEAR);
+                msg!("sub_rate_per_slot: {}", sub_rate_per_slot);
                 msg!("pool_size: {}", pool_size);
                 msg!("pool_size_lp: {}", pool_data.pool_size);
-                msg!("Expo: {}", current_price.expo);
                 msg!("Reward per year: {}", port_reward_per_year);
+                msg!("Sub-reward per year: {}", port_sub_reward_per_year);
             }
 
             port = Some(Reserves::Port(PortReserveWrapper {
                 reserve: port_reserve,
                 reward_per_year: port_reward_per_year,
+                sub_reward_per_year: port_sub_reward_per_year,
                 pool_size,
             }));
         }
@@ -183,6 +250,31 @@ impl TryFrom<&Context<'_, '_, '_, '_, Rebalance<'_>>> for AssetContainer<Reserve
     }
 }
 
+/// Value of the rewards distributed per year at `rate_per_slot`, denominated in the reserve token
+fn calc_reward_per_year(
+    config: &VaultConfig,
+    rate_per_slot: u64,
+    oracle: &AccountInfo,
+) -> Result<u64> {
+    let price = load_oracle_price(config, oracle)?;
+    let oracle_factor = (10_u64).pow(price.expo.unsigned_abs());
+
+    #[cfg(feature = "debug")]
+    {
+        msg!("price_raw: {}", price.price);
+        msg!("oracle_factor: {}", oracle_factor);
+        msg!("Expo: {}", price.expo);
+    }
+
+    rate_per_slot
+        .checked_mul(price.price)
+        .ok_or(ErrorCode::MathError)?
+        .checked_mul(SLOTS_PER_YEAR)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(oracle_factor)
+        .ok_or_else(|| ErrorCode::MathError.into())
+}
+
 #[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug)]
 pub struct StrategyWeightsArg {
     solend: u16,
@@ -205,22 +297,58 @@ pub fn handler(ctx: Context<Rebalance>, proposed_weights_arg: StrategyWeightsArg
     #[cfg(feature = "debug")]
     msg!("Rebalancing");
 
+    // Check that rebalances are not halted
+    (!ctx
+        .accounts
+        .vault
+        .get_halt_flags()
+        .contains(VaultFlags::HALT_REBALANCES))
+    .ok_or(ErrorCode::HaltedVault)?;
+
     let vault_value = ctx.accounts.vault.value.value;
     let slot = Clock::get()?.slot;
 
-    let assets = Box::new(AssetContainer::<Reserves>::try_from(&ctx)?);
-    let strategy_weights = assets.calculate_weights(
+    // Only the value above the idle buffer is allocated to yield sources,
+    // reconciles redeem whatever is needed to refill the buffer
+    let idle_value = Provider::iter()
+        .filter(|p| ctx.accounts.vault.get_yield_source_availability(*p))
+        .fold(vault_value, |acc, p| {
+            acc.saturating_sub(ctx.accounts.vault.actual_allocations[p].value)
+        });
+    let allocatable_value =
+        vault_value.saturating_sub(ctx.accounts.vault.idle_buffer(vault_value, idle_value));
+
+    #[cfg(feature = "debug")]
+    msg!("Allocatable value: {}", allocatable_value);
+
+    let mut assets = Box::new(AssetContainer::<Reserves>::try_from(&ctx)?);
+
+    // Withdraw-only yield sources are left out of the strategy and get zero weight,
+    // so that reconciles redeem everything from them
+    let withdraw_only_providers: Vec<Provider> = Provider::iter()
+        .filter(|p| ctx.accounts.vault.get_yield_source_state(*p) == YieldSourceState::WithdrawOnly)
+        .collect();
+    withdraw_only_providers
+        .iter()
+        .for_each(|p| assets[*p] = None);
+
+    let mut strategy_weights = assets.calculate_weights(
         ctx.accounts.vault.config.strategy_type,
         ctx.accounts.vault.config.allocation_cap_pct,
     )?;
+    withdraw_only_providers
+        .iter()
+        .for_each(|p| strategy_weights[*p] = Some(Rate::zero()));
 
-    AssetContainer::<u64>::try_from_weights(&strategy_weights, vault_value)
+    AssetContainer::<u64>::try_from_weights(&strategy_weights, allocatable_value)
         .and_then(
             |strategy_allocations| match ctx.accounts.vault.config.rebalance_mode {
                 RebalanceMode::ProofChecker => {
                     let proposed_weights = AssetContainer::<Rate>::from(proposed_weights_arg);
-                    let proposed_allocations =
-                        AssetContainer::<u64>::try_from_weights(&strategy_weights, vault_value)?;
+                    let proposed_allocations = AssetContainer::<u64>::try_from_weights(
+                        &strategy_weights,
+                        allocatable_value,
+                    )?;
 
                     #[cfg(feature = "debug")]
                     msg!(
@@ -231,6 +359,10 @@ pub fn handler(ctx: Context<Rebalance>, proposed_weights_arg: StrategyWeightsArg
                     // Check that proposed weights meet necessary constraints
                     proposed_weights
                         .verify_weights(ctx.accounts.vault.config.allocation_cap_pct)?;
+                    withdraw_only_providers
+                        .iter()
+                        .all(|p| proposed_weights[*p] == Some(Rate::zero()))
+                        .ok_or(ErrorCode::InvalidProposedWeights)?;
 
                     let actual_allocations = ctx
                         .accounts
@@ -279,6 +411,8 @@ pub fn handler(ctx: Context<Rebalance>, proposed_weights_arg: StrategyWeightsArg
             });
             emit!(RebalanceDataEvent::from(&final_allocations));
 
+            ctx.accounts.vault.strategy_weights =
+                StrategyWeights::from_allocations(&final_allocations, allocatable_value, slot);
             ctx.accounts.vault.target_allocations = final_allocations;
         })
 }
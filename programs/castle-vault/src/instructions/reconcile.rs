@@ -6,7 +6,7 @@ use boolinator::Boolinator;
 use crate::{
     errors::ErrorCode,
     reserves::Provider,
-    state::{Vault, VaultFlags},
+    state::{Vault, VaultFlags, YieldSourceState},
 };
 
 const MAX_SLOTS_SINCE_ALLOC_UPDATE: u64 = 100;
@@ -23,7 +23,7 @@ pub trait LendingMarket {
     fn convert_amount_lp_to_reserve(&self, amount: u64) -> Result<u64>;
 
     fn reserve_tokens_in_vault(&self) -> u64;
-    fn lp_tokens_in_vault(&self) -> u64;
+    fn lp_tokens_in_vault(&self) -> Result<u64>;
 
     fn provider(&self) -> Provider;
 }
@@ -31,6 +31,7 @@ pub trait LendingMarket {
 pub trait HasVault {
     fn vault(&self) -> &Vault;
     fn vault_mut(&mut self) -> &mut Vault;
+    fn vault_key(&self) -> Pubkey;
 }
 
 // TODO make this a custom derive procmacro
@@ -45,84 +46,169 @@ macro_rules! impl_has_vault {
             fn vault_mut(&mut self) -> &mut Vault {
                 self.vault.deref_mut()
             }
+
+            fn vault_key(&self) -> Pubkey {
+                self.vault.key()
+            }
         }
     )+)
 }
 
-pub fn handler<T: LendingMarket + HasVault>(ctx: Context<T>, withdraw_option: u64) -> Result<()> {
+/// Movement of a yield source position toward its target allocation
+#[derive(Clone, Copy, Debug)]
+pub enum ReconcileMove {
+    /// Reserve tokens to deposit
+    Deposit(u64),
+    /// LP tokens to redeem
+    Redeem(u64),
+    /// Reserve tokens that are below the dust threshold and are not moved
+    Skip(u64),
+}
+
+#[event]
+pub struct ReconcileSkipEvent {
+    vault: Pubkey,
+    provider: Provider,
+    amount: u64,
+}
+
+/// Computes the movement toward the target allocation set by the last rebalance,
+/// limited by the max reconcile move of the vault
+///
+/// Also returns whether the target allocation is reached after the move
+pub fn target_move<T: LendingMarket + HasVault>(accounts: &T) -> Result<(ReconcileMove, bool)> {
+    let provider = accounts.provider();
+    let lp_tokens_in_vault = accounts.lp_tokens_in_vault()?;
+    let current_value = accounts.convert_amount_lp_to_reserve(lp_tokens_in_vault)?;
+    let allocation = accounts.vault().target_allocations[provider];
+
+    #[cfg(feature = "debug")]
+    {
+        msg!("Desired allocation: {}", allocation.value);
+        msg!("Current allocation: {}", current_value);
+    }
+
+    // Make sure that rebalance was called recently
+    let clock = Clock::get()?;
+    if allocation.last_update.slots_elapsed(clock.slot)? > MAX_SLOTS_SINCE_ALLOC_UPDATE {
+        return Err(ErrorCode::AllocationIsNotUpdated.into());
+    }
+
+    let max_move = match accounts.vault().config.max_reconcile_move {
+        0 => u64::MAX,
+        max_move => max_move,
+    };
+
+    // Moves below the dust threshold are not worth the CPI, and count as reaching the target
+    let value_delta =
+        cmp::max(allocation.value, current_value) - cmp::min(allocation.value, current_value);
+    if value_delta < accounts.vault().min_reconcile_move(allocation.value) {
+        return Ok((ReconcileMove::Skip(value_delta), true));
+    }
+
+    match allocation.value.checked_sub(current_value) {
+        Some(tokens_to_deposit) => Ok((
+            ReconcileMove::Deposit(cmp::min(tokens_to_deposit, max_move)),
+            tokens_to_deposit <= max_move,
+        )),
+        None => {
+            let tokens_to_redeem = lp_tokens_in_vault
+                .checked_sub(accounts.convert_amount_reserve_to_lp(allocation.value)?)
+                .ok_or(ErrorCode::MathError)?;
+            let max_tokens_to_redeem = match max_move {
+                u64::MAX => u64::MAX,
+                max_move => accounts.convert_amount_reserve_to_lp(max_move)?,
+            };
+            Ok((
+                ReconcileMove::Redeem(cmp::min(tokens_to_redeem, max_tokens_to_redeem)),
+                tokens_to_redeem <= max_tokens_to_redeem,
+            ))
+        }
+    }
+}
+
+/// Executes a movement toward the target allocation
+pub fn execute_move<T: LendingMarket + HasVault>(
+    accounts: &mut T,
+    reconcile_move: ReconcileMove,
+) -> Result<()> {
+    match reconcile_move {
+        ReconcileMove::Deposit(tokens_to_deposit) => {
+            // Make sure that the amount deposited is not more than the vault has in reserves
+            // above the idle buffer floor
+            let tokens_to_deposit_checked = cmp::min(
+                tokens_to_deposit,
+                accounts
+                    .reserve_tokens_in_vault()
+                    .saturating_sub(accounts.vault().idle_buffer_floor()),
+            );
+
+            // Withdraw-only yield sources can only be redeemed from
+            (tokens_to_deposit_checked == 0
+                || accounts.vault().get_yield_source_state(accounts.provider())
+                    != YieldSourceState::WithdrawOnly)
+                .ok_or(ErrorCode::WithdrawOnlyYieldSource)?;
+
+            #[cfg(feature = "debug")]
+            msg!("Depositing {}", tokens_to_deposit_checked);
+
+            accounts.deposit(tokens_to_deposit_checked)
+        }
+        ReconcileMove::Redeem(tokens_to_redeem) => {
+            #[cfg(feature = "debug")]
+            msg!("Redeeming {}", tokens_to_redeem);
+
+            accounts.redeem(tokens_to_redeem)
+        }
+        ReconcileMove::Skip(amount) => {
+            #[cfg(feature = "debug")]
+            msg!("Skipping dust move of {}", amount);
+
+            emit!(ReconcileSkipEvent {
+                vault: accounts.vault_key(),
+                provider: accounts.provider(),
+                amount,
+            });
+
+            Ok(())
+        }
+    }
+}
+
+pub fn handler<T: LendingMarket + HasVault>(accounts: &mut T, withdraw_option: u64) -> Result<()> {
     // Check that reconciles are not halted
-    (!ctx
-        .accounts
+    (!accounts
         .vault()
         .get_halt_flags()
         .contains(VaultFlags::HALT_RECONCILES))
     .ok_or(ErrorCode::HaltedVault)?;
 
-    let provider = ctx.accounts.provider();
+    let provider = accounts.provider();
     match withdraw_option {
         // Normal case where reconcile is being called after rebalance
         0 => {
-            let lp_tokens_in_vault = ctx.accounts.lp_tokens_in_vault();
-            let current_value = ctx
-                .accounts
-                .convert_amount_lp_to_reserve(lp_tokens_in_vault)?;
-            let allocation = ctx.accounts.vault().target_allocations[provider];
-
-            #[cfg(feature = "debug")]
-            {
-                msg!("Desired allocation: {}", allocation.value);
-                msg!("Current allocation: {}", current_value);
-            }
-
-            // Make sure that rebalance was called recently
-            let clock = Clock::get()?;
-            if allocation.last_update.slots_elapsed(clock.slot)? > MAX_SLOTS_SINCE_ALLOC_UPDATE {
-                return Err(ErrorCode::AllocationIsNotUpdated.into());
-            }
+            let (reconcile_move, target_reached) = target_move(accounts)?;
+            execute_move(accounts, reconcile_move)?;
 
-            match allocation.value.checked_sub(current_value) {
-                Some(tokens_to_deposit) => {
-                    // Make sure that the amount deposited is not more than the vault has in reserves
-                    let tokens_to_deposit_checked =
-                        cmp::min(tokens_to_deposit, ctx.accounts.reserve_tokens_in_vault());
-
-                    #[cfg(feature = "debug")]
-                    msg!("Depositing {}", tokens_to_deposit_checked);
-
-                    ctx.accounts.deposit(tokens_to_deposit_checked)?;
-                }
-                None => {
-                    let tokens_to_redeem = ctx
-                        .accounts
-                        .lp_tokens_in_vault()
-                        .checked_sub(
-                            ctx.accounts
-                                .convert_amount_reserve_to_lp(allocation.value)?,
-                        )
-                        .ok_or(ErrorCode::MathError)?;
-
-                    #[cfg(feature = "debug")]
-                    msg!("Redeeming {}", tokens_to_redeem);
-
-                    ctx.accounts.redeem(tokens_to_redeem)?;
-                }
+            // Partial moves leave the target pending for the next reconciles
+            if target_reached {
+                accounts.vault_mut().target_allocations[provider].reset();
             }
-            ctx.accounts.vault_mut().target_allocations[provider].reset();
         }
         // Extra case where reconcile is being called in same tx as a withdraw or by vault owner to emergency brake
         _ => {
             // TODO check that tx is signed by owner OR there is a withdraw tx later with the withdraw_option <= withdraw_amount
 
-            let tokens_to_redeem = ctx.accounts.convert_amount_reserve_to_lp(withdraw_option)?;
+            let tokens_to_redeem = accounts.convert_amount_reserve_to_lp(withdraw_option)?;
 
             // Make sure that the amount to redeem is not more than the vault has
             let tokens_to_redeem_checked =
-                cmp::min(tokens_to_redeem, ctx.accounts.lp_tokens_in_vault());
+                cmp::min(tokens_to_redeem, accounts.lp_tokens_in_vault()?);
 
             #[cfg(feature = "debug")]
             msg!("Redeeming {}", tokens_to_redeem_checked);
 
-            ctx.accounts.redeem(tokens_to_redeem_checked)?;
+            accounts.redeem(tokens_to_redeem_checked)?;
         }
     }
     Ok(())
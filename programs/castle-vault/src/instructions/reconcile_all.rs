@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use boolinator::Boolinator;
+use strum::IntoEnumIterator;
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    adapters::{PortAccounts, SolendAccounts, SolendMarket},
+    errors::ErrorCode,
+    reserves::Provider,
+    state::{Vault, VaultFlags},
+};
+
+use super::{execute_move, target_move, HasVault, LendingMarket, ReconcileMove};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReconcilePhase {
+    Redeem,
+    Deposit,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileAll<'info> {
+    /// Vault state account
+    /// Only read here, the vault is updated through the accounts of each yield source
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+}
+
+/// Reconciles every available yield source toward the allocations set by the last rebalance.
+/// All redemptions are executed first so that deposits can use the resulting reserve tokens.
+///
+/// Remaining accounts are the accounts of `reconcile_solend` and `reconcile_port`, in that order,
+/// for each yield source that is available. The Solend obligation accounts (if any)
+/// follow the Solend accounts, see `SolendMarket::load`.
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, ReconcileAll<'info>>) -> Result<()> {
+    // Check that reconciles are not halted
+    (!ctx
+        .accounts
+        .vault
+        .get_halt_flags()
+        .contains(VaultFlags::HALT_RECONCILES))
+    .ok_or(ErrorCode::HaltedVault)?;
+
+    let vault_key = ctx.accounts.vault.key();
+
+    for phase in [ReconcilePhase::Redeem, ReconcilePhase::Deposit] {
+        #[cfg(feature = "debug")]
+        msg!("Reconcile phase: {:?}", phase);
+
+        // Accounts are deserialized again in every phase,
+        // so that each step sees the balances left by the previous ones
+        let mut remaining_accounts = ctx.remaining_accounts;
+        for provider in Provider::iter() {
+            if !ctx.accounts.vault.get_yield_source_availability(provider) {
+                continue;
+            }
+
+            // The vault is persisted after each move so that the next steps read the updated state
+            match provider {
+                Provider::Solend => {
+                    let mut accounts = load_accounts::<SolendAccounts>(
+                        ctx.program_id,
+                        &mut remaining_accounts,
+                        vault_key,
+                    )?;
+                    let mut market = SolendMarket::load(&mut accounts, &mut remaining_accounts)?;
+                    if reconcile_step(&mut market, phase)? {
+                        accounts.exit(ctx.program_id)?;
+                    }
+                }
+                Provider::Port => {
+                    let mut accounts = load_accounts::<PortAccounts>(
+                        ctx.program_id,
+                        &mut remaining_accounts,
+                        vault_key,
+                    )?;
+                    if reconcile_step(&mut accounts, phase)? {
+                        accounts.exit(ctx.program_id)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn load_accounts<'info, T: Accounts<'info>>(
+    program_id: &Pubkey,
+    remaining_accounts: &mut &[AccountInfo<'info>],
+    vault_key: Pubkey,
+) -> Result<T> {
+    // The vault is the first account of the accounts of every yield source
+    (remaining_accounts.first().map(|a| a.key()) == Some(vault_key))
+        .ok_or(ErrorCode::InvalidAccount)?;
+
+    T::try_accounts(program_id, remaining_accounts, &[], &mut BTreeMap::new())
+}
+
+/// Executes the move of a yield source if it belongs to the phase,
+/// and returns whether the vault was updated
+fn reconcile_step<T: LendingMarket + HasVault>(
+    accounts: &mut T,
+    phase: ReconcilePhase,
+) -> Result<bool> {
+    let provider = accounts.provider();
+
+    // Targets are reset once they are reached, so this source was handled in an earlier phase
+    if accounts.vault().target_allocations[provider]
+        .last_update
+        .stale
+    {
+        return Ok(false);
+    }
+
+    let (reconcile_move, target_reached) = target_move(accounts)?;
+    let in_phase = match reconcile_move {
+        ReconcileMove::Redeem(_) | ReconcileMove::Skip(_) => phase == ReconcilePhase::Redeem,
+        ReconcileMove::Deposit(_) => phase == ReconcilePhase::Deposit,
+    };
+
+    if in_phase {
+        execute_move(accounts, reconcile_move)?;
+        if target_reached {
+            accounts.vault_mut().target_allocations[provider].reset();
+        }
+    }
+
+    Ok(in_phase)
+}
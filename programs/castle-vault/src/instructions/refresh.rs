@@ -8,13 +8,47 @@ use port_anchor_adaptor::{port_lending_id, PortReserve};
 use crate::{
     adapters::{solend, SolendReserve},
     errors::ErrorCode,
+    reserves::Provider,
+    state::Vault,
 };
 
+#[event]
+pub struct LossEvent {
+    vault: Pubkey,
+    provider: Provider,
+    /// Loss recognized by this refresh, denominated in the reserve token
+    loss: u64,
+    /// Cumulative loss recognized for the yield source
+    realized_loss: u64,
+}
+
 pub trait Refresher<'info> {
     fn update_actual_allocation(&mut self, remaining_accounts: &[AccountInfo<'info>])
         -> Result<()>;
 }
 
+/// Records the refreshed value of a yield source, reporting any loss since the last refresh
+pub fn update_refreshed_allocation(
+    vault: &mut Account<Vault>,
+    provider: Provider,
+    value: u64,
+) -> Result<()> {
+    let loss = vault.record_refreshed_value(provider, value, Clock::get()?.slot)?;
+
+    if loss > 0 {
+        msg!("Yield source lost {} reserve tokens", loss);
+
+        emit!(LossEvent {
+            vault: vault.key(),
+            provider,
+            loss,
+            realized_loss: vault.realized_losses[provider],
+        });
+    }
+
+    Ok(())
+}
+
 /// Refreshes the reserves of downstream lending markets
 pub fn handler<'info, T: Refresher<'info>>(ctx: Context<'_, '_, '_, 'info, T>) -> Result<()> {
     #[cfg(feature = "debug")]
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use boolinator::Boolinator;
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+use strum::IntoEnumIterator;
+
+use crate::{
+    adapters::{split_vault_obligation, RefreshPort, RefreshSolend},
+    errors::ErrorCode,
+    impl_provider_index,
+    reserves::Provider,
+    state::Vault,
+};
+
+use super::{consolidate_vault_value, split_insurance_reserve, split_reward_accounts, Refresher};
+
+/// Values of the yield sources and of the whole vault after a refresh
+#[event]
+#[derive(Default)]
+pub struct RefreshAllEvent {
+    vault: Pubkey,
+    vault_value: u64,
+    solend: u64,
+    port: u64,
+}
+impl_provider_index!(RefreshAllEvent, u64);
+
+#[derive(Accounts)]
+pub struct RefreshAll<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = lp_token_mint,
+        has_one = fee_receiver,
+        has_one = referral_fee_receiver,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    /// Token account for the vault's reserve tokens
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    /// Mint for the vault lp token
+    #[account(mut)]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    /// Token account that receives the primary ratio of fees from the vault
+    /// CHECK: safe
+    #[account(mut)]
+    pub fee_receiver: AccountInfo<'info>,
+
+    /// Token account that receives the secondary ratio of fees from the vault
+    /// CHECK: safe
+    #[account(mut)]
+    pub referral_fee_receiver: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Refreshes every available yield source, then updates the vault total value and collects fees
+///
+/// Remaining accounts are the insurance reserve if the vault has one,
+/// the reward valuation accounts if the vault values its rewards, followed by
+/// the accounts of `RefreshSolend` and `RefreshPort`, in that order,
+/// for each yield source that is available. The Solend obligation (if any) follows
+/// the Solend accounts, and the Port oracle (if any) goes last.
+pub fn handler<'info>(ctx: Context<'_, '_, '_, 'info, RefreshAll<'info>>) -> Result<()> {
+    #[cfg(feature = "debug")]
+    msg!("Refreshing all yield sources");
+
+    let (insurance_reserve, remaining_accounts) =
+        split_insurance_reserve(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let (reward_accounts, refresh_accounts) =
+        split_reward_accounts(&ctx.accounts.vault, remaining_accounts)?;
+
+    refresh_yield_sources(&mut ctx.accounts.vault, ctx.program_id, refresh_accounts)?;
+
+    consolidate_vault_value(
+        &mut ctx.accounts.vault,
+        &ctx.accounts.vault_authority,
+        &ctx.accounts.vault_reserve_token,
+        &ctx.accounts.lp_token_mint,
+        &ctx.accounts.token_program.to_account_info(),
+        &[
+            ctx.accounts.fee_receiver.clone(),
+            ctx.accounts.referral_fee_receiver.clone(),
+        ],
+        insurance_reserve,
+        reward_accounts,
+    )?;
+
+    let vault = &ctx.accounts.vault;
+    let mut event = Provider::iter().fold(RefreshAllEvent::default(), |mut acc, provider| {
+        if vault.get_yield_source_availability(provider) {
+            acc[provider] = vault.actual_allocations[provider].value;
+        }
+        acc
+    });
+    event.vault = vault.key();
+    event.vault_value = vault.value.value;
+    emit!(event);
+
+    Ok(())
+}
+
+/// Refreshes every available yield source from the accounts of `RefreshSolend` and `RefreshPort`,
+/// and records the refreshed allocations and any losses in the vault
+pub fn refresh_yield_sources<'info>(
+    vault: &mut Account<'info, Vault>,
+    program_id: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let mut remaining_accounts = remaining_accounts;
+    let mut bumps = BTreeMap::new();
+
+    for provider in Provider::iter() {
+        if !vault.get_yield_source_availability(provider) {
+            continue;
+        }
+
+        // Deserializing the provider accounts struct runs all of its account checks.
+        // The refresh is done on the copy of the vault deserialized with the accounts,
+        // which is not written back, so it is recorded again in the vault.
+        match provider {
+            Provider::Solend => {
+                let mut accounts = RefreshSolend::try_accounts(
+                    program_id,
+                    &mut remaining_accounts,
+                    &[],
+                    &mut bumps,
+                )?;
+                (accounts.vault.key() == vault.key()).ok_or(ErrorCode::InvalidAccount)?;
+                accounts.update_actual_allocation(remaining_accounts)?;
+                vault.record_refresh_from(&accounts.vault, provider)?;
+                remaining_accounts = split_vault_obligation(&accounts.vault, remaining_accounts)?.1;
+            }
+            Provider::Port => {
+                let mut accounts = RefreshPort::try_accounts(
+                    program_id,
+                    &mut remaining_accounts,
+                    &[],
+                    &mut bumps,
+                )?;
+                (accounts.vault.key() == vault.key()).ok_or(ErrorCode::InvalidAccount)?;
+                accounts.update_actual_allocation(remaining_accounts)?;
+                vault.record_refresh_from(&accounts.vault, provider)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Refreshes every available yield source and updates the vault total value,
+/// for instructions that need an up-to-date vault value without a separate refresh
+///
+/// Remaining accounts are the fee receiver, the referral fee receiver,
+/// the insurance reserve if the vault has one,
+/// the reward valuation accounts if the vault values its rewards,
+/// followed by the accounts expected by `refresh_yield_sources`
+pub fn refresh_and_consolidate<'info>(
+    vault: &mut Account<'info, Vault>,
+    vault_authority: &AccountInfo<'info>,
+    vault_reserve_token: &Account<'info, TokenAccount>,
+    lp_token_mint: &Account<'info, Mint>,
+    token_program: &AccountInfo<'info>,
+    program_id: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    (remaining_accounts.len() >= 2).ok_or(ErrorCode::InsufficientAccounts)?;
+    let (fee_receivers, remaining_accounts) = remaining_accounts.split_at(2);
+    let (insurance_reserve, remaining_accounts) =
+        split_insurance_reserve(vault, remaining_accounts)?;
+    let (reward_accounts, refresh_accounts) = split_reward_accounts(vault, remaining_accounts)?;
+
+    refresh_yield_sources(vault, program_id, refresh_accounts)?;
+
+    consolidate_vault_value(
+        vault,
+        vault_authority,
+        vault_reserve_token,
+        lp_token_mint,
+        token_program,
+        fee_receivers,
+        insurance_reserve,
+        reward_accounts,
+    )
+}
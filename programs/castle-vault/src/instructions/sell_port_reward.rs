@@ -1,20 +1,75 @@
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::program::invoke_signed;
-use anchor_spl::token::{Token, TokenAccount};
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
+use boolinator::Boolinator;
 
 use crate::{
+    adapters::{OrcaLegacySwap, WhirlpoolSwap},
     errors::ErrorCode,
-    state::{DexStates, OrcaLegacyAccounts, Vault, VaultPortAdditionalState},
+    math::{calc_oracle_amount_out, ONE_AS_BPS},
+    oracle::load_oracle_price,
+    state::{
+        Dex, DexMarket, DexMarkets, DexStates, Vault, VaultConfig, VaultFlags,
+        VaultPortAdditionalState,
+    },
 };
 
+/// Max number of markets that a reward is swapped through to get to the reserve token
+pub const MAX_SWAP_HOPS: usize = 3;
+
+/// Token accounts of the vault that a swap moves tokens between
+pub struct SwapTokenAccounts<'info> {
+    pub source: AccountInfo<'info>,
+    pub destination: AccountInfo<'info>,
+    pub authority: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+// Analogous to `LendingMarket`, for the venues that the vault swaps through
+pub trait DexAdapter<'info> {
+    /// Expected output of selling `amount_in` tokens of `source_mint`.
+    /// Quotes can ignore price impact, so they do not protect against slippage:
+    /// the minimum output passed to `swap` does.
+    fn quote(&self, source_mint: &Pubkey, amount_in: u64) -> Result<u64>;
+
+    fn swap(
+        &self,
+        accounts: &SwapTokenAccounts<'info>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<()>;
+
+    /// Address of the market, as stored in the registry of the DEX
+    fn market(&self) -> Pubkey;
+}
+
+/// A market of a swap route, identified by its index in the registry of the DEX
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug)]
+pub struct SwapHop {
+    pub dex: Dex,
+    pub market_id: u8,
+}
+
+#[event]
+pub struct SellPortRewardEvent {
+    vault: Pubkey,
+    reward_token_mint: Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+    amount_out: u64,
+    hops: u8,
+}
+
 #[derive(Accounts)]
 pub struct SellPortReward<'info> {
     /// Vault state account
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
-        has_one = vault_reserve_token
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
     )]
     pub vault: Box<Account<'info, Vault>>,
 
@@ -28,6 +83,11 @@ pub struct SellPortReward<'info> {
     )]
     pub port_additional_states: Box<Account<'info, VaultPortAdditionalState>>,
 
+    /// Price of the Port reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = port_additional_states.port_reward_token_oracle)]
+    pub port_reward_token_oracle: AccountInfo<'info>,
+
     #[account(
         seeds = [vault.key().as_ref(), b"dex_states".as_ref()], 
         bump
@@ -35,11 +95,10 @@ pub struct SellPortReward<'info> {
     pub dex_states: Box<Account<'info, DexStates>>,
 
     #[account(
-        seeds = [vault.key().as_ref(), b"dex_orca_legacy".as_ref()],
-        bump,
-        has_one = orca_swap_program,
+        seeds = [vault.key().as_ref(), b"dex_orca_legacy_markets".as_ref()],
+        bump = dex_states.orca_legacy_markets_bump,
     )]
-    pub orca_legacy_accounts: Box<Account<'info, OrcaLegacyAccounts>>,
+    pub orca_legacy_markets: Box<Account<'info, DexMarkets>>,
 
     /// CHECK: safe
     pub orca_swap_state: AccountInfo<'info>,
@@ -51,15 +110,15 @@ pub struct SellPortReward<'info> {
     //#[soteria(ignore)]
     pub orca_swap_authority: AccountInfo<'info>,
 
-    /// CHECK: safe
+    /// Pool account of the input token, checked by the swap program
     #[account(mut)]
     //#[soteria(ignore)]
-    pub orca_input_token_account: AccountInfo<'info>,
+    pub orca_input_token_account: Box<Account<'info, TokenAccount>>,
 
-    /// CHECK: safe
+    /// Pool account of the output token, checked by the swap program
     #[account(mut)]
     //#[soteria(ignore)]
-    pub orca_output_token_account: AccountInfo<'info>,
+    pub orca_output_token_account: Box<Account<'info, TokenAccount>>,
 
     /// CHECK: safe
     #[account(mut)]
@@ -71,7 +130,7 @@ pub struct SellPortReward<'info> {
     //#[soteria(ignore)]
     pub orca_fee_account: AccountInfo<'info>,
 
-    /// CHECK: safe
+    /// CHECK: checked against the registered market
     #[account(executable)]
     pub orca_swap_program: AccountInfo<'info>,
 
@@ -82,62 +141,403 @@ pub struct SellPortReward<'info> {
     )]
     pub vault_port_reward_token: Box<Account<'info, TokenAccount>>,
 
+    #[account(address = vault_port_reward_token.mint)]
+    pub port_reward_token_mint: Box<Account<'info, Mint>>,
+
     #[account(mut)]
     pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
 
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
     pub token_program: Program<'info, Token>,
 }
 
-pub fn handler(ctx: Context<SellPortReward>, market_id: u8) -> Result<()> {
-    if market_id as usize > ctx.accounts.orca_legacy_accounts.orca_markets.len() {
-        msg!("Invalid market Id");
-        return Err(ErrorCode::InvalidArgument.into());
+#[derive(Accounts)]
+pub struct SellPortRewardRoute<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"port_additional_state".as_ref()], 
+        bump
+    )]
+    pub port_additional_states: Box<Account<'info, VaultPortAdditionalState>>,
+
+    /// Price of the Port reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = port_additional_states.port_reward_token_oracle)]
+    pub port_reward_token_oracle: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()], 
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_reward".as_ref()],
+        bump
+    )]
+    pub vault_port_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = vault_port_reward_token.mint)]
+    pub port_reward_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SellPortReward<'info> {
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_reward_token.to_account_info(),
+            reward_token_oracle: self.port_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
     }
 
-    // No need to check other orca accounts, because those are checked by the swap program.
-    // using data stored in orca_swap_state.
-    // We only have to check the integrity of orca_swap_state
-    if ctx.accounts.orca_swap_state.key()
-        != ctx.accounts.orca_legacy_accounts.orca_markets[market_id as usize]
-    {
-        return Err(ErrorCode::InvalidAccount.into());
+    fn orca_legacy_swap(&self) -> OrcaLegacySwap<'info> {
+        OrcaLegacySwap {
+            swap_program: self.orca_swap_program.clone(),
+            swap_state: self.orca_swap_state.clone(),
+            swap_authority: self.orca_swap_authority.clone(),
+            pool_source: self.orca_input_token_account.to_account_info(),
+            pool_destination: self.orca_output_token_account.to_account_info(),
+            pool_mint: self.orca_swap_token_mint.clone(),
+            fee_account: self.orca_fee_account.clone(),
+        }
     }
+}
+
+impl<'info> SellPortRewardRoute<'info> {
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_reward_token.to_account_info(),
+            reward_token_oracle: self.port_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+}
+
+/// Accounts used to sell a reward token of the vault for the reserve token
+pub struct RewardSwapAccounts<'info> {
+    pub vault_authority: AccountInfo<'info>,
+    pub reward_token: AccountInfo<'info>,
+    pub reward_token_oracle: AccountInfo<'info>,
+    pub vault_reserve_token: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+/// A swap of a route, between two token accounts of the vault
+pub struct SwapLeg<'info> {
+    pub dex: Box<dyn DexAdapter<'info> + 'info>,
+    pub market: DexMarket,
+    pub source: AccountInfo<'info>,
+    pub destination: AccountInfo<'info>,
+}
+
+impl<'info> RewardSwapAccounts<'info> {
+    /// Minimum output of selling `amount_in` reward tokens, from the oracle price of the reward token
+    /// less the configured slippage
+    fn oracle_minimum_amount_out(
+        &self,
+        config: &VaultConfig,
+        amount_in: u64,
+        reward_decimals: u8,
+        reserve_decimals: u8,
+    ) -> Result<u64> {
+        let oracle_price = load_oracle_price(config, &self.reward_token_oracle)?;
+
+        let oracle_amount_out = calc_oracle_amount_out(
+            amount_in,
+            oracle_price.price,
+            oracle_price.expo,
+            reward_decimals,
+            reserve_decimals,
+        )
+        .ok_or(ErrorCode::MathError)?;
 
-    let amount_in = ctx.accounts.vault_port_reward_token.amount;
-    let minimum_amount_out = 1;
-
-    let ix = spl_token_swap::instruction::swap(
-        &ctx.accounts.orca_swap_program.key(),
-        &ctx.accounts.token_program.key(),
-        &ctx.accounts.orca_swap_state.key(),
-        &ctx.accounts.orca_swap_authority.key(),
-        &ctx.accounts.vault_authority.key(),
-        &ctx.accounts.vault_port_reward_token.key(),
-        &ctx.accounts.orca_input_token_account.key(),
-        &ctx.accounts.orca_output_token_account.key(),
-        &ctx.accounts.vault_reserve_token.key(),
-        &ctx.accounts.orca_swap_token_mint.key(),
-        &ctx.accounts.orca_fee_account.key(),
-        None,
-        spl_token_swap::instruction::Swap {
+        #[cfg(feature = "debug")]
+        msg!(
+            "Oracle value of {} reward tokens: {}",
+            amount_in,
+            oracle_amount_out
+        );
+
+        Ok(
+            (oracle_amount_out as u128 * (ONE_AS_BPS - config.swap_slippage_bps as u64) as u128
+                / ONE_AS_BPS as u128) as u64,
+        )
+    }
+
+    /// Single swap from the reward token account to the vault reserve token account
+    pub fn direct_leg<T: DexAdapter<'info> + 'info>(
+        &self,
+        dex: T,
+        market: DexMarket,
+    ) -> Vec<SwapLeg<'info>> {
+        vec![SwapLeg {
+            dex: Box::new(dex),
+            market,
+            source: self.reward_token.clone(),
+            destination: self.vault_reserve_token.clone(),
+        }]
+    }
+
+    /// Loads the swaps of a route from the reward token account to the vault reserve token account
+    ///
+    /// Remaining accounts are, for each hop, the accounts of the market
+    /// (see `ROUTE_ACCOUNTS_LEN` of the DEX adapters), followed by the vault-owned
+    /// token account that receives the output of the hop, except for the last hop
+    pub fn route_legs(
+        &self,
+        vault: &Pubkey,
+        dex_states: &DexStates,
+        hops: &[SwapHop],
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<Vec<SwapLeg<'info>>> {
+        (!hops.is_empty() && hops.len() <= MAX_SWAP_HOPS).ok_or(ErrorCode::InvalidArgument)?;
+
+        let mut remaining_accounts = remaining_accounts;
+        let mut source = self.reward_token.clone();
+        let mut legs = Vec::with_capacity(hops.len());
+
+        for (i, hop) in hops.iter().enumerate() {
+            let accounts_len = match hop.dex {
+                Dex::OrcaLegacy => OrcaLegacySwap::ROUTE_ACCOUNTS_LEN,
+                Dex::Whirlpool => WhirlpoolSwap::ROUTE_ACCOUNTS_LEN,
+            };
+            (remaining_accounts.len() >= accounts_len).ok_or(ErrorCode::InsufficientAccounts)?;
+            let (dex_accounts, rest) = remaining_accounts.split_at(accounts_len);
+            remaining_accounts = rest;
+
+            let (dex, market): (Box<dyn DexAdapter<'info> + 'info>, DexMarket) = match hop.dex {
+                Dex::OrcaLegacy => {
+                    let (swap, market) = OrcaLegacySwap::from_route_accounts(
+                        vault,
+                        dex_states,
+                        hop.market_id,
+                        dex_accounts,
+                    )?;
+                    (Box::new(swap), market)
+                }
+                Dex::Whirlpool => {
+                    let (swap, market) = WhirlpoolSwap::from_route_accounts(
+                        vault,
+                        dex_states,
+                        hop.market_id,
+                        dex_accounts,
+                    )?;
+                    (Box::new(swap), market)
+                }
+            };
+
+            let destination = if i == hops.len() - 1 {
+                self.vault_reserve_token.clone()
+            } else {
+                let (intermediate, rest) = remaining_accounts
+                    .split_first()
+                    .ok_or(ErrorCode::InsufficientAccounts)?;
+                remaining_accounts = rest;
+
+                // Intermediate tokens have to stay with the vault between hops
+                let intermediate_token = Account::<TokenAccount>::try_from(intermediate)?;
+                (intermediate_token.owner == self.vault_authority.key())
+                    .ok_or(ErrorCode::InvalidAccount)?;
+                intermediate.clone()
+            };
+
+            legs.push(SwapLeg {
+                dex,
+                market,
+                source,
+                destination: destination.clone(),
+            });
+            source = destination;
+        }
+
+        remaining_accounts
+            .is_empty()
+            .ok_or(ErrorCode::InvalidArgument)?;
+
+        Ok(legs)
+    }
+
+    /// Swaps the whole balance of the reward token account into the vault reserve token account,
+    /// through each leg in order.
+    /// Returns the reserve tokens received
+    pub fn sell(
+        &self,
+        vault: &Account<'info, Vault>,
+        legs: &[SwapLeg<'info>],
+        reward_token_mint: &Account<'info, Mint>,
+        reserve_token_mint: &Account<'info, Mint>,
+    ) -> Result<u64> {
+        // Check that swaps are not halted
+        (!vault.get_halt_flags().contains(VaultFlags::HALT_SWAPS)).ok_or(ErrorCode::HaltedVault)?;
+
+        // Check that the registered markets sell the reward token for the reserve token
+        let mut mint = reward_token_mint.key();
+        for leg in legs {
+            (leg.market.input_mint == mint
+                && token::accessor::mint(&leg.source)? == leg.market.input_mint
+                && token::accessor::mint(&leg.destination)? == leg.market.output_mint)
+                .ok_or(ErrorCode::InvalidDexMarketMints)?;
+            mint = leg.market.output_mint;
+        }
+        (mint == reserve_token_mint.key()).ok_or(ErrorCode::InvalidDexMarketMints)?;
+
+        let amount_in = token::accessor::amount(&self.reward_token)?;
+        let minimum_amount_out = self.oracle_minimum_amount_out(
+            &vault.config,
+            amount_in,
+            reward_token_mint.decimals,
+            reserve_token_mint.decimals,
+        )?;
+
+        // Abort early with a clear error when the route cannot reach the minimum output.
+        // Slippage is still enforced by the minimum output of the final swap.
+        let quote = legs
+            .iter()
+            .try_fold(amount_in, |amount, leg| -> Result<u64> {
+                leg.dex.quote(&token::accessor::mint(&leg.source)?, amount)
+            })?;
+        (quote >= minimum_amount_out).ok_or(ErrorCode::SwapQuoteBelowMinimum)?;
+
+        // Each hop sells what the previous one received.
+        // The minimum output of the whole route is enforced on the final leg.
+        let mut amount = amount_in;
+        for (i, leg) in legs.iter().enumerate() {
+            let leg_minimum_amount_out = if i == legs.len() - 1 {
+                minimum_amount_out
+            } else {
+                1
+            };
+            amount = self.swap(vault, leg, amount, leg_minimum_amount_out)?;
+        }
+
+        emit!(SellPortRewardEvent {
+            vault: vault.key(),
+            reward_token_mint: reward_token_mint.key(),
+            amount_in,
+            minimum_amount_out,
+            amount_out: amount,
+            hops: legs.len() as u8,
+        });
+
+        Ok(amount)
+    }
+
+    /// Swaps `amount_in` through one leg of the route, returns the amount received
+    fn swap(
+        &self,
+        vault: &Account<'info, Vault>,
+        leg: &SwapLeg<'info>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> Result<u64> {
+        let balance_before = token::accessor::amount(&leg.destination)?;
+        leg.dex.swap(
+            &SwapTokenAccounts {
+                source: leg.source.clone(),
+                destination: leg.destination.clone(),
+                authority: self.vault_authority.clone(),
+                token_program: self.token_program.clone(),
+            },
             amount_in,
             minimum_amount_out,
-        },
+            &[&vault.authority_seeds()],
+        )?;
+        let balance_after = token::accessor::amount(&leg.destination)?;
+
+        #[cfg(feature = "debug")]
+        msg!(
+            "Swapped {} for {} through {}",
+            amount_in,
+            balance_after.saturating_sub(balance_before),
+            leg.dex.market()
+        );
+
+        balance_after
+            .checked_sub(balance_before)
+            .ok_or_else(|| ErrorCode::MathError.into())
+    }
+}
+
+/// Loads the `DexMarkets` registry of the vault from the accounts of a route hop
+pub fn load_dex_markets<'info>(
+    vault: &Pubkey,
+    dex_states: &DexStates,
+    dex: Dex,
+    account: &AccountInfo<'info>,
+) -> Result<Account<'info, DexMarkets>> {
+    let registry_key = Pubkey::create_program_address(
+        &[
+            vault.as_ref(),
+            dex.markets_seed(),
+            &[dex_states.get_markets_bump(dex)],
+        ],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidAccount)?;
+    (account.key() == registry_key).ok_or(ErrorCode::InvalidAccount)?;
+    Account::try_from(account)
+}
+
+pub fn handler(ctx: Context<SellPortReward>, market_id: u8) -> Result<()> {
+    let orca_legacy_swap = ctx.accounts.orca_legacy_swap();
+    let market = orca_legacy_swap.check_registered(&ctx.accounts.orca_legacy_markets, market_id)?;
+
+    let reward_swap_accounts = ctx.accounts.reward_swap_accounts();
+    reward_swap_accounts.sell(
+        &ctx.accounts.vault,
+        &reward_swap_accounts.direct_leg(orca_legacy_swap, market),
+        &ctx.accounts.port_reward_token_mint,
+        &ctx.accounts.reserve_token_mint,
     )?;
+    Ok(())
+}
 
-    let accounts: Vec<AccountInfo> = vec![
-        ctx.accounts.orca_swap_program.clone(),
-        ctx.accounts.token_program.to_account_info(),
-        ctx.accounts.orca_swap_state.clone(),
-        ctx.accounts.orca_swap_authority.clone(),
-        ctx.accounts.vault_authority.clone(),
-        ctx.accounts.vault_port_reward_token.to_account_info(),
-        ctx.accounts.orca_input_token_account.clone(),
-        ctx.accounts.orca_output_token_account.clone(),
-        ctx.accounts.vault_reserve_token.to_account_info(),
-        ctx.accounts.orca_swap_token_mint.clone(),
-        ctx.accounts.orca_fee_account.clone(),
-    ];
-
-    invoke_signed(&ix, &accounts, &[&ctx.accounts.vault.authority_seeds()]).map_err(|e| e.into())
+/// Sells the Port staking rewards through up to `MAX_SWAP_HOPS` registered markets
+///
+/// Remaining accounts are the accounts of each hop, see `RewardSwapAccounts::route_legs`
+pub fn route_handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SellPortRewardRoute<'info>>,
+    hops: Vec<SwapHop>,
+) -> Result<()> {
+    let reward_swap_accounts = ctx.accounts.reward_swap_accounts();
+    let legs = reward_swap_accounts.route_legs(
+        &ctx.accounts.vault.key(),
+        &ctx.accounts.dex_states,
+        &hops,
+        ctx.remaining_accounts,
+    )?;
+    reward_swap_accounts.sell(
+        &ctx.accounts.vault,
+        &legs,
+        &ctx.accounts.port_reward_token_mint,
+        &ctx.accounts.reserve_token_mint,
+    )?;
+    Ok(())
 }
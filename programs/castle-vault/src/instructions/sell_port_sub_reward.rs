@@ -0,0 +1,227 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+
+use crate::{
+    adapters::OrcaLegacySwap,
+    errors::ErrorCode,
+    state::{DexMarkets, DexStates, Vault, VaultPortAdditionalState},
+};
+
+use super::{RewardSwapAccounts, SwapHop};
+
+#[derive(Accounts)]
+pub struct SellPortSubReward<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"port_additional_state".as_ref()],
+        bump,
+        constraint = port_additional_states.sub_reward_available @ ErrorCode::InvalidAccount,
+    )]
+    pub port_additional_states: Box<Account<'info, VaultPortAdditionalState>>,
+
+    /// Price of the Port sub-reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = port_additional_states.port_sub_reward_token_oracle)]
+    pub port_sub_reward_token_oracle: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_orca_legacy_markets".as_ref()],
+        bump = dex_states.orca_legacy_markets_bump,
+    )]
+    pub orca_legacy_markets: Box<Account<'info, DexMarkets>>,
+
+    /// CHECK: checked against the registered markets
+    pub orca_swap_state: AccountInfo<'info>,
+
+    /// CHECK: checked by the swap program
+    //#[soteria(ignore)]
+    pub orca_swap_authority: AccountInfo<'info>,
+
+    /// Pool account of the input token, checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_input_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// Pool account of the output token, checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_output_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_swap_token_mint: AccountInfo<'info>,
+
+    /// CHECK: checked by the swap program
+    #[account(mut)]
+    //#[soteria(ignore)]
+    pub orca_fee_account: AccountInfo<'info>,
+
+    /// CHECK: checked against the registered market
+    #[account(executable)]
+    pub orca_swap_program: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_sub_reward".as_ref()],
+        bump
+    )]
+    pub vault_port_sub_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = vault_port_sub_reward_token.mint)]
+    pub port_sub_reward_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SellPortSubRewardRoute<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"port_additional_state".as_ref()],
+        bump,
+        constraint = port_additional_states.sub_reward_available @ ErrorCode::InvalidAccount,
+    )]
+    pub port_additional_states: Box<Account<'info, VaultPortAdditionalState>>,
+
+    /// Price of the Port sub-reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = port_additional_states.port_sub_reward_token_oracle)]
+    pub port_sub_reward_token_oracle: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"port_sub_reward".as_ref()],
+        bump
+    )]
+    pub vault_port_sub_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = vault_port_sub_reward_token.mint)]
+    pub port_sub_reward_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SellPortSubReward<'info> {
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_sub_reward_token.to_account_info(),
+            reward_token_oracle: self.port_sub_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+
+    fn orca_legacy_swap(&self) -> OrcaLegacySwap<'info> {
+        OrcaLegacySwap {
+            swap_program: self.orca_swap_program.clone(),
+            swap_state: self.orca_swap_state.clone(),
+            swap_authority: self.orca_swap_authority.clone(),
+            pool_source: self.orca_input_token_account.to_account_info(),
+            pool_destination: self.orca_output_token_account.to_account_info(),
+            pool_mint: self.orca_swap_token_mint.clone(),
+            fee_account: self.orca_fee_account.clone(),
+        }
+    }
+}
+
+impl<'info> SellPortSubRewardRoute<'info> {
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_port_sub_reward_token.to_account_info(),
+            reward_token_oracle: self.port_sub_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+}
+
+/// Sells the Port staking sub-rewards claimed by the vault for reserve tokens
+pub fn handler(ctx: Context<SellPortSubReward>, market_id: u8) -> Result<()> {
+    let orca_legacy_swap = ctx.accounts.orca_legacy_swap();
+    let market = orca_legacy_swap.check_registered(&ctx.accounts.orca_legacy_markets, market_id)?;
+
+    let reward_swap_accounts = ctx.accounts.reward_swap_accounts();
+    reward_swap_accounts.sell(
+        &ctx.accounts.vault,
+        &reward_swap_accounts.direct_leg(orca_legacy_swap, market),
+        &ctx.accounts.port_sub_reward_token_mint,
+        &ctx.accounts.reserve_token_mint,
+    )?;
+    Ok(())
+}
+
+/// Sells the Port staking sub-rewards through up to `MAX_SWAP_HOPS` registered markets
+///
+/// Remaining accounts are the accounts of each hop, see `RewardSwapAccounts::route_legs`
+pub fn route_handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SellPortSubRewardRoute<'info>>,
+    hops: Vec<SwapHop>,
+) -> Result<()> {
+    let reward_swap_accounts = ctx.accounts.reward_swap_accounts();
+    let legs = reward_swap_accounts.route_legs(
+        &ctx.accounts.vault.key(),
+        &ctx.accounts.dex_states,
+        &hops,
+        ctx.remaining_accounts,
+    )?;
+    reward_swap_accounts.sell(
+        &ctx.accounts.vault,
+        &legs,
+        &ctx.accounts.port_sub_reward_token_mint,
+        &ctx.accounts.reserve_token_mint,
+    )?;
+    Ok(())
+}
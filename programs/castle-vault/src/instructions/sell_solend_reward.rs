@@ -0,0 +1,98 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+
+use crate::{
+    errors::ErrorCode,
+    state::{DexStates, Vault, VaultSolendAdditionalState},
+};
+
+use super::{RewardSwapAccounts, SwapHop};
+
+#[derive(Accounts)]
+pub struct SellSolendReward<'info> {
+    /// Vault state account
+    /// Checks that the accounts passed in are correct
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = vault_authority,
+        has_one = vault_reserve_token,
+        has_one = reserve_token_mint,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Authority that the vault uses for lp token mints/burns and transfers to/from downstream assets
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"solend_additional_state".as_ref()],
+        bump = vault.vault_solend_additional_state_bump,
+    )]
+    pub solend_additional_states: Box<Account<'info, VaultSolendAdditionalState>>,
+
+    /// Price of the Solend reward token denominated in the reserve token
+    /// CHECK: address is checked, data is checked when the price is loaded
+    #[account(address = solend_additional_states.solend_reward_token_oracle)]
+    pub solend_reward_token_oracle: AccountInfo<'info>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"solend_reward".as_ref()],
+        bump = solend_additional_states.vault_solend_reward_token_bump
+    )]
+    pub vault_solend_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = vault_solend_reward_token.mint)]
+    pub solend_reward_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub vault_reserve_token: Box<Account<'info, TokenAccount>>,
+
+    pub reserve_token_mint: Box<Account<'info, Mint>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SellSolendReward<'info> {
+    fn reward_swap_accounts(&self) -> RewardSwapAccounts<'info> {
+        RewardSwapAccounts {
+            vault_authority: self.vault_authority.clone(),
+            reward_token: self.vault_solend_reward_token.to_account_info(),
+            reward_token_oracle: self.solend_reward_token_oracle.clone(),
+            vault_reserve_token: self.vault_reserve_token.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+}
+
+/// Sells the Solend liquidity mining rewards claimed by the vault for reserve tokens,
+/// through up to `MAX_SWAP_HOPS` registered markets.
+/// The proceeds are idle in the vault, and counted as yield by the next consolidation.
+///
+/// Remaining accounts are the accounts of each hop, see `RewardSwapAccounts::route_legs`
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, SellSolendReward<'info>>,
+    hops: Vec<SwapHop>,
+) -> Result<()> {
+    let reward_swap_accounts = ctx.accounts.reward_swap_accounts();
+    let legs = reward_swap_accounts.route_legs(
+        &ctx.accounts.vault.key(),
+        &ctx.accounts.dex_states,
+        &hops,
+        ctx.remaining_accounts,
+    )?;
+    reward_swap_accounts.sell(
+        &ctx.accounts.vault,
+        &legs,
+        &ctx.accounts.solend_reward_token_mint,
+        &ctx.accounts.reserve_token_mint,
+    )?;
+    Ok(())
+}
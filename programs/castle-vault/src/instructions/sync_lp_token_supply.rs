@@ -1,7 +1,7 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token::Mint;
 
-use crate::state::Vault;
+use crate::{errors::ErrorCode, state::Vault};
 
 #[event]
 pub struct WithdrawEvent {
@@ -17,6 +17,7 @@ pub struct SyncLpTokenSupply<'info> {
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
         has_one = lp_token_mint,
     )]
@@ -1,6 +1,9 @@
 use anchor_lang::prelude::*;
 
-use crate::state::{Vault, VaultConfig};
+use crate::{
+    errors::ErrorCode,
+    state::{Vault, VaultConfig},
+};
 
 use super::VaultConfigArg;
 
@@ -8,6 +11,7 @@ use super::VaultConfigArg;
 pub struct UpdateConfig<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
     )]
     pub vault: Box<Account<'info, Vault>>,
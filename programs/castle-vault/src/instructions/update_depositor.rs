@@ -0,0 +1,66 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    errors::ErrorCode,
+    state::{Depositor, Vault},
+};
+
+#[event]
+pub struct UpdateDepositorEvent {
+    vault: Pubkey,
+    wallet: Pubkey,
+    allowed: bool,
+    deposit_cap: u64,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDepositor<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        has_one = vault,
+    )]
+    pub depositor: Box<Account<'info, Depositor>>,
+
+    pub owner: Signer<'info>,
+}
+
+impl<'info> UpdateDepositor<'info> {
+    fn update(&mut self, allowed: bool, deposit_cap: u64) -> Result<()> {
+        #[cfg(feature = "debug")]
+        msg!(
+            "Updating depositor {}: allowed {}, cap {}",
+            self.depositor.wallet,
+            allowed,
+            deposit_cap
+        );
+
+        self.depositor.allowed = allowed;
+        self.depositor.deposit_cap = deposit_cap;
+
+        emit!(UpdateDepositorEvent {
+            vault: self.vault.key(),
+            wallet: self.depositor.wallet,
+            allowed,
+            deposit_cap,
+        });
+
+        Ok(())
+    }
+}
+
+/// Adds the wallet to the allowlist of the vault, with an optional deposit cap
+/// that overrides the per-user cap of the vault config
+pub fn add_handler(ctx: Context<UpdateDepositor>, deposit_cap: u64) -> Result<()> {
+    ctx.accounts.update(true, deposit_cap)
+}
+
+/// Removes the wallet from the allowlist of the vault. The wallet can still withdraw.
+pub fn remove_handler(ctx: Context<UpdateDepositor>) -> Result<()> {
+    ctx.accounts.update(false, 0)
+}
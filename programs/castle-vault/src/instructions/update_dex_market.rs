@@ -0,0 +1,362 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::Mint;
+use boolinator::Boolinator;
+
+use crate::{
+    adapters::{OrcaLegacySwap, WhirlpoolSwap},
+    errors::ErrorCode,
+    state::{Dex, DexMarket, DexMarkets, DexStates, OrcaLegacyAccounts, Vault, WhirlpoolAccounts},
+};
+
+#[event]
+pub struct UpdateDexMarketEvent {
+    vault: Pubkey,
+    dex: Dex,
+    market_id: u8,
+    market: Pubkey,
+    input_mint: Pubkey,
+    output_mint: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(dex: Dex)]
+pub struct UpdateDexMarket<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), dex.markets_seed()],
+        bump = dex_states.get_markets_bump(dex)
+    )]
+    pub dex_markets: Box<Account<'info, DexMarkets>>,
+
+    /// Account that stores the program of the DEX
+    /// CHECK: address is checked, data is loaded according to the DEX
+    #[account(
+        seeds = [vault.key().as_ref(), dex.accounts_seed()],
+        bump
+    )]
+    pub dex_accounts: AccountInfo<'info>,
+
+    /// Orca legacy swap state or whirlpool
+    /// CHECK: owner and mints are checked when loading the market
+    pub market: AccountInfo<'info>,
+
+    /// Mint of the token that the vault sells through the market
+    pub input_token_mint: Box<Account<'info, Mint>>,
+
+    /// Mint of the token that the vault buys through the market
+    pub output_token_mint: Box<Account<'info, Mint>>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(dex: Dex)]
+pub struct RemoveDexMarket<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), dex.markets_seed()],
+        bump = dex_states.get_markets_bump(dex)
+    )]
+    pub dex_markets: Box<Account<'info, DexMarkets>>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateOrcaLegacyMarkets<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"dex_states".as_ref()],
+        bump
+    )]
+    pub dex_states: Box<Account<'info, DexStates>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"dex_orca_legacy".as_ref()],
+        bump = dex_states.orca_legacy_accounts_bump
+    )]
+    pub orca_legacy_accounts: Box<Account<'info, OrcaLegacyAccounts>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), Dex::OrcaLegacy.markets_seed()],
+        bump = dex_states.get_markets_bump(Dex::OrcaLegacy)
+    )]
+    pub dex_markets: Box<Account<'info, DexMarkets>>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+}
+
+impl<'info> UpdateDexMarket<'info> {
+    /// Loads the market, checking that it is owned by the program of the DEX
+    /// and that it swaps between the input and output mints
+    fn load_market(&self, dex: Dex) -> Result<DexMarket> {
+        let (swap_program, (mint_a, mint_b)) = match dex {
+            Dex::OrcaLegacy => {
+                let swap_program =
+                    Account::<OrcaLegacyAccounts>::try_from(&self.dex_accounts)?.orca_swap_program;
+                (
+                    swap_program,
+                    OrcaLegacySwap::market_mints(&swap_program, &self.market)?,
+                )
+            }
+            Dex::Whirlpool => {
+                let swap_program =
+                    Account::<WhirlpoolAccounts>::try_from(&self.dex_accounts)?.whirlpool_program;
+                (
+                    swap_program,
+                    WhirlpoolSwap::market_mints(&swap_program, &self.market)?,
+                )
+            }
+        };
+
+        let input_mint = self.input_token_mint.key();
+        let output_mint = self.output_token_mint.key();
+        ((input_mint == mint_a && output_mint == mint_b)
+            || (input_mint == mint_b && output_mint == mint_a))
+            .ok_or(ErrorCode::InvalidDexMarketMints)?;
+
+        Ok(DexMarket {
+            market: self.market.key(),
+            swap_program,
+            input_mint,
+            output_mint,
+        })
+    }
+
+    fn update(&mut self, dex: Dex, market_id: u8, replace: bool) -> Result<()> {
+        let market = self.load_market(dex)?;
+
+        let entry = self
+            .dex_markets
+            .markets
+            .get_mut(market_id as usize)
+            .ok_or(ErrorCode::InvalidArgument)?;
+        if replace {
+            entry
+                .is_registered()
+                .ok_or(ErrorCode::DexMarketNotRegistered)?;
+        } else {
+            (!entry.is_registered()).ok_or(ErrorCode::DexMarketAlreadyRegistered)?;
+        }
+
+        #[cfg(feature = "debug")]
+        msg!(
+            "Registering market {} at {:?} index {}: {} -> {}",
+            market.market,
+            dex,
+            market_id,
+            market.input_mint,
+            market.output_mint
+        );
+
+        *entry = market;
+
+        emit!(UpdateDexMarketEvent {
+            vault: self.vault.key(),
+            dex,
+            market_id,
+            market: market.market,
+            input_mint: market.input_mint,
+            output_mint: market.output_mint,
+        });
+
+        Ok(())
+    }
+}
+
+/// Registers a market that swaps from the input mint to the output mint at an unused market id.
+/// Registered markets are used to sell or buy assets, e.g. in `sell_port_reward`
+pub fn add_handler(ctx: Context<UpdateDexMarket>, dex: Dex, market_id: u8) -> Result<()> {
+    ctx.accounts.update(dex, market_id, false)
+}
+
+/// Replaces a registered market, keeping its market id
+pub fn replace_handler(ctx: Context<UpdateDexMarket>, dex: Dex, market_id: u8) -> Result<()> {
+    ctx.accounts.update(dex, market_id, true)
+}
+
+/// Registered market of a legacy `OrcaLegacyAccounts.orca_markets` entry.
+/// Legacy markets were only used to sell rewards, so they buy the vault reserve token.
+fn legacy_market(
+    market: Pubkey,
+    swap_program: Pubkey,
+    (mint_a, mint_b): (Pubkey, Pubkey),
+    reserve_token_mint: Pubkey,
+) -> Result<DexMarket> {
+    let input_mint = if mint_b == reserve_token_mint {
+        mint_a
+    } else if mint_a == reserve_token_mint {
+        mint_b
+    } else {
+        return Err(ErrorCode::InvalidDexMarketMints.into());
+    };
+
+    Ok(DexMarket {
+        market,
+        swap_program,
+        input_mint,
+        output_mint: reserve_token_mint,
+    })
+}
+
+/// Moves the markets of the legacy `OrcaLegacyAccounts.orca_markets` registry
+/// to the Orca legacy `DexMarkets` registry, keeping their market ids.
+///
+/// Remaining accounts are the swap states of the legacy entries that are set, by market id.
+/// Migrated entries are cleared from the legacy registry.
+pub fn migrate_orca_legacy_handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, MigrateOrcaLegacyMarkets<'info>>,
+) -> Result<()> {
+    let vault_key = ctx.accounts.vault.key();
+    let reserve_token_mint = ctx.accounts.vault.reserve_token_mint;
+    let swap_program = ctx.accounts.orca_legacy_accounts.orca_swap_program;
+    let mut swap_states = ctx.remaining_accounts.iter();
+
+    for (market_id, legacy_entry) in ctx
+        .accounts
+        .orca_legacy_accounts
+        .orca_markets
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, legacy_entry)| **legacy_entry != Pubkey::default())
+    {
+        let swap_state = swap_states.next().ok_or(ErrorCode::InsufficientAccounts)?;
+        (swap_state.key() == *legacy_entry).ok_or(ErrorCode::InvalidAccount)?;
+
+        let market = legacy_market(
+            swap_state.key(),
+            swap_program,
+            OrcaLegacySwap::market_mints(&swap_program, swap_state)?,
+            reserve_token_mint,
+        )?;
+
+        let entry = &mut ctx.accounts.dex_markets.markets[market_id];
+        (!entry.is_registered()).ok_or(ErrorCode::DexMarketAlreadyRegistered)?;
+        *entry = market;
+        *legacy_entry = Pubkey::default();
+
+        emit!(UpdateDexMarketEvent {
+            vault: vault_key,
+            dex: Dex::OrcaLegacy,
+            market_id: market_id as u8,
+            market: market.market,
+            input_mint: market.input_mint,
+            output_mint: market.output_mint,
+        });
+    }
+
+    Ok(())
+}
+
+/// Unregisters a market, the market id can then be reused
+pub fn remove_handler(ctx: Context<RemoveDexMarket>, dex: Dex, market_id: u8) -> Result<()> {
+    let entry = ctx
+        .accounts
+        .dex_markets
+        .markets
+        .get_mut(market_id as usize)
+        .ok_or(ErrorCode::InvalidArgument)?;
+    entry
+        .is_registered()
+        .ok_or(ErrorCode::DexMarketNotRegistered)?;
+    *entry = DexMarket::default();
+
+    emit!(UpdateDexMarketEvent {
+        vault: ctx.accounts.vault.key(),
+        dex,
+        market_id,
+        market: Pubkey::default(),
+        input_mint: Pubkey::default(),
+        output_mint: Pubkey::default(),
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_market() {
+        let market = Pubkey::new_unique();
+        let swap_program = Pubkey::new_unique();
+        let reward_mint = Pubkey::new_unique();
+        let reserve_mint = Pubkey::new_unique();
+
+        let expected = DexMarket {
+            market,
+            swap_program,
+            input_mint: reward_mint,
+            output_mint: reserve_mint,
+        };
+        assert_eq!(
+            legacy_market(
+                market,
+                swap_program,
+                (reward_mint, reserve_mint),
+                reserve_mint
+            )
+            .unwrap(),
+            expected
+        );
+        assert_eq!(
+            legacy_market(
+                market,
+                swap_program,
+                (reserve_mint, reward_mint),
+                reserve_mint
+            )
+            .unwrap(),
+            expected
+        );
+
+        assert_eq!(
+            legacy_market(
+                market,
+                swap_program,
+                (reward_mint, Pubkey::new_unique()),
+                reserve_mint
+            )
+            .unwrap_err(),
+            Error::from(ErrorCode::InvalidDexMarketMints)
+        );
+    }
+}
@@ -1,11 +1,12 @@
 use anchor_lang::prelude::*;
 
-use crate::state::Vault;
+use crate::{errors::ErrorCode, state::Vault};
 
 #[derive(Accounts)]
 pub struct UpdateHaltFlags<'info> {
     #[account(
         mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = owner,
     )]
     pub vault: Box<Account<'info, Vault>>,
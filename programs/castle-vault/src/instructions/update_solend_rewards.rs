@@ -0,0 +1,138 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, TokenAccount};
+use boolinator::Boolinator;
+
+use crate::{
+    adapters::solend_reward_distributor,
+    errors::ErrorCode,
+    math::SLOTS_PER_YEAR,
+    state::{Vault, VaultSolendAdditionalState},
+};
+
+#[event]
+pub struct UpdateSolendRewardsEvent {
+    vault: Pubkey,
+    reward_token_oracle: Pubkey,
+    reward_distributor: Pubkey,
+    reward_rate_per_slot: u64,
+}
+
+#[derive(Accounts)]
+pub struct UpdateSolendRewards<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [vault.key().as_ref(), b"solend_additional_state".as_ref()],
+        bump = vault.vault_solend_additional_state_bump,
+    )]
+    pub solend_additional_states: Box<Account<'info, VaultSolendAdditionalState>>,
+
+    #[account(
+        seeds = [vault.key().as_ref(), b"solend_reward".as_ref()],
+        bump = solend_additional_states.vault_solend_reward_token_bump
+    )]
+    pub vault_solend_reward_token: Box<Account<'info, TokenAccount>>,
+
+    /// Mint of the Solend liquidity mining reward
+    #[account(address = vault_solend_reward_token.mint)]
+    pub solend_reward_token_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: safe
+    pub solend_reward_token_oracle: AccountInfo<'info>,
+
+    /// CHECK: owner is checked, data is checked by the distributor program when claiming
+    #[account(owner = solend_reward_distributor::ID)]
+    pub solend_reward_distributor: AccountInfo<'info>,
+
+    /// CHECK: address is checked
+    #[account(
+        executable,
+        address = solend_reward_distributor::ID,
+    )]
+    pub solend_reward_distributor_program: AccountInfo<'info>,
+
+    /// Owner of the vault
+    /// Only this account can call restricted instructions
+    pub owner: Signer<'info>,
+}
+
+/// Checks that the rewards of a year at the given rate do not exceed the reward token supply.
+///
+/// Solend distributes the rewards off-chain, so the rate cannot be read from the reserve
+/// and the owner is trusted to set the published rate. This only bounds how far it can be off.
+pub fn check_reward_rate(reward_rate_per_slot: u64, reward_token_supply: u64) -> Result<()> {
+    let reward_per_year = reward_rate_per_slot
+        .checked_mul(SLOTS_PER_YEAR)
+        .ok_or(ErrorCode::RewardRateTooHigh)?;
+    (reward_per_year <= reward_token_supply).ok_or(ErrorCode::RewardRateTooHigh)?;
+    Ok(())
+}
+
+/// Sets the Solend liquidity mining campaign the vault claims from and values in rebalances
+pub fn set_solend_rewards(
+    vault: Pubkey,
+    solend_additional_states: &mut VaultSolendAdditionalState,
+    reward_token_oracle: Pubkey,
+    reward_distributor: Pubkey,
+    reward_token_supply: u64,
+    reward_rate_per_slot: u64,
+) -> Result<()> {
+    check_reward_rate(reward_rate_per_slot, reward_token_supply)?;
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Updating Solend reward rate per slot: {} -> {}",
+        solend_additional_states.reward_rate_per_slot,
+        reward_rate_per_slot
+    );
+
+    solend_additional_states.solend_reward_token_oracle = reward_token_oracle;
+    solend_additional_states.solend_reward_distributor = reward_distributor;
+    solend_additional_states.solend_reward_distributor_program = solend_reward_distributor::ID;
+    solend_additional_states.reward_rate_per_slot = reward_rate_per_slot;
+
+    emit!(UpdateSolendRewardsEvent {
+        vault,
+        reward_token_oracle,
+        reward_distributor,
+        reward_rate_per_slot,
+    });
+
+    Ok(())
+}
+
+/// Updates the Solend liquidity mining campaign the vault claims from and values in rebalances
+pub fn handler(ctx: Context<UpdateSolendRewards>, reward_rate_per_slot: u64) -> Result<()> {
+    set_solend_rewards(
+        ctx.accounts.vault.key(),
+        &mut ctx.accounts.solend_additional_states,
+        ctx.accounts.solend_reward_token_oracle.key(),
+        ctx.accounts.solend_reward_distributor.key(),
+        ctx.accounts.solend_reward_token_mint.supply,
+        reward_rate_per_slot,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reward_rate() {
+        assert!(check_reward_rate(0, 0).is_ok());
+        assert!(check_reward_rate(1, SLOTS_PER_YEAR).is_ok());
+        assert_eq!(
+            check_reward_rate(1, SLOTS_PER_YEAR - 1).unwrap_err(),
+            Error::from(ErrorCode::RewardRateTooHigh)
+        );
+        assert_eq!(
+            check_reward_rate(u64::MAX, u64::MAX).unwrap_err(),
+            Error::from(ErrorCode::RewardRateTooHigh)
+        );
+    }
+}
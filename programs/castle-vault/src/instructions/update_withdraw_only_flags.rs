@@ -0,0 +1,26 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ErrorCode, state::Vault};
+
+#[derive(Accounts)]
+pub struct UpdateWithdrawOnlyFlags<'info> {
+    #[account(
+        mut,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub owner: Signer<'info>,
+}
+
+/// Marks yield sources as withdraw-only, so that they can be wound down without an emergency unwind
+pub fn handler(ctx: Context<UpdateWithdrawOnlyFlags>, flags: u16) -> Result<()> {
+    #[cfg(feature = "debug")]
+    msg!("New withdraw-only flags: {:?}", flags);
+
+    ctx.accounts.vault.set_withdraw_only_flags(flags)?;
+
+    // The number of sources the strategy can allocate to has changed
+    ctx.accounts.vault.adjust_allocation_cap()
+}
@@ -5,24 +5,26 @@ use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer};
 
 use crate::{
     errors::ErrorCode,
-    state::{Vault, VaultFlags},
+    state::{Depositor, Vault, VaultFlags},
 };
 
+use super::{refresh_and_consolidate, split_depositor};
+
 #[event]
 pub struct WithdrawEvent {
     vault: Pubkey,
     user: Pubkey,
     amount: u64,
+    early_withdraw_fee: u64,
 }
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     /// Vault state account
-    /// Checks that the refresh has been called in the same slot
     /// Checks that the accounts passed in are correct
     #[account(
         mut,
-        constraint = !vault.value.last_update.is_stale(clock.slot)? @ ErrorCode::VaultIsNotRefreshed,
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
         has_one = vault_authority,
         has_one = vault_reserve_token,
         has_one = lp_token_mint,
@@ -83,65 +85,153 @@ impl<'info> Withdraw<'info> {
             },
         )
     }
+
+    /// Withdraw from the vault
+    ///
+    /// Burns the user's lp tokens and transfers their share of reserve tokens.
+    /// The early withdrawal fee of lp tokens that are not unlocked is left in the vault.
+    fn withdraw(
+        &mut self,
+        depositor: Option<Account<'info, Depositor>>,
+        lp_token_amount: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "debug")]
+        msg!("Withdrawing {} lp tokens", lp_token_amount);
+
+        // Check that withdrawals are not halted
+        (!self
+            .vault
+            .get_halt_flags()
+            .contains(VaultFlags::HALT_WITHDRAWS))
+        .ok_or(ErrorCode::HaltedVault)?;
+
+        let vault = &self.vault;
+
+        let reserve_tokens_to_transfer = crate::math::calc_lp_to_reserve(
+            lp_token_amount,
+            self.vault.lp_token_supply,
+            vault.value.value,
+        )
+        .ok_or(ErrorCode::MathError)?;
+
+        // The lockup is tracked per wallet, so wallets need a depositor account to withdraw
+        // from vaults that track deposits
+        let early_withdraw_fee = match depositor {
+            Some(mut depositor) => {
+                let early_withdraw_fee = depositor.early_withdraw_fee(
+                    &vault.config,
+                    lp_token_amount,
+                    reserve_tokens_to_transfer,
+                    self.clock.unix_timestamp,
+                );
+                depositor.record_withdraw(
+                    lp_token_amount,
+                    reserve_tokens_to_transfer,
+                    self.clock.unix_timestamp,
+                );
+                depositor.exit(&crate::ID)?;
+                early_withdraw_fee
+            }
+            None => {
+                (!vault.config.requires_depositor()).ok_or(ErrorCode::DepositorNotInitialized)?;
+                0
+            }
+        };
+
+        let reserve_tokens_to_transfer = reserve_tokens_to_transfer
+            .checked_sub(early_withdraw_fee)
+            .ok_or(ErrorCode::MathError)?;
+
+        token::burn(self.burn_context(), lp_token_amount)?;
+
+        #[cfg(feature = "debug")]
+        msg!(
+            "Transferring {} reserve tokens, leaving {} in the vault",
+            reserve_tokens_to_transfer,
+            early_withdraw_fee
+        );
+
+        token::transfer(
+            self.transfer_context()
+                .with_signer(&[&vault.authority_seeds()]),
+            reserve_tokens_to_transfer,
+        )?;
+
+        self.vault.lp_token_supply = self
+            .vault
+            .lp_token_supply
+            .checked_sub(lp_token_amount)
+            .ok_or(ErrorCode::MathError)?;
+
+        // This is so that the SDK can read an up-to-date total value without calling refresh
+        self.vault.value.value = self
+            .vault
+            .value
+            .value
+            .checked_sub(reserve_tokens_to_transfer)
+            .ok_or(ErrorCode::MathError)?;
+
+        emit!(WithdrawEvent {
+            vault: self.vault.key(),
+            user: self.user_authority.key(),
+            amount: lp_token_amount,
+            early_withdraw_fee,
+        });
+
+        Ok(())
+    }
 }
 
-/// Withdraw from the vault
+/// Withdraw from the vault once it has been refreshed in the same slot
 ///
-/// Burns the user's lp tokens and transfers their share of reserve tokens
-pub fn handler(ctx: Context<Withdraw>, lp_token_amount: u64) -> Result<()> {
-    #[cfg(feature = "debug")]
-    msg!("Withdrawing {} lp tokens", lp_token_amount);
-
-    // Check that withdrawals are not halted
+/// The only remaining account is the optional depositor account of the user,
+/// which is required by vaults that track deposits, see `VaultConfig::requires_depositor`
+pub fn handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+    lp_token_amount: u64,
+) -> Result<()> {
+    // Check that the vault value has been refreshed in the same slot
     (!ctx
         .accounts
         .vault
-        .get_halt_flags()
-        .contains(VaultFlags::HALT_DEPOSITS_WITHDRAWS))
-    .ok_or(ErrorCode::HaltedVault)?;
-
-    let vault = &ctx.accounts.vault;
-
-    let reserve_tokens_to_transfer = crate::math::calc_lp_to_reserve(
-        lp_token_amount,
-        ctx.accounts.vault.lp_token_supply,
-        vault.value.value,
-    )
-    .ok_or(ErrorCode::MathError)?;
-
-    token::burn(ctx.accounts.burn_context(), lp_token_amount)?;
-
-    #[cfg(feature = "debug")]
-    msg!("Transferring {} reserve tokens", reserve_tokens_to_transfer);
-
-    token::transfer(
-        ctx.accounts
-            .transfer_context()
-            .with_signer(&[&vault.authority_seeds()]),
-        reserve_tokens_to_transfer,
+        .value
+        .last_update
+        .is_stale(ctx.accounts.clock.slot)?)
+    .ok_or(ErrorCode::VaultIsNotRefreshed)?;
+
+    let (depositor, _) = split_depositor(
+        &ctx.accounts.vault,
+        ctx.accounts.user_authority.key,
+        ctx.remaining_accounts,
     )?;
 
-    ctx.accounts.vault.lp_token_supply = ctx
-        .accounts
-        .vault
-        .lp_token_supply
-        .checked_sub(lp_token_amount)
-        .ok_or(ErrorCode::MathError)?;
+    ctx.accounts.withdraw(depositor, lp_token_amount)
+}
 
-    // This is so that the SDK can read an up-to-date total value without calling refresh
-    ctx.accounts.vault.value.value = ctx
-        .accounts
-        .vault
-        .value
-        .value
-        .checked_sub(reserve_tokens_to_transfer)
-        .ok_or(ErrorCode::MathError)?;
+/// Refreshes the vault value, then withdraws in the same instruction
+///
+/// Remaining accounts are the optional depositor account of the user, as in `handler`,
+/// followed by the accounts expected by `refresh_and_consolidate`
+pub fn refresh_and_withdraw_handler<'info>(
+    ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+    lp_token_amount: u64,
+) -> Result<()> {
+    let accounts = ctx.accounts;
+    let (depositor, remaining_accounts) = split_depositor(
+        &accounts.vault,
+        accounts.user_authority.key,
+        ctx.remaining_accounts,
+    )?;
 
-    emit!(WithdrawEvent {
-        vault: ctx.accounts.vault.key(),
-        user: ctx.accounts.user_authority.key(),
-        amount: lp_token_amount,
-    });
+    refresh_and_consolidate(
+        &mut accounts.vault,
+        &accounts.vault_authority,
+        &accounts.vault_reserve_token,
+        &accounts.lp_token_mint,
+        &accounts.token_program.to_account_info(),
+        ctx.program_id,
+        remaining_accounts,
+    )?;
 
-    Ok(())
+    accounts.withdraw(depositor, lp_token_amount)
 }
@@ -0,0 +1,89 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+
+use crate::{errors::ErrorCode, state::Vault};
+
+#[event]
+pub struct WithdrawInsuranceSurplusEvent {
+    vault: Pubkey,
+    amount: u64,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawInsuranceSurplus<'info> {
+    #[account(
+        constraint = vault.is_supported_version() @ ErrorCode::UnsupportedVaultVersion,
+        has_one = owner,
+        has_one = vault_authority,
+        has_one = insurance_reserve,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: safe
+    pub vault_authority: AccountInfo<'info>,
+
+    /// Token account for reserve tokens that cover realized losses
+    #[account(mut)]
+    pub insurance_reserve: Box<Account<'info, TokenAccount>>,
+
+    /// Token account that receives the surplus
+    #[account(
+        mut,
+        constraint = destination.mint == vault.reserve_token_mint @ ErrorCode::InvalidAccount,
+    )]
+    pub destination: Box<Account<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> WithdrawInsuranceSurplus<'info> {
+    fn transfer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        CpiContext::new(
+            self.token_program.to_account_info(),
+            Transfer {
+                from: self.insurance_reserve.to_account_info(),
+                to: self.destination.to_account_info(),
+                authority: self.vault_authority.clone(),
+            },
+        )
+    }
+}
+
+/// Withdraws the insurance reserve balance above its target size.
+/// Tokens needed to cover outstanding losses are never withdrawn.
+pub fn handler(ctx: Context<WithdrawInsuranceSurplus>) -> Result<()> {
+    let vault = &ctx.accounts.vault;
+
+    let retained = vault
+        .config
+        .insurance_target
+        .checked_add(vault.uncovered_losses)
+        .ok_or(ErrorCode::OverflowError)?;
+    let surplus = ctx
+        .accounts
+        .insurance_reserve
+        .amount
+        .saturating_sub(retained);
+
+    #[cfg(feature = "debug")]
+    msg!(
+        "Withdrawing {} reserve tokens of insurance surplus",
+        surplus
+    );
+
+    token::transfer(
+        ctx.accounts
+            .transfer_context()
+            .with_signer(&[&vault.authority_seeds()]),
+        surplus,
+    )?;
+
+    emit!(WithdrawInsuranceSurplusEvent {
+        vault: vault.key(),
+        amount: surplus,
+    });
+
+    Ok(())
+}
@@ -5,11 +5,13 @@ pub mod asset_container;
 pub mod errors;
 pub mod instructions;
 pub mod math;
+pub mod oracle;
 pub mod reserves;
 pub mod state;
 
 use adapters::*;
 use instructions::*;
+use state::Dex;
 
 #[cfg(not(feature = "devnet-castle-addr"))]
 declare_id!("Cast1eoVj8hwfKKRPji4cqX7WFgcnYz3um7TTgnaJKFn");
@@ -29,6 +31,19 @@ pub mod castle_vault {
         instructions::init_vault::handler(ctx, authority_bump, config)
     }
 
+    pub fn initialize_vault_registry(ctx: Context<InitializeVaultRegistry>) -> Result<()> {
+        instructions::init_vault_registry::handler(ctx)
+    }
+
+    pub fn initialize_pda(
+        ctx: Context<InitializePda>,
+        authority_bump: u8,
+        nonce: u64,
+        config: VaultConfigArg,
+    ) -> Result<()> {
+        instructions::init_vault_pda::handler(ctx, authority_bump, nonce, config)
+    }
+
     pub fn initialize_dex_states(ctx: Context<InitializeDexStates>) -> Result<()> {
         instructions::init_dex_states::handler(ctx)
     }
@@ -37,11 +52,34 @@ pub mod castle_vault {
         instructions::init_dex_orca_legacy::handler(ctx)
     }
 
-    pub fn initialize_dex_orca_legacy_market(
-        ctx: Context<InitializeDexOrcaLegacyMarket>,
+    pub fn initialize_dex_whirlpool(ctx: Context<InitializeDexWhirlpool>) -> Result<()> {
+        instructions::init_dex_whirlpool::handler(ctx)
+    }
+
+    pub fn initialize_dex_markets(ctx: Context<InitializeDexMarkets>, dex: Dex) -> Result<()> {
+        instructions::init_dex_markets::handler(ctx, dex)
+    }
+
+    pub fn add_dex_market(ctx: Context<UpdateDexMarket>, dex: Dex, market_id: u8) -> Result<()> {
+        instructions::update_dex_market::add_handler(ctx, dex, market_id)
+    }
+
+    pub fn replace_dex_market(
+        ctx: Context<UpdateDexMarket>,
+        dex: Dex,
         market_id: u8,
     ) -> Result<()> {
-        instructions::init_dex_orca_legacy_market::handler(ctx, market_id)
+        instructions::update_dex_market::replace_handler(ctx, dex, market_id)
+    }
+
+    pub fn migrate_orca_legacy_markets<'info>(
+        ctx: Context<'_, '_, '_, 'info, MigrateOrcaLegacyMarkets<'info>>,
+    ) -> Result<()> {
+        instructions::update_dex_market::migrate_orca_legacy_handler(ctx)
+    }
+
+    pub fn remove_dex_market(ctx: Context<RemoveDexMarket>, dex: Dex, market_id: u8) -> Result<()> {
+        instructions::update_dex_market::remove_handler(ctx, dex, market_id)
     }
 
     pub fn initialize_port_additional_state(
@@ -57,6 +95,13 @@ pub mod castle_vault {
         instructions::init_port_reward_accounts::handler(ctx, sub_reward_available)
     }
 
+    pub fn initialize_solend_reward_accounts(
+        ctx: Context<InitializeSolendRewardAccounts>,
+        reward_rate_per_slot: u64,
+    ) -> Result<()> {
+        instructions::init_solend_reward_accounts::handler(ctx, reward_rate_per_slot)
+    }
+
     pub fn initialize_port<'info>(
         ctx: Context<'_, '_, '_, 'info, InitializePort<'info>>,
     ) -> Result<()> {
@@ -80,18 +125,74 @@ pub mod castle_vault {
         instructions::update_yield_source_flags::handler(ctx, flags)
     }
 
+    pub fn update_withdraw_only_flags(
+        ctx: Context<UpdateWithdrawOnlyFlags>,
+        flags: u16,
+    ) -> Result<()> {
+        instructions::update_withdraw_only_flags::handler(ctx, flags)
+    }
+
     pub fn update_config(ctx: Context<UpdateConfig>, new_config: VaultConfigArg) -> Result<()> {
         instructions::update_config::handler(ctx, new_config)
     }
 
-    pub fn deposit(ctx: Context<Deposit>, reserve_token_amount: u64) -> Result<()> {
+    /// The reward rate is trusted, it only feeds the Solend return of rebalances
+    pub fn update_solend_rewards(
+        ctx: Context<UpdateSolendRewards>,
+        reward_rate_per_slot: u64,
+    ) -> Result<()> {
+        instructions::update_solend_rewards::handler(ctx, reward_rate_per_slot)
+    }
+
+    pub fn initialize_insurance_reserve(ctx: Context<InitializeInsuranceReserve>) -> Result<()> {
+        instructions::init_insurance_reserve::handler(ctx)
+    }
+
+    pub fn withdraw_insurance_surplus(ctx: Context<WithdrawInsuranceSurplus>) -> Result<()> {
+        instructions::withdraw_insurance_surplus::handler(ctx)
+    }
+
+    pub fn initialize_depositor(ctx: Context<InitializeDepositor>) -> Result<()> {
+        instructions::init_depositor::handler(ctx)
+    }
+
+    pub fn add_depositor(ctx: Context<UpdateDepositor>, deposit_cap: u64) -> Result<()> {
+        instructions::update_depositor::add_handler(ctx, deposit_cap)
+    }
+
+    pub fn remove_depositor(ctx: Context<UpdateDepositor>) -> Result<()> {
+        instructions::update_depositor::remove_handler(ctx)
+    }
+
+    pub fn deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        reserve_token_amount: u64,
+    ) -> Result<()> {
         instructions::deposit::handler(ctx, reserve_token_amount)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, lp_token_amount: u64) -> Result<()> {
+    /// Deposits are always left idle, auto-deploy is only done by `deposit`
+    pub fn deposit_with_refresh<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        reserve_token_amount: u64,
+    ) -> Result<()> {
+        instructions::deposit::refresh_and_deposit_handler(ctx, reserve_token_amount)
+    }
+
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+        lp_token_amount: u64,
+    ) -> Result<()> {
         instructions::withdraw::handler(ctx, lp_token_amount)
     }
 
+    pub fn withdraw_with_refresh<'info>(
+        ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+        lp_token_amount: u64,
+    ) -> Result<()> {
+        instructions::withdraw::refresh_and_withdraw_handler(ctx, lp_token_amount)
+    }
+
     pub fn rebalance(ctx: Context<Rebalance>, proposed_weights: StrategyWeightsArg) -> Result<()> {
         instructions::rebalance::handler(ctx, proposed_weights)
     }
@@ -112,25 +213,89 @@ pub mod castle_vault {
         instructions::consolidate_refresh::handler(ctx)
     }
 
-    pub fn reconcile_solend(ctx: Context<SolendAccounts>, withdraw_option: u64) -> Result<()> {
-        instructions::reconcile::handler(ctx, withdraw_option)
+    pub fn refresh_all<'info>(ctx: Context<'_, '_, '_, 'info, RefreshAll<'info>>) -> Result<()> {
+        instructions::refresh_all::handler(ctx)
+    }
+
+    pub fn reconcile_solend<'info>(
+        ctx: Context<'_, '_, '_, 'info, SolendAccounts<'info>>,
+        withdraw_option: u64,
+    ) -> Result<()> {
+        let mut remaining_accounts = ctx.remaining_accounts;
+        instructions::reconcile::handler(
+            &mut SolendMarket::load(ctx.accounts, &mut remaining_accounts)?,
+            withdraw_option,
+        )
     }
 
     pub fn reconcile_port(ctx: Context<PortAccounts>, withdraw_option: u64) -> Result<()> {
-        instructions::reconcile::handler(ctx, withdraw_option)
+        instructions::reconcile::handler(ctx.accounts, withdraw_option)
+    }
+
+    pub fn reconcile_all<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReconcileAll<'info>>,
+    ) -> Result<()> {
+        instructions::reconcile_all::handler(ctx)
     }
 
     pub fn claim_port_reward(ctx: Context<ClaimPortReward>) -> Result<()> {
         instructions::claim_port_reward::handler(ctx)
     }
 
+    pub fn claim_solend_reward(
+        ctx: Context<ClaimSolendReward>,
+        claim_status_bump: u8,
+        index: u64,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        instructions::claim_solend_reward::handler(ctx, claim_status_bump, index, amount, proof)
+    }
+
+    pub fn harvest<'info>(
+        ctx: Context<'_, '_, '_, 'info, Harvest<'info>>,
+        market_id: u8,
+        sub_reward_hops: Vec<SwapHop>,
+    ) -> Result<()> {
+        instructions::harvest::handler(ctx, market_id, sub_reward_hops)
+    }
+
     pub fn sell_port_reward(ctx: Context<SellPortReward>, market_id: u8) -> Result<()> {
         instructions::sell_port_reward::handler(ctx, market_id)
     }
 
+    pub fn sell_port_sub_reward(ctx: Context<SellPortSubReward>, market_id: u8) -> Result<()> {
+        instructions::sell_port_sub_reward::handler(ctx, market_id)
+    }
+
+    pub fn sell_port_reward_route<'info>(
+        ctx: Context<'_, '_, '_, 'info, SellPortRewardRoute<'info>>,
+        hops: Vec<SwapHop>,
+    ) -> Result<()> {
+        instructions::sell_port_reward::route_handler(ctx, hops)
+    }
+
+    pub fn sell_port_sub_reward_route<'info>(
+        ctx: Context<'_, '_, '_, 'info, SellPortSubRewardRoute<'info>>,
+        hops: Vec<SwapHop>,
+    ) -> Result<()> {
+        instructions::sell_port_sub_reward::route_handler(ctx, hops)
+    }
+
+    pub fn sell_solend_reward<'info>(
+        ctx: Context<'_, '_, '_, 'info, SellSolendReward<'info>>,
+        hops: Vec<SwapHop>,
+    ) -> Result<()> {
+        instructions::sell_solend_reward::handler(ctx, hops)
+    }
+
     pub fn sync_lp_token_supply(ctx: Context<SyncLpTokenSupply>) -> Result<()> {
         instructions::sync_lp_token_supply::handler(ctx)
     }
+
+    pub fn migrate(ctx: Context<Migrate>) -> Result<()> {
+        instructions::migrate::handler(ctx)
+    }
 }
 
 solana_security_txt::security_txt! {
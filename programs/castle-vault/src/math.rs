@@ -74,6 +74,39 @@ pub fn calc_mgmt_fees(aum: u64, fee_bps: u64, slots_elapsed: u64) -> Result<u64>
         .ok_or_else(|| ErrorCode::OverflowError.into())
 }
 
+/// Value of `amount_in` tokens in the output token, given the oracle price of the input token
+/// denominated in the output token, as `price * 10^expo`
+pub fn calc_oracle_amount_out(
+    amount_in: u64,
+    price: u64,
+    expo: i32,
+    in_decimals: u8,
+    out_decimals: u8,
+) -> Option<u64> {
+    let expo_factor = 10u128.checked_pow(expo.unsigned_abs())?;
+    let numerator = (amount_in as u128)
+        .checked_mul(price as u128)?
+        .checked_mul(10u128.checked_pow(out_decimals as u32)?)?;
+    let denominator = 10u128.checked_pow(in_decimals as u32)?;
+
+    let amount_out = if expo < 0 {
+        numerator.checked_div(denominator.checked_mul(expo_factor)?)?
+    } else {
+        numerator
+            .checked_mul(expo_factor)?
+            .checked_div(denominator)?
+    };
+    u64::try_from(amount_out).ok()
+}
+
+/// Output of a constant product swap of `amount_in` before fees
+pub fn calc_swap_quote(amount_in: u64, pool_in: u64, pool_out: u64) -> Option<u64> {
+    let amount_out = (pool_out as u128)
+        .checked_mul(amount_in as u128)?
+        .checked_div((pool_in as u128).checked_add(amount_in as u128)?)?;
+    u64::try_from(amount_out).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,4 +139,25 @@ mod tests {
     fn test_mgmt_fees() {
         assert_eq!(calc_mgmt_fees(1261440000, 1000, 100).unwrap(), 200)
     }
+
+    #[test]
+    fn test_oracle_amount_out() {
+        // 2 tokens at a price of 1.5
+        assert_eq!(
+            calc_oracle_amount_out(2_000_000, 150_000_000, -8, 6, 6),
+            Some(3_000_000)
+        );
+        assert_eq!(
+            calc_oracle_amount_out(2_000_000, 150_000_000, -8, 6, 9),
+            Some(3_000_000_000)
+        );
+        assert_eq!(calc_oracle_amount_out(2_000, 3, 1, 3, 0), Some(60));
+    }
+
+    #[test]
+    fn test_swap_quote() {
+        assert_eq!(calc_swap_quote(100, 900, 1000), Some(100));
+        assert_eq!(calc_swap_quote(0, 900, 1000), Some(0));
+        assert_eq!(calc_swap_quote(0, 0, 1000), None);
+    }
 }
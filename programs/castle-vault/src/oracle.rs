@@ -0,0 +1,95 @@
+use std::convert::TryFrom;
+
+use anchor_lang::prelude::*;
+use boolinator::Boolinator;
+use pyth_sdk_solana::{load_price_feed_from_account_info, PriceStatus};
+
+use crate::{errors::ErrorCode, math::ONE_AS_BPS, state::VaultConfig};
+
+/// A checked oracle price, as `price * 10^expo` with a confidence interval of `conf * 10^expo`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OraclePrice {
+    pub price: u64,
+    pub conf: u64,
+    pub expo: i32,
+}
+
+/// Loads the current price of a Pyth oracle, checking it against the vault config
+pub fn load_oracle_price(config: &VaultConfig, oracle: &AccountInfo) -> Result<OraclePrice> {
+    let price_feed =
+        load_price_feed_from_account_info(oracle).map_err(|_| ErrorCode::PriceFeedError)?;
+    (price_feed.status == PriceStatus::Trading).ok_or(ErrorCode::OracleNotTrading)?;
+    let current_price = price_feed
+        .get_current_price()
+        .ok_or(ErrorCode::OracleNotTrading)?;
+
+    check_oracle_price(
+        current_price.price,
+        current_price.conf,
+        current_price.expo,
+        Clock::get()?
+            .unix_timestamp
+            .saturating_sub(price_feed.publish_time),
+        config.oracle_max_age,
+        config.oracle_max_confidence_bps,
+    )
+}
+
+/// Checks the sign, the age and the confidence interval of an oracle price
+fn check_oracle_price(
+    price: i64,
+    conf: u64,
+    expo: i32,
+    age: i64,
+    max_age: u64,
+    max_confidence_bps: u16,
+) -> Result<OraclePrice> {
+    let price = match u64::try_from(price) {
+        Ok(price) if price > 0 => price,
+        _ => return Err(ErrorCode::NonPositiveOraclePrice.into()),
+    };
+
+    if age > max_age as i64 {
+        return Err(ErrorCode::StaleOraclePrice.into());
+    }
+
+    if conf as u128 * ONE_AS_BPS as u128 > price as u128 * max_confidence_bps as u128 {
+        return Err(ErrorCode::OracleConfidenceTooWide.into());
+    }
+
+    Ok(OraclePrice { price, conf, expo })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_oracle_price() {
+        assert_eq!(
+            check_oracle_price(100_000, 2_000, -5, 60, 60, 200).unwrap(),
+            OraclePrice {
+                price: 100_000,
+                conf: 2_000,
+                expo: -5
+            }
+        );
+
+        assert_eq!(
+            check_oracle_price(0, 0, -5, 0, 60, 200).unwrap_err(),
+            Error::from(ErrorCode::NonPositiveOraclePrice)
+        );
+        assert_eq!(
+            check_oracle_price(-100_000, 0, -5, 0, 60, 200).unwrap_err(),
+            Error::from(ErrorCode::NonPositiveOraclePrice)
+        );
+        assert_eq!(
+            check_oracle_price(100_000, 0, -5, 61, 60, 200).unwrap_err(),
+            Error::from(ErrorCode::StaleOraclePrice)
+        );
+        assert_eq!(
+            check_oracle_price(100_000, 2_001, -5, 0, 60, 200).unwrap_err(),
+            Error::from(ErrorCode::OracleConfidenceTooWide)
+        );
+    }
+}
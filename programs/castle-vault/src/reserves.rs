@@ -69,12 +69,26 @@ pub trait ReturnCalculator {
     fn calculate_return(&self, new_allocation: u64, old_allocation: u64) -> Result<Rate>;
 }
 
+#[derive(Clone)]
+pub struct SolendReserveWrapper {
+    pub reserve: Box<SolendReserve>,
+    // Liquidity mining reward to be distributed per year based on the configured rate
+    // Denominated in vault reserve tokens (USDC). Zero if the vault does not earn the rewards.
+    pub reward_per_year: u64,
+    // Total deposits of the reserve denominated in vault reserve tokens (USDC).
+    // Reward we receive is proportional to our share of the deposits.
+    pub pool_size: u64,
+}
+
 #[derive(Clone)]
 pub struct PortReserveWrapper {
     pub reserve: Box<PortReserve>,
     // Reward token to be distributed per year based on current rate
     // Denominated in vault reserve tokens (USDC).
     pub reward_per_year: u64,
+    // Sub-reward token to be distributed per year based on current rate
+    // Denominated in vault reserve tokens (USDC). Zero if the pool has no sub-reward.
+    pub sub_reward_per_year: u64,
     // Size of the staking pool denominated in vault reserve tokens (USDC).
     // Reward we receive is proportional to our share in the staking pool.
     pub pool_size: u64,
@@ -82,7 +96,7 @@ pub struct PortReserveWrapper {
 
 #[derive(Clone)]
 pub enum Reserves {
-    Solend(Box<SolendReserve>),
+    Solend(SolendReserveWrapper),
     Port(PortReserveWrapper),
 }
 
@@ -90,14 +104,14 @@ pub enum Reserves {
 impl ReserveAccessor for Reserves {
     fn utilization_rate(&self) -> Result<Rate> {
         match self {
-            Reserves::Solend(reserve) => reserve.utilization_rate(),
+            Reserves::Solend(reserve) => reserve.reserve.utilization_rate(),
             Reserves::Port(reserve) => reserve.reserve.utilization_rate(),
         }
     }
 
     fn borrow_rate(&self) -> Result<Rate> {
         match self {
-            Reserves::Solend(reserve) => reserve.borrow_rate(),
+            Reserves::Solend(reserve) => reserve.reserve.borrow_rate(),
             Reserves::Port(reserve) => reserve.reserve.borrow_rate(),
         }
     }
@@ -108,9 +122,9 @@ impl ReserveAccessor for Reserves {
         old_allocation: u64,
     ) -> Result<Box<dyn ReserveAccessor>> {
         match self {
-            Reserves::Solend(reserve) => {
-                reserve.reserve_with_deposit(new_allocation, old_allocation)
-            }
+            Reserves::Solend(reserve) => reserve
+                .reserve
+                .reserve_with_deposit(new_allocation, old_allocation),
             Reserves::Port(reserve) => reserve
                 .reserve
                 .reserve_with_deposit(new_allocation, old_allocation),
@@ -122,49 +136,80 @@ impl ReturnCalculator for Reserves {
     fn calculate_return(&self, new_allocation: u64, old_allocation: u64) -> Result<Rate> {
         match self {
             Reserves::Solend(reserve) => {
-                let rate = reserve.calculate_return(new_allocation, old_allocation)?;
+                let base_rate = reserve
+                    .reserve
+                    .calculate_return(new_allocation, old_allocation)?;
+                let reward_rate = calc_reward_rate(
+                    reserve.reward_per_year,
+                    reserve.pool_size,
+                    new_allocation,
+                    old_allocation,
+                )?;
+
                 #[cfg(feature = "debug")]
                 {
-                    msg!("solend rate: {}", rate);
+                    msg!("solend base rate: {}", base_rate);
+                    msg!("solend reward rate: {}", reward_rate);
                 }
-                Ok(rate)
+
+                Ok(base_rate.try_add(reward_rate)?)
             }
             Reserves::Port(reserve) => {
                 let base_rate = reserve
                     .reserve
                     .calculate_return(new_allocation, old_allocation)?;
-
-                let pool_size = reserve
-                    .pool_size
-                    .checked_add(new_allocation)
-                    .ok_or(ErrorCode::MathError)?
-                    .checked_sub(old_allocation)
-                    .ok_or(ErrorCode::MathError)?;
-
-                if pool_size == 0 {
-                    return Ok(base_rate);
-                }
-
-                let reward_apr_bps = reserve
-                    .reward_per_year
-                    .checked_mul(10000)
-                    .ok_or(ErrorCode::MathError)?
-                    .checked_div(pool_size)
-                    .ok_or(ErrorCode::MathError)?;
-                let reward_rate = Rate::from_bips(reward_apr_bps);
+                let reward_rate = calc_reward_rate(
+                    reserve.reward_per_year,
+                    reserve.pool_size,
+                    new_allocation,
+                    old_allocation,
+                )?;
+                let sub_reward_rate = calc_reward_rate(
+                    reserve.sub_reward_per_year,
+                    reserve.pool_size,
+                    new_allocation,
+                    old_allocation,
+                )?;
 
                 #[cfg(feature = "debug")]
                 {
                     msg!("port base rate: {}", base_rate);
                     msg!("port reward rate: {}", reward_rate);
+                    msg!("port sub-reward rate: {}", sub_reward_rate);
                 }
 
-                Ok(base_rate.try_add(reward_rate)?)
+                Ok(base_rate.try_add(reward_rate)?.try_add(sub_reward_rate)?)
             }
         }
     }
 }
 
+/// APR of a reward distributed proportionally to the share of the pool,
+/// after moving the allocation of the vault in the pool from `old_allocation` to `new_allocation`
+fn calc_reward_rate(
+    reward_per_year: u64,
+    pool_size: u64,
+    new_allocation: u64,
+    old_allocation: u64,
+) -> Result<Rate> {
+    let pool_size = pool_size
+        .checked_add(new_allocation)
+        .ok_or(ErrorCode::MathError)?
+        .checked_sub(old_allocation)
+        .ok_or(ErrorCode::MathError)?;
+
+    if pool_size == 0 {
+        return Ok(Rate::zero());
+    }
+
+    let reward_apr_bps = reward_per_year
+        .checked_mul(10000)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(pool_size)
+        .ok_or(ErrorCode::MathError)?;
+    Ok(Rate::from_bips(reward_apr_bps))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1,4 +1,4 @@
-use std::cmp::Ordering;
+use std::cmp::{self, Ordering};
 
 use core::convert::TryFrom;
 
@@ -7,6 +7,7 @@ use strum::IntoEnumIterator;
 use type_layout::TypeLayout;
 
 use anchor_lang::prelude::*;
+use boolinator::Boolinator;
 use jet_proto_proc_macros::assert_size;
 
 use crate::{
@@ -18,7 +19,15 @@ use crate::{
     reserves::Provider,
 };
 
-#[assert_size(768)]
+/// Max slots since the last rebalance for its strategy weights to be used to deploy deposits,
+/// about a day
+pub const MAX_SLOTS_SINCE_REBALANCE: u64 = 216_000;
+
+/// Oldest vault version that handlers will operate on.
+/// Vaults initialized with an older version need to be upgraded with `migrate` first.
+pub const MIN_SUPPORTED_VERSION: [u8; 3] = [3, 7, 0];
+
+#[assert_size(992)]
 #[account]
 #[repr(C, align(8))]
 #[derive(Debug)]
@@ -89,19 +98,58 @@ pub struct Vault {
     // Stores accounts needed for interacting with DEX
     pub dex_states_bump: u8,
 
-    _reserved0: [u8; 2],
-    _reserved1: [u32; 25],
+    /// Yield sources that can only be redeemed from, a subset of yield_source_flags
+    withdraw_only_flags: u16,
+
+    pub vault_solend_additional_state_bump: u8,
+
+    /// Whether the solend lp tokens are deposited into the vault's obligation
+    /// to accrue Solend liquidity mining rewards
+    pub solend_obligation_enabled: bool,
+
+    _reserved1: [u8; 2],
+
+    /// Cumulative losses recognized by refreshes, denominated in the reserve token
+    pub realized_losses: RealizedLosses,
+
+    /// Account where reserve tokens set aside to cover losses are stored
+    /// Not counted in the vault value. Unset if the vault has no insurance reserve.
+    pub insurance_reserve: Pubkey,
+
+    /// Realized losses that have not been covered by the insurance reserve yet
+    pub uncovered_losses: u64,
+
+    /// Unix timestamp of the last harvest
+    pub last_harvest_timestamp: i64,
+
+    /// Weights of the yield sources set by the last rebalance
+    /// Kept after the target allocations are reached, to deploy deposits between rebalances
+    pub strategy_weights: StrategyWeights,
+
+    _reserved2: [u64; 1],
 }
 
 impl Vault {
+    pub fn has_insurance_reserve(&self) -> bool {
+        self.insurance_reserve != Pubkey::default()
+    }
+
+    /// Returns false if the vault needs to be migrated before it can be used
+    pub fn is_supported_version(&self) -> bool {
+        self.version >= MIN_SUPPORTED_VERSION
+    }
+
+    /// Vaults that have not been migrated yet may still store the legacy flags,
+    /// so they are always migrated when read
     pub fn get_halt_flags(&self) -> VaultFlags {
         VaultFlags::from_bits(self.halt_flags)
             .unwrap_or_else(|| panic!("{:?} does not resolve to valid VaultFlags", self.halt_flags))
+            .migrate()
     }
 
     pub fn set_halt_flags(&mut self, bits: u16) -> Result<()> {
-        VaultFlags::from_bits(bits).ok_or(ErrorCode::InvalidVaultFlags)?;
-        self.halt_flags = bits;
+        let flags = VaultFlags::from_bits(bits).ok_or(ErrorCode::InvalidVaultFlags)?;
+        self.halt_flags = flags.migrate().bits();
         Ok(())
     }
 
@@ -117,15 +165,39 @@ impl Vault {
     pub fn set_yield_source_flags(&mut self, flags: u16) -> Result<()> {
         YieldSourceFlags::from_bits(flags).ok_or(ErrorCode::InvalidVaultFlags)?;
         self.yield_source_flags = flags;
+        // Keep the withdraw-only flags a subset of the available yield sources
+        self.withdraw_only_flags &= flags;
+        Ok(())
+    }
+
+    pub fn get_withdraw_only_flags(&self) -> YieldSourceFlags {
+        YieldSourceFlags::from_bits(self.withdraw_only_flags).unwrap_or_else(|| {
+            panic!(
+                "{:?} does not resolve to valid YieldSourceFlags",
+                self.withdraw_only_flags
+            )
+        })
+    }
+
+    pub fn set_withdraw_only_flags(&mut self, flags: u16) -> Result<()> {
+        YieldSourceFlags::from_bits(flags).ok_or(ErrorCode::InvalidVaultFlags)?;
+        // Only available yield sources can be withdraw-only
+        ((flags & !self.yield_source_flags) == 0).ok_or(ErrorCode::InvalidVaultFlags)?;
+        self.withdraw_only_flags = flags;
         Ok(())
     }
 
     // The lower bound of allocation cap is adjusted to 100 / N
-    // Where N is the number of available yield sources according to yield_source_flags
+    // Where N is the number of active yield sources, i.e. those that are
+    // set in yield_source_flags and are not withdraw-only
+    // The cap is left unchanged if there are no active yield sources
     pub fn adjust_allocation_cap(&mut self) -> Result<()> {
-        let cnt: u8 =
-            u8::try_from((0..16).fold(0, |sum, i| sum + ((self.yield_source_flags >> i) & 1)))
-                .map_err(|_| ErrorCode::MathError)?;
+        let active_flags = self.yield_source_flags & !self.withdraw_only_flags;
+        let cnt: u8 = u8::try_from((0..16).fold(0, |sum, i| sum + ((active_flags >> i) & 1)))
+            .map_err(|_| ErrorCode::MathError)?;
+        if cnt == 0 {
+            return Ok(());
+        }
         let new_allocation_cap = 100_u8
             .checked_div(cnt)
             .ok_or(ErrorCode::MathError)?
@@ -147,11 +219,132 @@ impl Vault {
     }
 
     pub fn get_yield_source_availability(&self, provider: Provider) -> bool {
-        let flags = self.get_yield_source_flags();
-        match provider {
-            Provider::Solend => flags.contains(YieldSourceFlags::SOLEND),
-            Provider::Port => flags.contains(YieldSourceFlags::PORT),
+        self.get_yield_source_flags()
+            .contains(YieldSourceFlags::from(provider))
+    }
+
+    pub fn get_yield_source_state(&self, provider: Provider) -> YieldSourceState {
+        if !self.get_yield_source_availability(provider) {
+            YieldSourceState::Disabled
+        } else if self
+            .get_withdraw_only_flags()
+            .contains(YieldSourceFlags::from(provider))
+        {
+            YieldSourceState::WithdrawOnly
+        } else {
+            YieldSourceState::Active
+        }
+    }
+
+    /// Reserve tokens to keep idle in the vault instead of allocating them to yield sources,
+    /// given the vault value and the reserve tokens currently idle
+    pub fn idle_buffer(&self, vault_value: u64, idle_value: u64) -> u64 {
+        if self.config.idle_buffer_max_pct > 0 {
+            let band = calc_pct(vault_value, self.config.idle_buffer_min_pct)
+                ..=calc_pct(vault_value, self.config.idle_buffer_max_pct);
+            if band.contains(&idle_value) {
+                return idle_value;
+            }
+        }
+        calc_pct(vault_value, self.config.idle_buffer_pct)
+    }
+
+    /// Smallest move that a reconcile executes for the given target allocation
+    pub fn min_reconcile_move(&self, allocation: u64) -> u64 {
+        let min_move_of_allocation =
+            (allocation as u128 * self.config.min_reconcile_move_bps as u128 / 10000) as u64;
+        cmp::max(self.config.min_reconcile_move, min_move_of_allocation)
+    }
+
+    /// Reserve tokens that reconciles leave in the vault
+    pub fn idle_buffer_floor(&self) -> u64 {
+        if self.config.idle_buffer_max_pct > 0 {
+            calc_pct(self.value.value, self.config.idle_buffer_min_pct)
+        } else {
+            0
+        }
+    }
+
+    /// Active yield source that is the furthest below its weight from the last rebalance,
+    /// and the reserve tokens it is short of given the current vault value.
+    ///
+    /// Returns None if the last rebalance is older than `MAX_SLOTS_SINCE_REBALANCE`
+    /// or no yield source is underweight.
+    pub fn most_underweight(&self, slot: u64) -> Result<Option<(Provider, u64)>> {
+        if self.strategy_weights.last_update.slots_elapsed(slot)? > MAX_SLOTS_SINCE_REBALANCE {
+            return Ok(None);
         }
+
+        // Same allocatable value as rebalances
+        let vault_value = self.value.value;
+        let idle_value = Provider::iter()
+            .filter(|p| self.get_yield_source_availability(*p))
+            .fold(vault_value, |acc, p| {
+                acc.saturating_sub(self.actual_allocations[p].value)
+            });
+        let allocatable_value =
+            vault_value.saturating_sub(self.idle_buffer(vault_value, idle_value));
+
+        Ok(Provider::iter()
+            .filter(|p| self.get_yield_source_state(*p) == YieldSourceState::Active)
+            .map(|p| {
+                let target =
+                    (allocatable_value as u128 * self.strategy_weights[p] as u128 / 10000) as u64;
+                (p, target.saturating_sub(self.actual_allocations[p].value))
+            })
+            .max_by_key(|(_, shortfall)| *shortfall)
+            .filter(|(_, shortfall)| *shortfall > 0))
+    }
+
+    /// Updates the value of a yield source from a refresh.
+    /// Any drop from the last recorded value is recognized as a realized loss,
+    /// and halts the vault if it exceeds the configured share of that value.
+    ///
+    /// Returns the realized loss
+    pub fn record_refreshed_value(
+        &mut self,
+        provider: Provider,
+        value: u64,
+        slot: u64,
+    ) -> Result<u64> {
+        let last_value = self.actual_allocations[provider].value;
+        let loss = last_value.saturating_sub(value);
+
+        if loss > 0 {
+            self.realized_losses[provider] = self.realized_losses[provider]
+                .checked_add(loss)
+                .ok_or(ErrorCode::OverflowError)?;
+            self.uncovered_losses = self
+                .uncovered_losses
+                .checked_add(loss)
+                .ok_or(ErrorCode::OverflowError)?;
+
+            if self.config.loss_halt_bps > 0
+                && (loss as u128) * 10000
+                    >= (last_value as u128) * (self.config.loss_halt_bps as u128)
+            {
+                #[cfg(feature = "debug")]
+                msg!("Halting vault after a loss of {} reserve tokens", loss);
+
+                // Refreshes stay enabled so that the vault value keeps reflecting the loss
+                let mut flags = self.get_halt_flags();
+                flags.insert(VaultFlags::HALT_ON_LOSS);
+                self.set_halt_flags(flags.bits())?;
+            }
+        }
+
+        self.actual_allocations[provider].update(value, slot);
+
+        Ok(loss)
+    }
+
+    /// Records the refresh of a yield source done on another copy of the vault,
+    /// such as the one deserialized by the refresh accounts of the yield source
+    ///
+    /// Returns the realized loss
+    pub fn record_refresh_from(&mut self, refreshed: &Vault, provider: Provider) -> Result<u64> {
+        let allocation = refreshed.actual_allocations[provider];
+        self.record_refreshed_value(provider, allocation.value, allocation.last_update.slot)
     }
 
     pub fn calculate_fees(&self, new_vault_value: u64, slot: u64) -> Result<u64> {
@@ -188,6 +381,11 @@ impl Vault {
     }
 }
 
+fn calc_pct(value: u64, pct: u8) -> u64 {
+    // Cannot overflow since pct is at most 100
+    (value as u128 * pct as u128 / 100) as u64
+}
+
 #[assert_size(392)]
 #[account]
 #[repr(C, align(8))]
@@ -221,6 +419,37 @@ pub struct VaultPortAdditionalState {
     _reserved2: [u64; 32],
 }
 
+#[assert_size(256)]
+#[account]
+#[repr(C, align(8))]
+#[derive(Debug, Default)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct VaultSolendAdditionalState {
+    /// Obligation holding the vault's solend lp tokens
+    pub vault_solend_obligation_bump: u8,
+
+    /// Account used to receive Solend liquidity mining rewards
+    pub vault_solend_reward_token_bump: u8,
+
+    _reserved0: [u8; 6],
+
+    pub solend_reward_token_oracle: Pubkey,
+
+    /// Merkle distributor the liquidity mining rewards are claimed from
+    pub solend_reward_distributor: Pubkey,
+
+    /// Always the pinned merkle distributor program, kept for the account layout
+    pub solend_reward_distributor_program: Pubkey,
+
+    /// Reward tokens distributed to all depositors of the reserve per slot
+    /// Set by the owner, since Solend distributes the rewards off-chain.
+    /// The owner is trusted to set the published rate,
+    /// it is only bounded by the supply of the reward token.
+    pub reward_rate_per_slot: u64,
+
+    _reserved1: [u64; 18],
+}
+
 #[assert_size(128)]
 #[account]
 #[repr(C, align(8))]
@@ -229,10 +458,224 @@ pub struct VaultPortAdditionalState {
 pub struct DexStates {
     pub orca_legacy_accounts_bump: u8,
 
-    _reserved0: [u8; 7],
+    pub whirlpool_accounts_bump: u8,
+
+    pub orca_legacy_markets_bump: u8,
+
+    pub whirlpool_markets_bump: u8,
+
+    _reserved0: [u8; 4],
     _reserved1: [u64; 15],
 }
 
+impl DexStates {
+    pub fn get_markets_bump(&self, dex: Dex) -> u8 {
+        match dex {
+            Dex::OrcaLegacy => self.orca_legacy_markets_bump,
+            Dex::Whirlpool => self.whirlpool_markets_bump,
+        }
+    }
+
+    pub fn set_markets_bump(&mut self, dex: Dex, bump: u8) {
+        match dex {
+            Dex::OrcaLegacy => self.orca_legacy_markets_bump = bump,
+            Dex::Whirlpool => self.whirlpool_markets_bump = bump,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum Dex {
+    OrcaLegacy,
+    Whirlpool,
+}
+
+impl Dex {
+    /// Seed of the account that holds the program of the DEX
+    pub fn accounts_seed(&self) -> &'static [u8] {
+        match self {
+            Dex::OrcaLegacy => b"dex_orca_legacy",
+            Dex::Whirlpool => b"dex_whirlpool",
+        }
+    }
+
+    /// Seed of the `DexMarkets` registry of the DEX
+    pub fn markets_seed(&self) -> &'static [u8] {
+        match self {
+            Dex::OrcaLegacy => b"dex_orca_legacy_markets",
+            Dex::Whirlpool => b"dex_whirlpool_markets",
+        }
+    }
+}
+
+/// Vaults of a given owner and reserve token mint
+/// Allows clients to look up vaults that were initialized at PDA addresses
+#[assert_size(648)]
+#[account]
+#[repr(C, align(8))]
+#[derive(Debug, Default)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct VaultRegistry {
+    pub reserve_token_mint: Pubkey,
+
+    pub owner: Pubkey,
+
+    pub num_vaults: u64,
+
+    pub vaults: [Pubkey; 16],
+
+    _reserved0: [u64; 8],
+}
+
+impl VaultRegistry {
+    pub fn add_vault(&mut self, vault: Pubkey) -> Result<()> {
+        let index = usize::try_from(self.num_vaults).map_err(|_| ErrorCode::MathError)?;
+        *self
+            .vaults
+            .get_mut(index)
+            .ok_or(ErrorCode::VaultRegistryFull)? = vault;
+        self.num_vaults = self.num_vaults.checked_add(1).ok_or(ErrorCode::MathError)?;
+        Ok(())
+    }
+}
+
+/// Deposits of a single wallet into a vault
+#[assert_size(160)]
+#[account]
+#[repr(C, align(8))]
+#[derive(Debug, Default)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct Depositor {
+    pub vault: Pubkey,
+
+    pub wallet: Pubkey,
+
+    /// Reserve tokens deposited by the wallet, net of the reserve tokens it withdrew
+    pub net_principal: u64,
+
+    /// Overrides the per-user deposit cap of the vault config if not 0
+    pub deposit_cap: u64,
+
+    /// Whether the wallet can deposit into a permissioned vault
+    pub allowed: bool,
+
+    pub bump: u8,
+
+    _reserved0: [u8; 6],
+
+    /// Time at which the locked lp tokens of the wallet unlock
+    pub lockup_end: i64,
+
+    /// Lp tokens minted to the wallet by its deposits, net of the lp tokens it withdrew
+    pub lp_tokens: u64,
+
+    /// Lp tokens of the deposits made during the current lockup period
+    pub locked_lp_tokens: u64,
+
+    _reserved1: [u64; 6],
+}
+
+impl Depositor {
+    /// Per-user deposit cap that applies to this depositor, 0 if there is none
+    pub fn get_deposit_cap(&self, config: &VaultConfig) -> u64 {
+        if self.deposit_cap > 0 {
+            self.deposit_cap
+        } else {
+            config.per_user_deposit_cap
+        }
+    }
+
+    /// Records a deposit, checking it against the allowlist and the per-user cap.
+    /// The minted lp tokens are locked along with the lp tokens that are still locked,
+    /// which restarts their lockup period. Unlocked lp tokens stay unlocked.
+    pub fn record_deposit(
+        &mut self,
+        config: &VaultConfig,
+        amount: u64,
+        lp_token_amount: u64,
+        timestamp: i64,
+    ) -> Result<()> {
+        if config.permissioned && !self.allowed {
+            return Err(ErrorCode::DepositorNotAllowed.into());
+        }
+
+        let net_principal = self
+            .net_principal
+            .checked_add(amount)
+            .ok_or(ErrorCode::OverflowError)?;
+
+        let deposit_cap = self.get_deposit_cap(config);
+        if deposit_cap > 0 && net_principal > deposit_cap {
+            msg!("User deposit cap reached");
+            return Err(ErrorCode::UserDepositCapError.into());
+        }
+
+        self.net_principal = net_principal;
+        self.lp_tokens = self
+            .lp_tokens
+            .checked_add(lp_token_amount)
+            .ok_or(ErrorCode::OverflowError)?;
+        self.locked_lp_tokens = self
+            .locked_lp_tokens_at(timestamp)
+            .checked_add(lp_token_amount)
+            .ok_or(ErrorCode::OverflowError)?;
+        self.lockup_end = timestamp.saturating_add(config.lockup_seconds as i64);
+        Ok(())
+    }
+
+    fn locked_lp_tokens_at(&self, timestamp: i64) -> u64 {
+        if timestamp < self.lockup_end {
+            self.locked_lp_tokens
+        } else {
+            0
+        }
+    }
+
+    fn unlocked_lp_tokens_at(&self, timestamp: i64) -> u64 {
+        self.lp_tokens
+            .saturating_sub(self.locked_lp_tokens_at(timestamp))
+    }
+
+    /// Fee charged on a withdrawal of lp tokens worth the given reserve tokens.
+    /// The fee applies to the withdrawn lp tokens above the unlocked lp tokens of the wallet,
+    /// which are either locked or were not minted by its own deposits, e.g. received in a transfer.
+    pub fn early_withdraw_fee(
+        &self,
+        config: &VaultConfig,
+        lp_token_amount: u64,
+        reserve_token_amount: u64,
+        timestamp: i64,
+    ) -> u64 {
+        if !config.has_lockup() || lp_token_amount == 0 {
+            return 0;
+        }
+
+        let charged_lp_tokens =
+            lp_token_amount.saturating_sub(self.unlocked_lp_tokens_at(timestamp));
+        let charged_reserve_tokens =
+            reserve_token_amount as u128 * charged_lp_tokens as u128 / lp_token_amount as u128;
+        (charged_reserve_tokens * config.early_withdraw_fee_bps as u128 / 10000) as u64
+    }
+
+    /// Records a withdrawal, which uses up the unlocked lp tokens first.
+    /// Withdrawals above the principal, such as earned interest, bring the principal down to 0.
+    pub fn record_withdraw(
+        &mut self,
+        lp_token_amount: u64,
+        reserve_token_amount: u64,
+        timestamp: i64,
+    ) {
+        let withdrawn_locked_lp_tokens =
+            lp_token_amount.saturating_sub(self.unlocked_lp_tokens_at(timestamp));
+        self.locked_lp_tokens = self
+            .locked_lp_tokens_at(timestamp)
+            .saturating_sub(withdrawn_locked_lp_tokens);
+        self.lp_tokens = self.lp_tokens.saturating_sub(lp_token_amount);
+        self.net_principal = self.net_principal.saturating_sub(reserve_token_amount);
+    }
+}
+
 #[assert_size(672)]
 #[account]
 #[repr(C, align(8))]
@@ -241,10 +684,82 @@ pub struct DexStates {
 pub struct OrcaLegacyAccounts {
     pub orca_swap_program: Pubkey,
 
+    /// Legacy registry of the markets that rewards were sold through, by market id.
+    /// Markets are now registered in `DexMarkets`, see `migrate_orca_legacy_markets`.
     pub orca_markets: [Pubkey; 20],
 }
 
-#[assert_size(aligns, 32)]
+/// Orca Whirlpools program that the vault can swap through
+#[assert_size(672)]
+#[account]
+#[repr(C, align(8))]
+#[derive(Debug, Default)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct WhirlpoolAccounts {
+    pub whirlpool_program: Pubkey,
+
+    _reserved0: [Pubkey; 20],
+}
+
+/// A market that the vault can swap through, checked against the market account when registered
+#[assert_size(aligns, 128)]
+#[repr(C, align(8))]
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct DexMarket {
+    /// Orca legacy swap state or whirlpool
+    pub market: Pubkey,
+
+    /// Program that owns the market
+    pub swap_program: Pubkey,
+
+    /// Mint of the token that the vault sells through the market
+    pub input_mint: Pubkey,
+
+    /// Mint of the token that the vault buys through the market
+    pub output_mint: Pubkey,
+}
+
+impl DexMarket {
+    pub fn is_registered(&self) -> bool {
+        self.market != Pubkey::default()
+    }
+}
+
+/// Markets of a DEX that the vault can swap through, indexed by market id
+#[assert_size(2560)]
+#[account]
+#[repr(C, align(8))]
+#[derive(Debug, Default)]
+#[cfg_attr(test, derive(TypeLayout))]
+pub struct DexMarkets {
+    pub markets: [DexMarket; 20],
+}
+
+impl DexMarkets {
+    /// Registered market with the given id
+    pub fn get_market(&self, market_id: u8) -> Result<&DexMarket> {
+        let market = self
+            .markets
+            .get(market_id as usize)
+            .ok_or(ErrorCode::InvalidArgument)?;
+        if !market.is_registered() {
+            return Err(ErrorCode::DexMarketNotRegistered.into());
+        }
+        Ok(market)
+    }
+}
+
+/// Swap slippage of vaults that were initialized before it was configurable
+pub const DEFAULT_SWAP_SLIPPAGE_BPS: u16 = 100;
+
+/// Oracle confidence limit of vaults that were initialized before it was configurable
+pub const DEFAULT_ORACLE_MAX_CONFIDENCE_BPS: u16 = 200;
+
+/// Oracle price age limit of vaults that were initialized before it was configurable
+pub const DEFAULT_ORACLE_MAX_AGE: u64 = 60;
+
+#[assert_size(aligns, 256)]
 #[repr(C, align(8))]
 #[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug)]
 #[cfg_attr(test, derive(TypeLayout))]
@@ -270,11 +785,95 @@ pub struct VaultConfig {
     /// Strategy type that is executed during rebalance
     pub strategy_type: StrategyType,
 
-    // 4 * 3 = 12
-    _padding: [u32; 3],
+    /// Basis points of a yield source's value that, when lost in a single refresh,
+    /// halts the vault. 0 disables halting on losses.
+    pub loss_halt_bps: u16,
+
+    /// Percentage of the collected fees that is set aside in the insurance reserve
+    pub insurance_fee_pct: u8,
+
+    _padding0: u8,
+
+    /// Size of the insurance reserve above which the owner can withdraw the surplus
+    pub insurance_target: u64,
+
+    /// Percentage of the vault value to keep idle in the vault for withdrawals
+    pub idle_buffer_pct: u8,
+
+    /// Optional band around the idle buffer target, disabled when the max is 0.
+    /// Idle reserves within the band are left as they are by rebalances,
+    /// and reconciles never deposit below the min.
+    pub idle_buffer_min_pct: u8,
+    pub idle_buffer_max_pct: u8,
+
+    /// Whether only depositors added by the owner can deposit
+    pub permissioned: bool,
+
+    /// Min move of a reconcile as basis points of the target allocation, see `min_reconcile_move`
+    pub min_reconcile_move_bps: u16,
+
+    /// Basis points of the withdrawn reserve tokens that are left in the vault
+    /// when a depositor withdraws during its lockup period
+    pub early_withdraw_fee_bps: u16,
+
+    /// Largest deposit that is deployed to a yield source right away
+    /// when the deposit is given the adapter accounts. 0 disables auto-deploy.
+    pub auto_deploy_max: u64,
+
+    /// Max reserve tokens moved in or out of a yield source by a single reconcile.
+    /// The rest of the move stays pending in the target allocations. 0 disables the limit.
+    pub max_reconcile_move: u64,
+
+    /// Min reserve tokens moved by a reconcile. Smaller moves, or moves under
+    /// `min_reconcile_move_bps` of the target allocation, are skipped.
+    pub min_reconcile_move: u64,
+
+    /// Max principal, in reserve tokens, that a single wallet can have deposited.
+    /// Can be overridden per depositor. 0 disables the limit.
+    pub per_user_deposit_cap: u64,
+
+    /// Seconds after a deposit during which its lp tokens are locked. Withdrawals of locked lp tokens,
+    /// and of lp tokens that the wallet did not mint itself, are charged the early withdrawal fee.
+    /// 0 disables the lockup.
+    pub lockup_seconds: u64,
+
+    /// Max basis points that a reward swap can return below the oracle value of the rewards.
+    /// Cannot be 0, since swaps return less than the oracle value once DEX fees apply.
+    pub swap_slippage_bps: u16,
+
+    /// Max confidence interval of an oracle price, as basis points of the price. Cannot be 0.
+    pub oracle_max_confidence_bps: u16,
+
+    /// Whether consolidations count the unclaimed and unsold Port rewards in the vault value,
+    /// at their oracle value less the confidence interval and the haircut
+    pub value_rewards: bool,
+
+    _padding4: u8,
+
+    /// Discount applied to the oracle value of rewards counted in the vault value, in basis points
+    pub reward_value_haircut_bps: u16,
+
+    /// Max seconds since an oracle price was published. Cannot be 0.
+    pub oracle_max_age: u64,
+
+    /// Min seconds between two harvests. 0 disables the limit.
+    pub min_harvest_interval: u64,
+
+    // 8 * 19 = 152
+    _padding1: [u64; 19],
 }
 
 impl VaultConfig {
+    /// Whether deposits need an initialized `Depositor` account
+    pub fn requires_depositor(&self) -> bool {
+        self.permissioned || self.per_user_deposit_cap > 0 || self.has_lockup()
+    }
+
+    /// Whether withdrawals can be charged the early withdrawal fee
+    pub fn has_lockup(&self) -> bool {
+        self.lockup_seconds > 0 && self.early_withdraw_fee_bps > 0
+    }
+
     pub fn new(config: VaultConfigArg) -> Result<Self> {
         // Fee cannot be over 100%
         if config.fee_carry_bps > 10000 {
@@ -297,6 +896,54 @@ impl VaultConfig {
             return Err(ErrorCode::InvalidAllocationCap.into());
         }
 
+        // Loss threshold cannot be over 100%
+        if config.loss_halt_bps > 10000 {
+            return Err(ErrorCode::InvalidArgument.into());
+        }
+
+        // Insurance share cannot be over 100%
+        if config.insurance_fee_pct > 100 {
+            return Err(ErrorCode::InvalidFeeConfig.into());
+        }
+
+        // Early withdrawal fee cannot be over 100%
+        if config.early_withdraw_fee_bps > 10000 {
+            return Err(ErrorCode::InvalidFeeConfig.into());
+        }
+
+        // Reward swaps cannot return the full oracle value once DEX fees apply
+        if config.swap_slippage_bps == 0 {
+            return Err(ErrorCode::InvalidArgument.into());
+        }
+
+        // Oracle prices always have to be checked for staleness and confidence
+        if config.oracle_max_confidence_bps == 0 || config.oracle_max_age == 0 {
+            return Err(ErrorCode::InvalidArgument.into());
+        }
+
+        // Slippage, oracle confidence and reward haircut cannot be over 100%
+        if config.swap_slippage_bps > 10000
+            || config.oracle_max_confidence_bps > 10000
+            || config.reward_value_haircut_bps > 10000
+        {
+            return Err(ErrorCode::InvalidArgument.into());
+        }
+
+        // Dust threshold cannot be over 100%
+        if config.min_reconcile_move_bps > 10000 {
+            return Err(ErrorCode::InvalidArgument.into());
+        }
+
+        // Idle buffer band has to contain the target, and cannot be over 100%
+        if config.idle_buffer_pct > 100
+            || (config.idle_buffer_max_pct > 0
+                && !(config.idle_buffer_min_pct <= config.idle_buffer_pct
+                    && config.idle_buffer_pct <= config.idle_buffer_max_pct
+                    && config.idle_buffer_max_pct <= 100))
+        {
+            return Err(ErrorCode::InvalidIdleBufferConfig.into());
+        }
+
         Ok(Self {
             deposit_cap: config.deposit_cap,
             fee_carry_bps: config.fee_carry_bps,
@@ -305,7 +952,29 @@ impl VaultConfig {
             allocation_cap_pct: config.allocation_cap_pct,
             rebalance_mode: config.rebalance_mode,
             strategy_type: config.strategy_type,
-            _padding: [0; 3],
+            loss_halt_bps: config.loss_halt_bps,
+            insurance_fee_pct: config.insurance_fee_pct,
+            _padding0: 0,
+            insurance_target: config.insurance_target,
+            idle_buffer_pct: config.idle_buffer_pct,
+            idle_buffer_min_pct: config.idle_buffer_min_pct,
+            idle_buffer_max_pct: config.idle_buffer_max_pct,
+            permissioned: config.permissioned,
+            min_reconcile_move_bps: config.min_reconcile_move_bps,
+            early_withdraw_fee_bps: config.early_withdraw_fee_bps,
+            auto_deploy_max: config.auto_deploy_max,
+            max_reconcile_move: config.max_reconcile_move,
+            min_reconcile_move: config.min_reconcile_move,
+            per_user_deposit_cap: config.per_user_deposit_cap,
+            lockup_seconds: config.lockup_seconds,
+            swap_slippage_bps: config.swap_slippage_bps,
+            oracle_max_confidence_bps: config.oracle_max_confidence_bps,
+            value_rewards: config.value_rewards,
+            _padding4: 0,
+            reward_value_haircut_bps: config.reward_value_haircut_bps,
+            oracle_max_age: config.oracle_max_age,
+            min_harvest_interval: config.min_harvest_interval,
+            _padding1: [0; 19],
         })
     }
 }
@@ -332,14 +1001,55 @@ bitflags::bitflags! {
         /// Disable refreshes
         const HALT_REFRESHES = 1 << 1;
 
+        /// Legacy flag that disables deposits + withdrawals
+        /// Migrated to HALT_DEPOSITS | HALT_WITHDRAWS, see `VaultFlags::migrate`
+        const HALT_DEPOSITS_WITHDRAWS_LEGACY = 1 << 2;
+
+        /// Disable deposits
+        const HALT_DEPOSITS = 1 << 3;
+
+        /// Disable withdrawals
+        const HALT_WITHDRAWS = 1 << 4;
+
+        /// Disable rebalances
+        const HALT_REBALANCES = 1 << 5;
+
+        /// Disable claiming of yield source rewards
+        const HALT_REWARD_CLAIMS = 1 << 6;
+
+        /// Disable DEX swaps (e.g. selling rewards)
+        const HALT_SWAPS = 1 << 7;
+
         /// Disable deposits + withdrawals
-        const HALT_DEPOSITS_WITHDRAWS = 1 << 2;
+        const HALT_DEPOSITS_WITHDRAWS = Self::HALT_DEPOSITS.bits
+                                      | Self::HALT_WITHDRAWS.bits;
+
+        /// Operations disabled when a yield source realizes a loss above the configured threshold
+        const HALT_ON_LOSS = Self::HALT_RECONCILES.bits
+                           | Self::HALT_DEPOSITS.bits
+                           | Self::HALT_WITHDRAWS.bits
+                           | Self::HALT_REBALANCES.bits;
 
         /// Disable all operations
         const HALT_ALL = Self::HALT_RECONCILES.bits
                        | Self::HALT_REFRESHES.bits
-                       | Self::HALT_DEPOSITS_WITHDRAWS.bits;
+                       | Self::HALT_DEPOSITS.bits
+                       | Self::HALT_WITHDRAWS.bits
+                       | Self::HALT_REBALANCES.bits
+                       | Self::HALT_REWARD_CLAIMS.bits
+                       | Self::HALT_SWAPS.bits;
+
+    }
+}
 
+impl VaultFlags {
+    /// Replaces the legacy combined deposit/withdraw flag with the granular flags
+    pub fn migrate(mut self) -> Self {
+        if self.contains(VaultFlags::HALT_DEPOSITS_WITHDRAWS_LEGACY) {
+            self.remove(VaultFlags::HALT_DEPOSITS_WITHDRAWS_LEGACY);
+            self.insert(VaultFlags::HALT_DEPOSITS_WITHDRAWS);
+        }
+        self
     }
 }
 
@@ -350,6 +1060,28 @@ bitflags::bitflags! {
     }
 }
 
+impl From<Provider> for YieldSourceFlags {
+    fn from(provider: Provider) -> Self {
+        match provider {
+            Provider::Solend => YieldSourceFlags::SOLEND,
+            Provider::Port => YieldSourceFlags::PORT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YieldSourceState {
+    /// Ignored by all operations
+    Disabled,
+
+    /// Given zero weight by the strategy and can only be redeemed from by reconciles.
+    /// Still counted in the vault value by refreshes.
+    WithdrawOnly,
+
+    /// Fully participates in the strategy
+    Active,
+}
+
 #[assert_size(aligns, 72)]
 #[repr(C, align(8))]
 #[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug, Default)]
@@ -374,16 +1106,48 @@ impl Allocations {
         let mut retval = AssetContainer::<u64>::default();
         Provider::iter().for_each(|p| {
             retval[p] = flags
-                .contains(match p {
-                    Provider::Solend => YieldSourceFlags::SOLEND,
-                    Provider::Port => YieldSourceFlags::PORT,
-                })
+                .contains(YieldSourceFlags::from(p))
                 .then(|| self[p].value);
         });
         retval
     }
 }
 
+/// Weights of the yield sources in basis points of the allocatable value
+#[assert_size(aligns, 24)]
+#[repr(C, align(8))]
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug, Default)]
+pub struct StrategyWeights {
+    pub solend: u16,
+    pub port: u16,
+    _filler: [u8; 4],
+    pub last_update: LastUpdate,
+}
+impl_provider_index!(StrategyWeights, u16);
+
+impl StrategyWeights {
+    pub fn from_allocations(allocations: &Allocations, allocatable_value: u64, slot: u64) -> Self {
+        let mut weights = Provider::iter().fold(Self::default(), |mut acc, p| {
+            if allocatable_value > 0 {
+                // Cannot overflow since allocations add up to at most the allocatable value
+                acc[p] = (allocations[p].value as u128 * 10000 / allocatable_value as u128) as u16;
+            }
+            acc
+        });
+        weights.last_update.update_slot(slot);
+        weights
+    }
+}
+
+#[assert_size(aligns, 16)]
+#[repr(C, align(8))]
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Debug, Default)]
+pub struct RealizedLosses {
+    pub solend: u64,
+    pub port: u64,
+}
+impl_provider_index!(RealizedLosses, u64);
+
 // This should be a generic, but anchor doesn't support that yet
 // https://github.com/project-serum/anchor/issues/1849
 #[repr(C, align(8))]
@@ -471,6 +1235,306 @@ impl PartialOrd for LastUpdate {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_migrate_halt_flags() {
+        assert_eq!(
+            VaultFlags::HALT_DEPOSITS_WITHDRAWS_LEGACY.migrate(),
+            VaultFlags::HALT_DEPOSITS_WITHDRAWS
+        );
+        assert_eq!(
+            (VaultFlags::HALT_RECONCILES | VaultFlags::HALT_DEPOSITS_WITHDRAWS_LEGACY).migrate(),
+            VaultFlags::HALT_RECONCILES | VaultFlags::HALT_DEPOSITS | VaultFlags::HALT_WITHDRAWS
+        );
+        assert_eq!(
+            VaultFlags::HALT_DEPOSITS.migrate(),
+            VaultFlags::HALT_DEPOSITS
+        );
+        assert_eq!(VaultFlags::HALT_ALL.migrate(), VaultFlags::HALT_ALL);
+    }
+
+    fn default_config_arg() -> VaultConfigArg {
+        VaultConfigArg {
+            deposit_cap: u64::MAX,
+            fee_carry_bps: 0,
+            fee_mgmt_bps: 0,
+            referral_fee_pct: 0,
+            allocation_cap_pct: 100,
+            rebalance_mode: RebalanceMode::Calculator,
+            strategy_type: StrategyType::MaxYield,
+            loss_halt_bps: 0,
+            insurance_fee_pct: 0,
+            insurance_target: 0,
+            idle_buffer_pct: 0,
+            idle_buffer_min_pct: 0,
+            idle_buffer_max_pct: 0,
+            auto_deploy_max: 0,
+            max_reconcile_move: 0,
+            min_reconcile_move: 0,
+            min_reconcile_move_bps: 0,
+            permissioned: false,
+            per_user_deposit_cap: 0,
+            lockup_seconds: 0,
+            early_withdraw_fee_bps: 0,
+            swap_slippage_bps: 100,
+            oracle_max_confidence_bps: 200,
+            oracle_max_age: 60,
+            min_harvest_interval: 0,
+            value_rewards: false,
+            reward_value_haircut_bps: 0,
+        }
+    }
+
+    #[test]
+    fn test_idle_buffer_config() {
+        let config_arg = |pct, min_pct, max_pct| VaultConfigArg {
+            idle_buffer_pct: pct,
+            idle_buffer_min_pct: min_pct,
+            idle_buffer_max_pct: max_pct,
+            ..default_config_arg()
+        };
+
+        assert!(VaultConfig::new(config_arg(0, 0, 0)).is_ok());
+        assert!(VaultConfig::new(config_arg(10, 0, 0)).is_ok());
+        assert!(VaultConfig::new(config_arg(10, 5, 15)).is_ok());
+        assert!(VaultConfig::new(config_arg(101, 0, 0)).is_err());
+        assert!(VaultConfig::new(config_arg(10, 11, 15)).is_err());
+        assert!(VaultConfig::new(config_arg(10, 5, 9)).is_err());
+        assert!(VaultConfig::new(config_arg(100, 50, 101)).is_err());
+    }
+
+    #[test]
+    fn test_swap_slippage_config() {
+        let config_arg = |swap_slippage_bps| VaultConfigArg {
+            swap_slippage_bps,
+            ..default_config_arg()
+        };
+
+        assert!(VaultConfig::new(config_arg(0)).is_err());
+        assert!(VaultConfig::new(config_arg(1)).is_ok());
+        assert!(VaultConfig::new(config_arg(10000)).is_ok());
+        assert!(VaultConfig::new(config_arg(10001)).is_err());
+    }
+
+    #[test]
+    fn test_oracle_config() {
+        let config_arg = |oracle_max_confidence_bps, oracle_max_age| VaultConfigArg {
+            oracle_max_confidence_bps,
+            oracle_max_age,
+            ..default_config_arg()
+        };
+
+        assert!(VaultConfig::new(config_arg(0, 60)).is_err());
+        assert!(VaultConfig::new(config_arg(200, 0)).is_err());
+        assert!(VaultConfig::new(config_arg(1, 1)).is_ok());
+        assert!(VaultConfig::new(config_arg(10000, 60)).is_ok());
+        assert!(VaultConfig::new(config_arg(10001, 60)).is_err());
+    }
+
+    #[test]
+    fn test_depositor_deposit_cap() {
+        let mut arg = default_config_arg();
+        arg.per_user_deposit_cap = 100;
+        let mut config = VaultConfig::new(arg).unwrap();
+        let mut depositor = Depositor::default();
+
+        assert!(depositor.record_deposit(&config, 60, 60, 0).is_ok());
+        assert!(depositor.record_deposit(&config, 41, 41, 0).is_err());
+        assert_eq!(depositor.net_principal, 60);
+
+        depositor.record_withdraw(60, 70, 0);
+        assert_eq!(depositor.net_principal, 0);
+
+        depositor.deposit_cap = 200;
+        assert!(depositor.record_deposit(&config, 150, 150, 0).is_ok());
+
+        config.permissioned = true;
+        assert!(depositor.record_deposit(&config, 1, 1, 0).is_err());
+        depositor.allowed = true;
+        assert!(depositor.record_deposit(&config, 1, 1, 0).is_ok());
+        assert_eq!(depositor.net_principal, 151);
+    }
+
+    #[test]
+    fn test_depositor_early_withdraw_fee() {
+        let mut arg = default_config_arg();
+        arg.lockup_seconds = 100;
+        arg.early_withdraw_fee_bps = 50;
+        let config = VaultConfig::new(arg).unwrap();
+        let mut depositor = Depositor::default();
+
+        depositor.record_deposit(&config, 1000, 1000, 1000).unwrap();
+        assert_eq!(depositor.early_withdraw_fee(&config, 1000, 10000, 1099), 50);
+        assert_eq!(depositor.early_withdraw_fee(&config, 1000, 10000, 1100), 0);
+
+        // A top-up locks its own lp tokens, not the ones that are already unlocked
+        depositor.record_deposit(&config, 1000, 1000, 1150).unwrap();
+        assert_eq!(depositor.early_withdraw_fee(&config, 1000, 10000, 1200), 0);
+        assert_eq!(depositor.early_withdraw_fee(&config, 2000, 20000, 1200), 50);
+        assert_eq!(depositor.early_withdraw_fee(&config, 2000, 20000, 1250), 0);
+
+        // Unlocked lp tokens are withdrawn first
+        depositor.record_withdraw(1500, 15000, 1200);
+        assert_eq!(depositor.lp_tokens, 500);
+        assert_eq!(depositor.locked_lp_tokens, 500);
+        assert_eq!(depositor.early_withdraw_fee(&config, 500, 5000, 1200), 25);
+    }
+
+    #[test]
+    fn test_depositor_early_withdraw_fee_after_transfer() {
+        let mut arg = default_config_arg();
+        arg.lockup_seconds = 100;
+        arg.early_withdraw_fee_bps = 50;
+        let config = VaultConfig::new(arg).unwrap();
+
+        // Lp tokens received in a transfer were not minted to the receiver,
+        // so they are charged the fee whatever the lockup of the sender
+        let receiver = Depositor::default();
+        assert_eq!(receiver.early_withdraw_fee(&config, 1000, 10000, 1050), 50);
+        assert_eq!(receiver.early_withdraw_fee(&config, 1000, 10000, 1100), 50);
+
+        // Only the lp tokens minted by the receiver's own deposits are exempt
+        let mut receiver = Depositor::default();
+        receiver.record_deposit(&config, 500, 500, 0).unwrap();
+        assert_eq!(receiver.early_withdraw_fee(&config, 1500, 15000, 1050), 50);
+    }
+
+    fn zeroed_vault() -> Vault {
+        Vault::deserialize(&mut &[0_u8; 992][..]).unwrap()
+    }
+
+    #[test]
+    fn test_set_withdraw_only_flags() {
+        let mut vault = zeroed_vault();
+        vault
+            .set_yield_source_flags(YieldSourceFlags::SOLEND.bits())
+            .unwrap();
+
+        assert!(vault
+            .set_withdraw_only_flags(YieldSourceFlags::PORT.bits())
+            .is_err());
+        assert!(vault
+            .set_withdraw_only_flags(YieldSourceFlags::SOLEND.bits())
+            .is_ok());
+        assert_eq!(
+            vault.get_yield_source_state(Provider::Solend),
+            YieldSourceState::WithdrawOnly
+        );
+        assert_eq!(
+            vault.get_yield_source_state(Provider::Port),
+            YieldSourceState::Disabled
+        );
+    }
+
+    #[test]
+    fn test_adjust_allocation_cap() {
+        let mut vault = zeroed_vault();
+        vault.config.allocation_cap_pct = 40;
+        vault
+            .set_yield_source_flags(YieldSourceFlags::all().bits())
+            .unwrap();
+
+        vault.adjust_allocation_cap().unwrap();
+        assert_eq!(vault.config.allocation_cap_pct, 51);
+
+        vault
+            .set_withdraw_only_flags(YieldSourceFlags::PORT.bits())
+            .unwrap();
+        vault.adjust_allocation_cap().unwrap();
+        assert_eq!(vault.config.allocation_cap_pct, 100);
+
+        // No active yield sources leaves the cap unchanged
+        vault.config.allocation_cap_pct = 60;
+        vault
+            .set_withdraw_only_flags(YieldSourceFlags::all().bits())
+            .unwrap();
+        vault.adjust_allocation_cap().unwrap();
+        assert_eq!(vault.config.allocation_cap_pct, 60);
+    }
+
+    #[test]
+    fn test_record_refresh_from() {
+        let mut vault = zeroed_vault();
+        vault.config.loss_halt_bps = 500;
+        vault.actual_allocations.solend.update(1000, 10);
+        vault.actual_allocations.port.update(2000, 10);
+
+        // Each yield source is refreshed on its own copy of the vault,
+        // deserialized before any of the refreshes are recorded
+        let mut solend_copy = vault.clone();
+        solend_copy
+            .record_refreshed_value(Provider::Solend, 900, 11)
+            .unwrap();
+        let mut port_copy = vault.clone();
+        port_copy
+            .record_refreshed_value(Provider::Port, 1950, 11)
+            .unwrap();
+
+        assert_eq!(
+            vault.record_refresh_from(&solend_copy, Provider::Solend),
+            Ok(100)
+        );
+        assert_eq!(
+            vault.record_refresh_from(&port_copy, Provider::Port),
+            Ok(50)
+        );
+
+        assert_eq!(vault.actual_allocations.solend.value, 900);
+        assert_eq!(vault.actual_allocations.port.value, 1950);
+        assert_eq!(vault.actual_allocations.port.last_update.slot, 11);
+        assert_eq!(vault.realized_losses.solend, 100);
+        assert_eq!(vault.realized_losses.port, 50);
+        assert_eq!(vault.uncovered_losses, 150);
+        assert!(vault.get_halt_flags().contains(VaultFlags::HALT_ON_LOSS));
+    }
+
+    #[test]
+    fn test_most_underweight() {
+        let mut vault = zeroed_vault();
+        vault
+            .set_yield_source_flags(YieldSourceFlags::all().bits())
+            .unwrap();
+        vault.value.update(1100, 10);
+        vault.actual_allocations.solend.update(500, 10);
+        vault.actual_allocations.port.update(500, 10);
+
+        let mut allocations = Allocations::default();
+        allocations.solend.update(400, 5);
+        allocations.port.update(600, 5);
+        vault.strategy_weights = StrategyWeights::from_allocations(&allocations, 1000, 5);
+        assert_eq!(vault.strategy_weights.solend, 4000);
+        assert_eq!(vault.strategy_weights.port, 6000);
+
+        // Targets are reset once reconciles reach them, the weights still apply
+        vault.target_allocations = Allocations::default();
+        assert_eq!(vault.most_underweight(10), Ok(Some((Provider::Port, 160))));
+        assert_eq!(
+            vault.most_underweight(5 + MAX_SLOTS_SINCE_REBALANCE),
+            Ok(Some((Provider::Port, 160)))
+        );
+
+        // Weights of an old rebalance are not used
+        assert_eq!(
+            vault.most_underweight(6 + MAX_SLOTS_SINCE_REBALANCE),
+            Ok(None)
+        );
+
+        // Withdraw-only yield sources are not deployed to
+        vault
+            .set_withdraw_only_flags(YieldSourceFlags::PORT.bits())
+            .unwrap();
+        assert_eq!(vault.most_underweight(10), Ok(None));
+    }
+
+    #[test]
+    fn test_dex_markets_get_market() {
+        let mut dex_markets = DexMarkets::default();
+        dex_markets.markets[19].market = Pubkey::new_unique();
+
+        assert!(dex_markets.get_market(0).is_err());
+        assert!(dex_markets.get_market(19).is_ok());
+        assert!(dex_markets.get_market(20).is_err());
+    }
+
     #[test]
     fn print_vault_layout() {
         println!("{}", Vault::type_layout());
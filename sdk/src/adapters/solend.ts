@@ -21,6 +21,7 @@ import * as anchor from "@castlefinance/anchor";
 
 import {
     LENDING_MARKET_SIZE,
+    parseObligation,
     SolendReserve,
     SolendMarket,
 } from "@solendprotocol/solend-sdk";
@@ -41,6 +42,13 @@ export interface SolendAccounts {
     switchboardFeed: PublicKey;
     collateralMint: PublicKey;
     liquiditySupply: PublicKey;
+    collateralSupply: PublicKey;
+    // Accounts used to earn Solend liquidity mining rewards
+    rewardTokenMint?: PublicKey;
+    rewardTokenOracle?: PublicKey;
+    rewardDistributor?: PublicKey;
+    // Obligation the vault deposits its lp tokens into, if it is enabled
+    vaultSolendObligation?: PublicKey;
 }
 
 export class SolendReserveAsset extends LendingMarket {
@@ -90,6 +98,9 @@ export class SolendReserveAsset extends LendingMarket {
             ),
             collateralMint: new PublicKey(reserve.config.collateralMintAddress),
             liquiditySupply: new PublicKey(reserve.config.liquidityAddress),
+            collateralSupply: new PublicKey(
+                reserve.config.collateralSupplyAddress
+            ),
         };
 
         const lpToken = await getToken(
@@ -157,7 +168,7 @@ export class SolendReserveAsset extends LendingMarket {
                 liquidityFeeReceiverAddress: "",
                 symbol: "",
                 address: accounts.reserve.toBase58(),
-                collateralSupplyAddress: "",
+                collateralSupplyAddress: accounts.collateralSupply.toBase58(),
             },
             await SolendMarket.initialize(provider.connection, "devnet"),
             provider.connection
@@ -195,11 +206,30 @@ export class SolendReserveAsset extends LendingMarket {
             TOKEN_PROGRAM_ID,
             Keypair.generate() // dummy signer since we aren't making any txs
         );
-        const lpTokenAmount = new Big(
+        let lpTokenAmount = new Big(
             (
                 await lpToken.getAccountInfo(vaultState.vaultSolendLpToken)
             ).amount.toString()
         );
+
+        // Lp tokens deposited into the vault obligation count as held
+        if (vaultState.solendObligationEnabled) {
+            const obligation = parseObligation(
+                this.accounts.vaultSolendObligation,
+                await this.provider.connection.getAccountInfo(
+                    this.accounts.vaultSolendObligation
+                )
+            );
+            const collateral = obligation.info.deposits.find((deposit) =>
+                deposit.depositReserve.equals(this.accounts.reserve)
+            );
+            if (collateral != null) {
+                lpTokenAmount = lpTokenAmount.add(
+                    collateral.depositedAmount.toString()
+                );
+            }
+        }
+
         const exchangeRate = new Big(this.reserve.stats.cTokenExchangeRate);
 
         return TokenAmount.fromToken(
@@ -246,6 +276,30 @@ export class SolendReserveAsset extends LendingMarket {
         );
     }
 
+    async getVaultObligation(
+        program: anchor.Program<CastleVault>,
+        vaultId: PublicKey
+    ): Promise<PublicKey> {
+        const [vaultSolendObligation] = await PublicKey.findProgramAddress(
+            [
+                vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_obligation"),
+            ],
+            program.programId
+        );
+        return vaultSolendObligation;
+    }
+
+    async loadAdditionalAccounts(
+        program: anchor.Program<CastleVault>,
+        vaultId: PublicKey
+    ) {
+        this.accounts.vaultSolendObligation = await this.getVaultObligation(
+            program,
+            vaultId
+        );
+    }
+
     async getRefreshIx(
         program: anchor.Program<CastleVault>,
         vaultId: PublicKey,
@@ -262,6 +316,20 @@ export class SolendReserveAsset extends LendingMarket {
                 solendSwitchboard: this.accounts.switchboardFeed,
                 clock: SYSVAR_CLOCK_PUBKEY,
             })
+            .remainingAccounts(
+                vaultState.solendObligationEnabled
+                    ? [
+                          {
+                              isSigner: false,
+                              isWritable: false,
+                              pubkey: await this.getVaultObligation(
+                                  program,
+                                  vaultId
+                              ),
+                          },
+                      ]
+                    : []
+            )
             .instruction();
     }
 
@@ -289,6 +357,25 @@ export class SolendReserveAsset extends LendingMarket {
                 clock: SYSVAR_CLOCK_PUBKEY,
                 tokenProgram: TOKEN_PROGRAM_ID,
             })
+            .remainingAccounts(
+                vaultState.solendObligationEnabled
+                    ? [
+                          {
+                              isSigner: false,
+                              isWritable: true,
+                              pubkey: await this.getVaultObligation(
+                                  program,
+                                  vaultId
+                              ),
+                          },
+                          {
+                              isSigner: false,
+                              isWritable: true,
+                              pubkey: this.accounts.collateralSupply,
+                          },
+                      ]
+                    : []
+            )
             .instruction();
     }
 
@@ -532,6 +619,7 @@ export async function addReserve(
         switchboardFeed: switchboardFeed,
         collateralMint: collateralMint.publicKey,
         liquiditySupply: liquiditySupply.publicKey,
+        collateralSupply: collateralSupply.publicKey,
         market: lendingMarket,
         marketAuthority: lendingMarketAuthority,
     };
@@ -1,5 +1,6 @@
 import Big from "big.js";
 import {
+    AccountMeta,
     Connection,
     Keypair,
     PublicKey,
@@ -56,8 +57,56 @@ interface YieldSources {
 interface ExchangeMarkets {
     dexStates?: PublicKey;
     orcaLegacy?: OrcaLegacySwap;
+    whirlpool?: WhirlpoolRegistry;
 }
 
+interface WhirlpoolRegistry {
+    vaultWhirlpoolAccount: PublicKey;
+    vaultWhirlpoolMarkets: PublicKey;
+}
+
+const WHIRLPOOL_PROGRAM_ID = new PublicKey(
+    "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc"
+);
+
+// Merkle distributor that Solend liquidity mining rewards are claimed from
+const SOLEND_REWARD_DISTRIBUTOR_PROGRAM_ID = new PublicKey(
+    "MRKGLMizK9XSTaD1d1jbVkdHZbQVCSnPpYiTw9aKQv8"
+);
+
+// Auto-deploy follows the strategy weights of rebalances at most this old
+const MAX_SLOTS_SINCE_REBALANCE = 216_000;
+
+const DEFAULT_VAULT_CONFIG: VaultConfig = {
+    depositCap: new anchor.BN("18446744073709551615"), // U64::MAX
+    feeCarryBps: 0,
+    feeMgmtBps: 0,
+    referralFeePct: 0,
+    allocationCapPct: 100,
+    rebalanceMode: { calculator: {} },
+    strategyType: { maxYield: {} },
+    lossHaltBps: 0,
+    insuranceFeePct: 0,
+    insuranceTarget: new anchor.BN(0),
+    idleBufferPct: 0,
+    idleBufferMinPct: 0,
+    idleBufferMaxPct: 0,
+    autoDeployMax: new anchor.BN(0),
+    maxReconcileMove: new anchor.BN(0),
+    minReconcileMove: new anchor.BN(0),
+    minReconcileMoveBps: 0,
+    permissioned: false,
+    perUserDepositCap: new anchor.BN(0),
+    lockupSeconds: new anchor.BN(0),
+    earlyWithdrawFeeBps: 0,
+    swapSlippageBps: 100,
+    oracleMaxConfidenceBps: 200,
+    oracleMaxAge: new anchor.BN(60),
+    minHarvestInterval: new anchor.BN(3600),
+    valueRewards: false,
+    rewardValueHaircutBps: 2000,
+};
+
 export class VaultClient {
     private constructor(
         public program: anchor.Program<CastleVault>,
@@ -108,6 +157,13 @@ export class VaultClient {
                 cluster,
                 reserveMint
             );
+
+            if (vaultState.solendObligationEnabled) {
+                await yieldSources.solend.loadAdditionalAccounts(
+                    program,
+                    vaultId
+                );
+            }
         }
         if (vaultState.yieldSourceFlags & YieldSourceFlags.Port) {
             yieldSources.port = await PortReserveAsset.load(
@@ -155,6 +211,53 @@ export class VaultClient {
                         );
                     dex.orcaLegacy.accounts.vaultOrcaLegacyAccount =
                         orcaLegacyAddress;
+
+                    const orcaLegacyMarketsAddress =
+                        await PublicKey.createProgramAddress(
+                            [
+                                vaultId.toBuffer(),
+                                anchor.utils.bytes.utf8.encode(
+                                    "dex_orca_legacy_markets"
+                                ),
+                                new Uint8Array([
+                                    dexStates.orcaLegacyMarketsBump,
+                                ]),
+                            ],
+                            program.programId
+                        );
+                    dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets =
+                        orcaLegacyMarketsAddress;
+
+                    if (dexStates.whirlpoolMarketsBump != 0) {
+                        dex.whirlpool = {
+                            vaultWhirlpoolAccount:
+                                await PublicKey.createProgramAddress(
+                                    [
+                                        vaultId.toBuffer(),
+                                        anchor.utils.bytes.utf8.encode(
+                                            "dex_whirlpool"
+                                        ),
+                                        new Uint8Array([
+                                            dexStates.whirlpoolAccountsBump,
+                                        ]),
+                                    ],
+                                    program.programId
+                                ),
+                            vaultWhirlpoolMarkets:
+                                await PublicKey.createProgramAddress(
+                                    [
+                                        vaultId.toBuffer(),
+                                        anchor.utils.bytes.utf8.encode(
+                                            "dex_whirlpool_markets"
+                                        ),
+                                        new Uint8Array([
+                                            dexStates.whirlpoolMarketsBump,
+                                        ]),
+                                    ],
+                                    program.programId
+                                ),
+                        };
+                    }
                 } catch (error) {
                     console.log("Failed to load Orca DEX market");
                 }
@@ -214,81 +317,137 @@ export class VaultClient {
         }
 
         const vaultId = Keypair.generate();
+        const { authorityBump, accounts } = await this.getInitAccounts(
+            program,
+            vaultId.publicKey,
+            reserveTokenMint,
+            owner,
+            referralFeeOwner
+        );
 
-        const [vaultAuthority, authorityBump] =
-            await PublicKey.findProgramAddress(
-                [
-                    vaultId.publicKey.toBuffer(),
-                    anchor.utils.bytes.utf8.encode("authority"),
-                ],
-                program.programId
-            );
+        const txSig = await program.methods
+            .initialize(
+                // Anchor has a bug that decodes nested types incorrectly
+                // https://github.com/project-serum/anchor/pull/1726
+                //@ts-ignore
+                authorityBump,
+                { ...DEFAULT_VAULT_CONFIG, ...config }
+            )
+            .accounts({
+                ...accounts,
+                payer: wallet.payer.publicKey,
+                owner: owner,
+            })
+            .signers([vaultId, wallet.payer])
+            .preInstructions([
+                await program.account.vault.createInstruction(vaultId),
+            ])
+            .rpc();
 
-        const [vaultReserveTokenAccount] = await PublicKey.findProgramAddress(
-            [vaultId.publicKey.toBuffer(), reserveTokenMint.toBuffer()],
-            program.programId
+        await program.provider.connection.confirmTransaction(
+            txSig,
+            "finalized"
         );
 
-        const [lpTokenMint] = await PublicKey.findProgramAddress(
+        const vaultState = await program.account.vault.fetch(vaultId.publicKey);
+        const [reserveToken, lpToken] = await this.getReserveAndLpTokens(
+            provider.connection,
+            vaultState
+        );
+
+        return new VaultClient(
+            program,
+            vaultId.publicKey,
+            vaultState,
+            {},
+            {},
+            reserveToken,
+            lpToken
+        );
+    }
+
+    /**
+     * Initializes a vault at the PDA derived from the reserve token mint,
+     * the owner and the nonce, and adds it to the vault registry of the owner
+     */
+    static async initializePda(
+        provider: anchor.AnchorProvider,
+        wallet: anchor.Wallet,
+        env: DeploymentEnv,
+        reserveTokenMint: PublicKey,
+        owner: Keypair,
+        referralFeeOwner: PublicKey,
+        nonce: anchor.BN,
+        config: VaultConfig,
+        program?: anchor.Program<CastleVault>
+    ): Promise<VaultClient> {
+        if (program == null) {
+            program = (await anchor.Program.at(
+                PROGRAM_IDS[env],
+                provider
+            )) as anchor.Program<CastleVault>;
+        }
+
+        const [vaultId] = await PublicKey.findProgramAddress(
             [
-                vaultId.publicKey.toBuffer(),
-                anchor.utils.bytes.utf8.encode("lp_mint"),
+                anchor.utils.bytes.utf8.encode("vault"),
+                reserveTokenMint.toBuffer(),
+                owner.publicKey.toBuffer(),
+                nonce.toArrayLike(Buffer, "le", 8),
             ],
             program.programId
         );
-
-        const feeReceiver = await SplToken.getAssociatedTokenAddress(
-            ASSOCIATED_TOKEN_PROGRAM_ID,
-            TOKEN_PROGRAM_ID,
-            lpTokenMint,
-            owner
+        const vaultRegistry = await this.getVaultRegistryAddress(
+            program,
+            reserveTokenMint,
+            owner.publicKey
         );
-
-        const referralFeeReceiver = await SplToken.getAssociatedTokenAddress(
-            ASSOCIATED_TOKEN_PROGRAM_ID,
-            TOKEN_PROGRAM_ID,
-            lpTokenMint,
+        const { authorityBump, accounts } = await this.getInitAccounts(
+            program,
+            vaultId,
+            reserveTokenMint,
+            owner.publicKey,
             referralFeeOwner
         );
 
-        const defaultConfig: VaultConfig = {
-            depositCap: new anchor.BN("18446744073709551615"), // U64::MAX
-            feeCarryBps: 0,
-            feeMgmtBps: 0,
-            referralFeePct: 0,
-            allocationCapPct: 100,
-            rebalanceMode: { calculator: {} },
-            strategyType: { maxYield: {} },
-        };
+        // The registry is created along with the first vault of the owner
+        const preInstructions: TransactionInstruction[] = [];
+        if (
+            (await program.account.vaultRegistry.fetchNullable(
+                vaultRegistry
+            )) == null
+        ) {
+            preInstructions.push(
+                await program.methods
+                    .initializeVaultRegistry()
+                    .accounts({
+                        vaultRegistry: vaultRegistry,
+                        reserveTokenMint: reserveTokenMint,
+                        payer: wallet.payer.publicKey,
+                        owner: owner.publicKey,
+                        systemProgram: SystemProgram.programId,
+                    })
+                    .instruction()
+            );
+        }
 
         const txSig = await program.methods
-            .initialize(
+            .initializePda(
                 // Anchor has a bug that decodes nested types incorrectly
                 // https://github.com/project-serum/anchor/pull/1726
                 //@ts-ignore
                 authorityBump,
-                { ...defaultConfig, ...config }
+                nonce,
+                { ...DEFAULT_VAULT_CONFIG, ...config }
             )
             .accounts({
-                vault: vaultId.publicKey,
-                vaultAuthority: vaultAuthority,
-                lpTokenMint: lpTokenMint,
-                vaultReserveToken: vaultReserveTokenAccount,
-                reserveTokenMint: reserveTokenMint,
-                feeReceiver: feeReceiver,
-                referralFeeReceiver: referralFeeReceiver,
-                referralFeeOwner: referralFeeOwner,
+                ...accounts,
+                vaultRegistry: vaultRegistry,
                 payer: wallet.payer.publicKey,
-                owner: owner,
-                systemProgram: SystemProgram.programId,
-                tokenProgram: TOKEN_PROGRAM_ID,
-                associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
-                rent: SYSVAR_RENT_PUBKEY,
+                owner: owner.publicKey,
             })
-            .signers([vaultId, wallet.payer])
-            .preInstructions([
-                await program.account.vault.createInstruction(vaultId),
-            ])
+            .signers([owner, wallet.payer])
+            .preInstructions(preInstructions)
             .rpc();
 
         await program.provider.connection.confirmTransaction(
@@ -296,7 +455,7 @@ export class VaultClient {
             "finalized"
         );
 
-        const vaultState = await program.account.vault.fetch(vaultId.publicKey);
+        const vaultState = await program.account.vault.fetch(vaultId);
         const [reserveToken, lpToken] = await this.getReserveAndLpTokens(
             provider.connection,
             vaultState
@@ -304,7 +463,7 @@ export class VaultClient {
 
         return new VaultClient(
             program,
-            vaultId.publicKey,
+            vaultId,
             vaultState,
             {},
             {},
@@ -313,6 +472,105 @@ export class VaultClient {
         );
     }
 
+    /**
+     * @returns PDA vaults of the owner for the reserve token,
+     *          in the order they were initialized
+     */
+    static async getRegisteredVaults(
+        program: anchor.Program<CastleVault>,
+        reserveTokenMint: PublicKey,
+        owner: PublicKey
+    ): Promise<PublicKey[]> {
+        const vaultRegistry = await program.account.vaultRegistry.fetchNullable(
+            await this.getVaultRegistryAddress(program, reserveTokenMint, owner)
+        );
+        if (vaultRegistry == null) {
+            return [];
+        }
+        return vaultRegistry.vaults.slice(
+            0,
+            vaultRegistry.numVaults.toNumber()
+        );
+    }
+
+    private static async getVaultRegistryAddress(
+        program: anchor.Program<CastleVault>,
+        reserveTokenMint: PublicKey,
+        owner: PublicKey
+    ): Promise<PublicKey> {
+        const [vaultRegistry] = await PublicKey.findProgramAddress(
+            [
+                anchor.utils.bytes.utf8.encode("vault_registry"),
+                reserveTokenMint.toBuffer(),
+                owner.toBuffer(),
+            ],
+            program.programId
+        );
+        return vaultRegistry;
+    }
+
+    /**
+     * @returns Accounts of the vault initialization derived from its address
+     */
+    private static async getInitAccounts(
+        program: anchor.Program<CastleVault>,
+        vaultId: PublicKey,
+        reserveTokenMint: PublicKey,
+        owner: PublicKey,
+        referralFeeOwner: PublicKey
+    ) {
+        const [vaultAuthority, authorityBump] =
+            await PublicKey.findProgramAddress(
+                [
+                    vaultId.toBuffer(),
+                    anchor.utils.bytes.utf8.encode("authority"),
+                ],
+                program.programId
+            );
+
+        const [vaultReserveTokenAccount] = await PublicKey.findProgramAddress(
+            [vaultId.toBuffer(), reserveTokenMint.toBuffer()],
+            program.programId
+        );
+
+        const [lpTokenMint] = await PublicKey.findProgramAddress(
+            [vaultId.toBuffer(), anchor.utils.bytes.utf8.encode("lp_mint")],
+            program.programId
+        );
+
+        const feeReceiver = await SplToken.getAssociatedTokenAddress(
+            ASSOCIATED_TOKEN_PROGRAM_ID,
+            TOKEN_PROGRAM_ID,
+            lpTokenMint,
+            owner
+        );
+
+        const referralFeeReceiver = await SplToken.getAssociatedTokenAddress(
+            ASSOCIATED_TOKEN_PROGRAM_ID,
+            TOKEN_PROGRAM_ID,
+            lpTokenMint,
+            referralFeeOwner
+        );
+
+        return {
+            authorityBump,
+            accounts: {
+                vault: vaultId,
+                vaultAuthority: vaultAuthority,
+                lpTokenMint: lpTokenMint,
+                vaultReserveToken: vaultReserveTokenAccount,
+                reserveTokenMint: reserveTokenMint,
+                feeReceiver: feeReceiver,
+                referralFeeReceiver: referralFeeReceiver,
+                referralFeeOwner: referralFeeOwner,
+                systemProgram: SystemProgram.programId,
+                tokenProgram: TOKEN_PROGRAM_ID,
+                associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+                rent: SYSVAR_RENT_PUBKEY,
+            },
+        };
+    }
+
     async initializeDexStates(
         wallet: anchor.Wallet,
         owner: Keypair | anchor.WalletAdaptor
@@ -359,6 +617,14 @@ export class VaultClient {
             this.program.programId
         );
 
+        const [marketsPda] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("dex_orca_legacy_markets"),
+            ],
+            this.program.programId
+        );
+
         // We allow user to pass existing orca swap struct, for test purpose.
         // TODO can be do better? don't like mixing test and production stuff in such an non-obvious way.
         let orcaMarket: OrcaLegacySwap = orca;
@@ -382,30 +648,14 @@ export class VaultClient {
                     owner: owner.publicKey,
                     systemProgram: SystemProgram.programId,
                 })
-                .instruction()
-        );
-
-        await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
-
-        orcaMarket.accounts.vaultOrcaLegacyAccount = pda;
-        this.dex.orcaLegacy = orcaMarket;
-    }
-
-    async initializeOrcaLegacyMarket(
-        wallet: anchor.Wallet,
-        owner: Keypair | anchor.WalletAdaptor
-    ) {
-        const tx = new Transaction().add(
+                .instruction(),
             await this.program.methods
-                .initializeDexOrcaLegacyMarket(
-                    this.dex.orcaLegacy.accounts.marketId
-                )
+                .initializeDexMarkets({ orcaLegacy: {} })
                 .accounts({
                     vault: this.vaultId,
                     dexStates: this.dex.dexStates,
-                    orcaLegacyAccounts:
-                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
-                    orcaSwapState: this.dex.orcaLegacy.accounts.swapProgram,
+                    dexMarkets: marketsPda,
+                    payer: wallet.payer.publicKey,
                     owner: owner.publicKey,
                     systemProgram: SystemProgram.programId,
                 })
@@ -413,27 +663,52 @@ export class VaultClient {
         );
 
         await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+
+        orcaMarket.accounts.vaultOrcaLegacyAccount = pda;
+        orcaMarket.accounts.vaultOrcaLegacyMarkets = marketsPda;
+        this.dex.orcaLegacy = orcaMarket;
     }
 
-    async initializePortAdditionalState(
+    async initializeDexWhirlpool(
         wallet: anchor.Wallet,
-        owner: Keypair | anchor.WalletAdaptor
+        owner: Keypair | anchor.WalletAdaptor,
+        whirlpoolProgram: PublicKey = WHIRLPOOL_PROGRAM_ID
     ) {
         const [pda] = await PublicKey.findProgramAddress(
             [
                 this.vaultId.toBuffer(),
-                anchor.utils.bytes.utf8.encode("port_additional_state"),
+                anchor.utils.bytes.utf8.encode("dex_whirlpool"),
             ],
             this.program.programId
         );
 
-        const tx = new Transaction();
-        tx.add(
+        const [marketsPda] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("dex_whirlpool_markets"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction().add(
             await this.program.methods
-                .initializePortAdditionalState()
+                .initializeDexWhirlpool()
                 .accounts({
                     vault: this.vaultId,
-                    portAdditionalStates: pda,
+                    dexStates: this.dex.dexStates,
+                    whirlpoolAccounts: pda,
+                    whirlpoolProgram: whirlpoolProgram,
+                    payer: wallet.payer.publicKey,
+                    owner: owner.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .instruction(),
+            await this.program.methods
+                .initializeDexMarkets({ whirlpool: {} })
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets: marketsPda,
                     payer: wallet.payer.publicKey,
                     owner: owner.publicKey,
                     systemProgram: SystemProgram.programId,
@@ -442,9 +717,138 @@ export class VaultClient {
         );
 
         await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+
+        this.dex.whirlpool = {
+            vaultWhirlpoolAccount: pda,
+            vaultWhirlpoolMarkets: marketsPda,
+        };
     }
 
-    async initializePortRewardAccounts(
+    /**
+     * Registers a whirlpool that sells the given token
+     *
+     * @param outputTokenMint defaults to the reserve token, other tokens are
+     *                        intermediate hops of routes
+     */
+    async addWhirlpoolMarket(
+        owner: Keypair | anchor.WalletAdaptor,
+        whirlpool: PublicKey,
+        marketId: number,
+        inputTokenMint: PublicKey,
+        outputTokenMint: PublicKey = this.vaultState.reserveTokenMint
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .addDexMarket({ whirlpool: {} }, marketId)
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets: this.dex.whirlpool.vaultWhirlpoolMarkets,
+                    dexAccounts: this.dex.whirlpool.vaultWhirlpoolAccount,
+                    market: whirlpool,
+                    inputTokenMint: inputTokenMint,
+                    outputTokenMint: outputTokenMint,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        return this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    async initializeOrcaLegacyMarket(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor
+    ) {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .addDexMarket(
+                    { orcaLegacy: {} },
+                    this.dex.orcaLegacy.accounts.marketId
+                )
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    dexAccounts:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
+                    market: this.dex.orcaLegacy.accounts.swapProgram,
+                    inputTokenMint:
+                        this.yieldSources.port.accounts.stakingRewardTokenMint,
+                    outputTokenMint: this.vaultState.reserveTokenMint,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+    }
+
+    /**
+     * Moves the markets of the legacy Orca registry to the Orca legacy DexMarkets registry
+     *
+     * @param swapStates swap states of the legacy registry entries that are set, by market id
+     */
+    async migrateOrcaLegacyMarkets(
+        owner: Keypair | anchor.WalletAdaptor,
+        swapStates: PublicKey[] = []
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .migrateOrcaLegacyMarkets()
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    orcaLegacyAccounts:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
+                    dexMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    owner: owner.publicKey,
+                })
+                .remainingAccounts(
+                    swapStates.map((pubkey) => ({
+                        pubkey,
+                        isSigner: false,
+                        isWritable: false,
+                    }))
+                )
+                .instruction()
+        );
+
+        return this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    async initializePortAdditionalState(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor
+    ) {
+        const [pda] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("port_additional_state"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction();
+        tx.add(
+            await this.program.methods
+                .initializePortAdditionalState()
+                .accounts({
+                    vault: this.vaultId,
+                    portAdditionalStates: pda,
+                    payer: wallet.payer.publicKey,
+                    owner: owner.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .instruction()
+        );
+
+        await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+    }
+
+    async initializePortRewardAccounts(
         wallet: anchor.Wallet,
         owner: Keypair | anchor.WalletAdaptor
     ) {
@@ -548,6 +952,109 @@ export class VaultClient {
         await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
     }
 
+    async initializeSolendRewardAccounts(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor,
+        rewardRatePerSlot: anchor.BN
+    ) {
+        const solend = this.yieldSources.solend;
+        const [solendAdditionalStates] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_additional_state"),
+            ],
+            this.program.programId
+        );
+        const [vaultSolendRewardToken] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_reward"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction();
+        tx.add(
+            await this.program.methods
+                .initializeSolendRewardAccounts(rewardRatePerSlot)
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    solendAdditionalStates: solendAdditionalStates,
+                    vaultSolendObligation: await solend.getVaultObligation(
+                        this.program,
+                        this.vaultId
+                    ),
+                    vaultSolendRewardToken: vaultSolendRewardToken,
+                    solendRewardTokenMint: solend.accounts.rewardTokenMint,
+                    solendRewardTokenOracle: solend.accounts.rewardTokenOracle,
+                    solendRewardDistributor: solend.accounts.rewardDistributor,
+                    solendRewardDistributorProgram:
+                        SOLEND_REWARD_DISTRIBUTOR_PROGRAM_ID,
+                    solendProgram: solend.accounts.program,
+                    solendMarket: solend.accounts.market,
+                    payer: wallet.payer.publicKey,
+                    owner: owner.publicKey,
+                    systemProgram: SystemProgram.programId,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                    clock: SYSVAR_CLOCK_PUBKEY,
+                    rent: SYSVAR_RENT_PUBKEY,
+                })
+                .instruction()
+        );
+
+        await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+        await solend.loadAdditionalAccounts(this.program, this.vaultId);
+        await this.reload();
+    }
+
+    /**
+     * Updates the Solend liquidity mining campaign the vault claims from,
+     * from the reward accounts of the Solend reserve
+     *
+     * @param rewardRatePerSlot reward tokens distributed to all depositors of
+     *                          the reserve per slot, as published by Solend
+     */
+    async updateSolendRewards(
+        owner: Keypair | anchor.WalletAdaptor,
+        rewardRatePerSlot: anchor.BN
+    ): Promise<TransactionSignature> {
+        const solend = this.yieldSources.solend;
+        const [solendAdditionalStates] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_additional_state"),
+            ],
+            this.program.programId
+        );
+        const [vaultSolendRewardToken] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_reward"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction().add(
+            await this.program.methods
+                .updateSolendRewards(rewardRatePerSlot)
+                .accounts({
+                    vault: this.vaultId,
+                    solendAdditionalStates: solendAdditionalStates,
+                    vaultSolendRewardToken: vaultSolendRewardToken,
+                    solendRewardTokenMint: solend.accounts.rewardTokenMint,
+                    solendRewardTokenOracle: solend.accounts.rewardTokenOracle,
+                    solendRewardDistributor: solend.accounts.rewardDistributor,
+                    solendRewardDistributorProgram:
+                        SOLEND_REWARD_DISTRIBUTOR_PROGRAM_ID,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        return this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
     async initializeSolend(
         wallet: anchor.Wallet,
         solend: SolendReserveAsset,
@@ -679,16 +1186,8 @@ export class VaultClient {
 
     getConsolidateRefreshIx(): Promise<TransactionInstruction> {
         const feeAccounts = [
-            {
-                isSigner: false,
-                isWritable: true,
-                pubkey: this.vaultState.feeReceiver,
-            },
-            {
-                isSigner: false,
-                isWritable: true,
-                pubkey: this.vaultState.referralFeeReceiver,
-            },
+            ...this.getFeeReceiverAccounts(),
+            ...this.getValuationAccounts(),
         ];
 
         // We include the vault lp token account for ALL lending pools here
@@ -706,6 +1205,115 @@ export class VaultClient {
             .instruction();
     }
 
+    /**
+     * Refreshes every yield source and consolidates the vault value at once
+     */
+    async getRefreshAllIx(): Promise<TransactionInstruction> {
+        return this.program.methods
+            .refreshAll()
+            .accounts({
+                vault: this.vaultId,
+                vaultAuthority: this.vaultState.vaultAuthority,
+                vaultReserveToken: this.vaultState.vaultReserveToken,
+                lpTokenMint: this.vaultState.lpTokenMint,
+                feeReceiver: this.vaultState.feeReceiver,
+                referralFeeReceiver: this.vaultState.referralFeeReceiver,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts([
+                ...this.getValuationAccounts(),
+                ...(await this.getYieldSourceRefreshAccounts()),
+            ])
+            .instruction();
+    }
+
+    async refreshAll(): Promise<TransactionSignature> {
+        const tx = new Transaction().add(await this.getRefreshAllIx());
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
+    private getFeeReceiverAccounts(): AccountMeta[] {
+        return [
+            {
+                isSigner: false,
+                isWritable: true,
+                pubkey: this.vaultState.feeReceiver,
+            },
+            {
+                isSigner: false,
+                isWritable: true,
+                pubkey: this.vaultState.referralFeeReceiver,
+            },
+        ];
+    }
+
+    /**
+     * @returns Insurance reserve if the vault has one, followed by the
+     *          reward valuation accounts if the vault values its rewards
+     */
+    private getValuationAccounts(): AccountMeta[] {
+        const accounts: AccountMeta[] = [];
+        if (!this.vaultState.insuranceReserve.equals(PublicKey.default)) {
+            accounts.push({
+                isSigner: false,
+                isWritable: true,
+                pubkey: this.vaultState.insuranceReserve,
+            });
+        }
+        if (this.vaultState.config.valueRewards) {
+            const port = this.yieldSources.port.accounts;
+            accounts.push(
+                ...[
+                    port.vaultPortAdditionalStates,
+                    port.vaultPortStakeAccount,
+                    port.vaultPortRewardToken,
+                    port.stakingRewardTokenMint,
+                    port.stakingRewardOracle,
+                    port.vaultPortSubRewardToken,
+                    port.stakingSubRewardTokenMint ?? PublicKey.default,
+                    port.stakingSubRewardOracle ?? PublicKey.default,
+                    this.vaultState.reserveTokenMint,
+                ].map((pubkey) => ({
+                    isSigner: false,
+                    isWritable: false,
+                    pubkey,
+                }))
+            );
+        }
+        return accounts;
+    }
+
+    /**
+     * @returns Accounts of the refresh instructions of the yield sources,
+     *          Solend first
+     */
+    private async getYieldSourceRefreshAccounts(): Promise<AccountMeta[]> {
+        const accounts: AccountMeta[] = [];
+        for (const ys of [this.yieldSources.solend, this.yieldSources.port]) {
+            if (ys != null) {
+                const refreshIx = await ys.getRefreshIx(
+                    this.program,
+                    this.vaultId,
+                    this.vaultState
+                );
+                accounts.push(...refreshIx.keys);
+            }
+        }
+        return accounts;
+    }
+
+    /**
+     * @returns Remaining accounts of the instructions that refresh the vault
+     *          before they run, e.g. deposit_with_refresh
+     */
+    private async getRefreshAndConsolidateAccounts(): Promise<AccountMeta[]> {
+        return [
+            ...this.getFeeReceiverAccounts(),
+            ...this.getValuationAccounts(),
+            ...(await this.getYieldSourceRefreshAccounts()),
+        ];
+    }
+
     /**
      *
      * @param wallet
@@ -785,10 +1393,94 @@ export class VaultClient {
         return await this.program.provider.sendAndConfirm(tx, [owner]);
     }
 
+    /**
+     * Marks yield sources as withdraw-only, so that rebalances give them zero
+     * weight and reconciles only redeem from them
+     */
+    async updateWithdrawOnlyFlags(
+        owner: Keypair | anchor.WalletAdaptor,
+        flags: number
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .updateWithdrawOnlyFlags(flags)
+                .accounts({
+                    vault: this.vaultId,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+        return await this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
     /**
      * @param new_value
      * @returns
      */
+    async initializeInsuranceReserve(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor
+    ): Promise<TransactionSignature> {
+        const [insuranceReserve] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("insurance_reserve"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction().add(
+            await this.program.methods
+                .initializeInsuranceReserve()
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    insuranceReserve: insuranceReserve,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
+                    payer: wallet.payer.publicKey,
+                    owner: owner.publicKey,
+                    systemProgram: SystemProgram.programId,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                    rent: SYSVAR_RENT_PUBKEY,
+                })
+                .instruction()
+        );
+
+        const txSig = await this.program.provider.sendAndConfirm(tx, [
+            owner,
+            wallet.payer,
+        ]);
+        await this.reload();
+        return txSig;
+    }
+
+    /**
+     * Withdraws the insurance reserve balance above its target size
+     *
+     * @param owner
+     * @param destination reserve token account that receives the surplus
+     * @returns
+     */
+    async withdrawInsuranceSurplus(
+        owner: Keypair | anchor.WalletAdaptor,
+        destination: PublicKey
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .withdrawInsuranceSurplus()
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    insuranceReserve: this.vaultState.insuranceReserve,
+                    destination: destination,
+                    owner: owner.publicKey,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .instruction()
+        );
+        return await this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
     async updateConfig(
         owner: Keypair | anchor.WalletAdaptor,
         config: VaultConfig
@@ -808,26 +1500,292 @@ export class VaultClient {
         return await this.program.provider.sendAndConfirm(tx, [owner]);
     }
 
-    getDepositIx(
-        amount: anchor.BN,
+    /**
+     * Upgrades the vault account to the layout and version of the program.
+     * Vaults that are already up to date are left unchanged
+     *
+     * @param wallet pays for any additional space the vault needs
+     * @param owner
+     * @returns
+     */
+    async migrate(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .migrate()
+                .accounts({
+                    vault: this.vaultId,
+                    lpTokenMint: this.vaultState.lpTokenMint,
+                    payer: wallet.payer.publicKey,
+                    owner: owner.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .instruction()
+        );
+
+        const txSig = await this.program.provider.sendAndConfirm(tx, [
+            owner,
+            wallet.payer,
+        ]);
+        await this.reload();
+        return txSig;
+    }
+
+    async getDepositorAddress(wallet: PublicKey): Promise<PublicKey> {
+        const [depositor] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("depositor"),
+                wallet.toBuffer(),
+            ],
+            this.program.programId
+        );
+        return depositor;
+    }
+
+    /**
+     * Creates the account that tracks the deposits of a wallet, which is
+     * required to deposit into permissioned, capped or locked up vaults
+     *
+     * @param wallet pays for the account
+     * @param depositorWallet defaults to the paying wallet
+     * @returns
+     */
+    async initializeDepositor(
+        wallet: anchor.Wallet,
+        depositorWallet: PublicKey = wallet.publicKey
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .initializeDepositor()
+                .accounts({
+                    vault: this.vaultId,
+                    depositor: await this.getDepositorAddress(depositorWallet),
+                    wallet: depositorWallet,
+                    payer: wallet.payer.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .instruction()
+        );
+        return await this.program.provider.sendAndConfirm(tx, [wallet.payer]);
+    }
+
+    /**
+     * Adds a wallet to the allowlist of a permissioned vault
+     *
+     * @param owner
+     * @param depositorWallet wallet with an initialized depositor account
+     * @param depositCap overrides the per-user deposit cap of the vault,
+     *                   unless it is 0
+     * @returns
+     */
+    async addDepositor(
+        owner: Keypair | anchor.WalletAdaptor,
+        depositorWallet: PublicKey,
+        depositCap: anchor.BN = new anchor.BN(0)
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .addDepositor(depositCap)
+                .accounts({
+                    vault: this.vaultId,
+                    depositor: await this.getDepositorAddress(depositorWallet),
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+        return await this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    /**
+     * Removes a wallet from the allowlist of a permissioned vault,
+     * the wallet can still withdraw
+     *
+     * @param owner
+     * @param depositorWallet
+     * @returns
+     */
+    async removeDepositor(
+        owner: Keypair | anchor.WalletAdaptor,
+        depositorWallet: PublicKey
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .removeDepositor()
+                .accounts({
+                    vault: this.vaultId,
+                    depositor: await this.getDepositorAddress(depositorWallet),
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+        return await this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    /**
+     * @returns The depositor account of the wallet if it is initialized,
+     *          which goes first in the remaining accounts of deposits
+     *          and withdrawals
+     */
+    private async getDepositorAccounts(
+        wallet: PublicKey
+    ): Promise<AccountMeta[]> {
+        const depositor = await this.getDepositorAddress(wallet);
+        const info = await this.program.provider.connection.getAccountInfo(
+            depositor
+        );
+        if (info == null) {
+            return [];
+        }
+        return [{ isSigner: false, isWritable: true, pubkey: depositor }];
+    }
+
+    async getDepositIx(
+        amount: anchor.BN,
+        userAuthority: PublicKey,
+        userLpTokenAccount: PublicKey,
+        userReserveTokenAccount: PublicKey
+    ): Promise<TransactionInstruction> {
+        return this.program.methods
+            .deposit(amount)
+            .accounts(
+                await this.getDepositAccounts(
+                    userAuthority,
+                    userLpTokenAccount,
+                    userReserveTokenAccount
+                )
+            )
+            .remainingAccounts([
+                ...(await this.getDepositorAccounts(userAuthority)),
+                ...(await this.getAutoDeployAccounts(amount)),
+            ])
+            .instruction();
+    }
+
+    /**
+     * Picks the yield source that a deposit is auto-deployed to the same way
+     * the program does, i.e. the one furthest below its strategy weight
+     *
+     * @returns Accounts of the reconcile instruction of that yield source,
+     *          or none if the deposit is left idle
+     */
+    async getAutoDeployAccounts(amount: anchor.BN): Promise<AccountMeta[]> {
+        const vaultState = this.vaultState;
+        if (
+            amount.isZero() ||
+            amount.gt(vaultState.config.autoDeployMax) ||
+            (vaultState.haltFlags & VaultFlags.HaltReconciles) != 0
+        ) {
+            return [];
+        }
+
+        const slot = await this.program.provider.connection.getSlot();
+        const slotsSinceRebalance =
+            slot - vaultState.strategyWeights.lastUpdate.slot.toNumber();
+        if (slotsSinceRebalance > MAX_SLOTS_SINCE_REBALANCE) {
+            return [];
+        }
+
+        const calcPct = (value: anchor.BN, pct: number) =>
+            value.muln(pct).divn(100);
+        const vaultValue = vaultState.value.value.add(amount);
+        const idleValue = Object.keys(this.yieldSources).reduce(
+            (acc, ys) => acc.sub(vaultState.actualAllocations[ys].value),
+            vaultValue
+        );
+        const config = vaultState.config;
+        let idleBuffer = calcPct(vaultValue, config.idleBufferPct);
+        if (
+            config.idleBufferMaxPct > 0 &&
+            idleValue.gte(calcPct(vaultValue, config.idleBufferMinPct)) &&
+            idleValue.lte(calcPct(vaultValue, config.idleBufferMaxPct))
+        ) {
+            idleBuffer = idleValue;
+        }
+        const allocatableValue = anchor.BN.max(
+            vaultValue.sub(idleBuffer),
+            new anchor.BN(0)
+        );
+
+        // Ties go to the last yield source, like on-chain
+        let target: SolendReserveAsset | PortReserveAsset;
+        let maxShortfall = new anchor.BN(0);
+        for (const [ys, flag] of [
+            ["solend", YieldSourceFlags.Solend],
+            ["port", YieldSourceFlags.Port],
+        ] as const) {
+            if (
+                this.yieldSources[ys] == null ||
+                (vaultState.withdrawOnlyFlags & flag) != 0
+            ) {
+                continue;
+            }
+            const shortfall = allocatableValue
+                .muln(vaultState.strategyWeights[ys])
+                .divn(10000)
+                .sub(vaultState.actualAllocations[ys].value);
+            if (shortfall.gtn(0) && shortfall.gte(maxShortfall)) {
+                target = this.yieldSources[ys];
+                maxShortfall = shortfall;
+            }
+        }
+        if (target == null) {
+            return [];
+        }
+
+        const reconcileIx = await target.getReconcileIx(
+            this.program,
+            this.vaultId,
+            vaultState
+        );
+        return reconcileIx.keys;
+    }
+
+    /**
+     * Deposit instruction that refreshes the vault itself,
+     * so it does not need to follow the refresh instructions.
+     * Unlike getDepositIx, the deposit is never auto-deployed
+     */
+    async getDepositWithRefreshIx(
+        amount: anchor.BN,
+        userAuthority: PublicKey,
+        userLpTokenAccount: PublicKey,
+        userReserveTokenAccount: PublicKey
+    ): Promise<TransactionInstruction> {
+        return this.program.methods
+            .depositWithRefresh(amount)
+            .accounts(
+                await this.getDepositAccounts(
+                    userAuthority,
+                    userLpTokenAccount,
+                    userReserveTokenAccount
+                )
+            )
+            .remainingAccounts([
+                ...(await this.getDepositorAccounts(userAuthority)),
+                ...(await this.getRefreshAndConsolidateAccounts()),
+            ])
+            .instruction();
+    }
+
+    private async getDepositAccounts(
         userAuthority: PublicKey,
         userLpTokenAccount: PublicKey,
         userReserveTokenAccount: PublicKey
-    ): Promise<TransactionInstruction> {
-        return this.program.methods
-            .deposit(amount)
-            .accounts({
-                vault: this.vaultId,
-                vaultAuthority: this.vaultState.vaultAuthority,
-                vaultReserveToken: this.vaultState.vaultReserveToken,
-                lpTokenMint: this.vaultState.lpTokenMint,
-                userReserveToken: userReserveTokenAccount,
-                userLpToken: userLpTokenAccount,
-                userAuthority: userAuthority,
-                tokenProgram: TOKEN_PROGRAM_ID,
-                clock: SYSVAR_CLOCK_PUBKEY,
-            })
-            .instruction();
+    ) {
+        return {
+            vault: this.vaultId,
+            vaultAuthority: this.vaultState.vaultAuthority,
+            vaultReserveToken: this.vaultState.vaultReserveToken,
+            lpTokenMint: this.vaultState.lpTokenMint,
+            userReserveToken: userReserveTokenAccount,
+            userLpToken: userLpTokenAccount,
+            userAuthority: userAuthority,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            clock: SYSVAR_CLOCK_PUBKEY,
+        };
     }
 
     /**
@@ -902,7 +1860,7 @@ export class VaultClient {
         return await this.program.provider.sendAll(txs);
     }
 
-    getWithdrawIx(
+    async getWithdrawIx(
         amount: anchor.BN,
         userAuthority: PublicKey,
         userLpTokenAccount: PublicKey,
@@ -910,20 +1868,61 @@ export class VaultClient {
     ): Promise<TransactionInstruction> {
         return this.program.methods
             .withdraw(amount)
-            .accounts({
-                vault: this.vaultId,
-                vaultAuthority: this.vaultState.vaultAuthority,
-                userAuthority: userAuthority,
-                userLpToken: userLpTokenAccount,
-                userReserveToken: userReserveTokenAccount,
-                vaultReserveToken: this.vaultState.vaultReserveToken,
-                lpTokenMint: this.vaultState.lpTokenMint,
-                tokenProgram: TOKEN_PROGRAM_ID,
-                clock: SYSVAR_CLOCK_PUBKEY,
-            })
+            .accounts(
+                await this.getWithdrawAccounts(
+                    userAuthority,
+                    userLpTokenAccount,
+                    userReserveTokenAccount
+                )
+            )
+            .remainingAccounts(await this.getDepositorAccounts(userAuthority))
+            .instruction();
+    }
+
+    /**
+     * Withdraw instruction that refreshes the vault itself,
+     * so it does not need to follow the refresh instructions
+     */
+    async getWithdrawWithRefreshIx(
+        amount: anchor.BN,
+        userAuthority: PublicKey,
+        userLpTokenAccount: PublicKey,
+        userReserveTokenAccount: PublicKey
+    ): Promise<TransactionInstruction> {
+        return this.program.methods
+            .withdrawWithRefresh(amount)
+            .accounts(
+                await this.getWithdrawAccounts(
+                    userAuthority,
+                    userLpTokenAccount,
+                    userReserveTokenAccount
+                )
+            )
+            .remainingAccounts([
+                ...(await this.getDepositorAccounts(userAuthority)),
+                ...(await this.getRefreshAndConsolidateAccounts()),
+            ])
             .instruction();
     }
 
+    private async getWithdrawAccounts(
+        userAuthority: PublicKey,
+        userLpTokenAccount: PublicKey,
+        userReserveTokenAccount: PublicKey
+    ) {
+        return {
+            vault: this.vaultId,
+            vaultAuthority: this.vaultState.vaultAuthority,
+            userAuthority: userAuthority,
+            userLpToken: userLpTokenAccount,
+            userReserveToken: userReserveTokenAccount,
+            vaultReserveToken: this.vaultState.vaultReserveToken,
+            lpTokenMint: this.vaultState.lpTokenMint,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            clock: SYSVAR_CLOCK_PUBKEY,
+        };
+    }
+
     /**
      *
      * @param wallet
@@ -1091,6 +2090,72 @@ export class VaultClient {
         });
     }
 
+    async getRebalanceRemainingAccounts(): Promise<AccountMeta[]> {
+        const remainingAccounts: AccountMeta[] = [];
+        if (
+            this.yieldSources.solend != null &&
+            this.vaultState.solendObligationEnabled
+        ) {
+            const solendAdditionalStates =
+                await PublicKey.createProgramAddress(
+                    [
+                        this.vaultId.toBuffer(),
+                        anchor.utils.bytes.utf8.encode(
+                            "solend_additional_state"
+                        ),
+                        new Uint8Array([
+                            this.vaultState.vaultSolendAdditionalStateBump,
+                        ]),
+                    ],
+                    this.program.programId
+                );
+            const { solendRewardTokenOracle } =
+                await this.program.account.vaultSolendAdditionalState.fetch(
+                    solendAdditionalStates
+                );
+            remainingAccounts.push(
+                {
+                    isSigner: false,
+                    isWritable: false,
+                    pubkey: solendAdditionalStates,
+                },
+                {
+                    isSigner: false,
+                    isWritable: false,
+                    pubkey: solendRewardTokenOracle,
+                }
+            );
+        }
+        if (this.yieldSources.port != null) {
+            const port = this.yieldSources.port.accounts;
+            const { subRewardAvailable } =
+                await this.program.account.vaultPortAdditionalState.fetch(
+                    port.vaultPortAdditionalStates
+                );
+            // The sub-reward oracle is only passed if the pool has a sub-reward
+            const portAccounts = subRewardAvailable
+                ? [
+                      port.vaultPortAdditionalStates,
+                      port.stakingRewardOracle,
+                      port.stakingSubRewardOracle,
+                      port.stakingPool,
+                  ]
+                : [
+                      port.vaultPortAdditionalStates,
+                      port.stakingRewardOracle,
+                      port.stakingPool,
+                  ];
+            remainingAccounts.push(
+                ...portAccounts.map((pubkey) => ({
+                    isSigner: false,
+                    isWritable: false,
+                    pubkey,
+                }))
+            );
+        }
+        return remainingAccounts;
+    }
+
     async getRebalanceTx(
         proposedWeights: ProposedWeightsBps
     ): Promise<Transaction> {
@@ -1115,30 +2180,7 @@ export class VaultClient {
                             ? this.yieldSources.port.accounts.reserve
                             : Keypair.generate().publicKey,
                 })
-                .remainingAccounts(
-                    this.yieldSources.port != null
-                        ? [
-                              {
-                                  isSigner: false,
-                                  isWritable: false,
-                                  pubkey: this.yieldSources.port.accounts
-                                      .vaultPortAdditionalStates,
-                              },
-                              {
-                                  isSigner: false,
-                                  isWritable: false,
-                                  pubkey: this.yieldSources.port.accounts
-                                      .stakingRewardOracle,
-                              },
-                              {
-                                  isSigner: false,
-                                  isWritable: false,
-                                  pubkey: this.yieldSources.port.accounts
-                                      .stakingPool,
-                              },
-                          ]
-                        : []
-                )
+                .remainingAccounts(await this.getRebalanceRemainingAccounts())
                 .instruction()
         );
         return rebalanceTx;
@@ -1192,28 +2234,7 @@ export class VaultClient {
                                 : dummyKey,
                     })
                     .remainingAccounts(
-                        this.yieldSources.port != null
-                            ? [
-                                  {
-                                      isSigner: false,
-                                      isWritable: false,
-                                      pubkey: this.yieldSources.port.accounts
-                                          .vaultPortAdditionalStates,
-                                  },
-                                  {
-                                      isSigner: false,
-                                      isWritable: false,
-                                      pubkey: this.yieldSources.port.accounts
-                                          .stakingRewardOracle,
-                                  },
-                                  {
-                                      isSigner: false,
-                                      isWritable: false,
-                                      pubkey: this.yieldSources.port.accounts
-                                          .stakingPool,
-                                  },
-                              ]
-                            : []
+                        await this.getRebalanceRemainingAccounts()
                     )
                     .preInstructions(simIx)
                     .simulate()
@@ -1274,6 +2295,47 @@ export class VaultClient {
         return this.program.provider.sendAll(txs);
     }
 
+    /**
+     * Reconciles every yield source toward the allocations set by the last
+     * rebalance in one instruction, redemptions first
+     */
+    async getReconcileAllIx(): Promise<TransactionInstruction> {
+        const remainingAccounts: AccountMeta[] = [];
+        for (const ys of [this.yieldSources.solend, this.yieldSources.port]) {
+            if (ys != null) {
+                const reconcileIx = await ys.getReconcileIx(
+                    this.program,
+                    this.vaultId,
+                    this.vaultState
+                );
+                remainingAccounts.push(...reconcileIx.keys);
+            }
+        }
+
+        return this.program.methods
+            .reconcileAll()
+            .accounts({
+                vault: this.vaultId,
+            })
+            .remainingAccounts(remainingAccounts)
+            .instruction();
+    }
+
+    async reconcileAll(): Promise<TransactionSignature> {
+        const tx = new Transaction().add(this.getComputeBudgetIx(1000000, 0));
+        for (const ys of Object.values(this.yieldSources)) {
+            tx.add(
+                await ys.getRefreshIx(
+                    this.program,
+                    this.vaultId,
+                    this.vaultState
+                )
+            );
+        }
+        tx.add(await this.getReconcileAllIx());
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
     async claimPortReward(): Promise<TransactionSignature> {
         const tx = new Transaction();
         tx.add(
@@ -1296,9 +2358,11 @@ export class VaultClient {
                     portAdditionalStates:
                         this.yieldSources.port.accounts
                             .vaultPortAdditionalStates,
+                    portRewardTokenOracle:
+                        this.yieldSources.port.accounts.stakingRewardOracle,
                     dexStates: this.dex.dexStates,
-                    orcaLegacyAccounts:
-                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
+                    orcaLegacyMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
                     orcaSwapState: this.dex.orcaLegacy.accounts.swapProgram,
                     orcaSwapAuthority:
                         this.dex.orcaLegacy.accounts.swapAuthority,
@@ -1313,14 +2377,362 @@ export class VaultClient {
                     orcaSwapProgram: this.dex.orcaLegacy.accounts.programId,
                     vaultPortRewardToken:
                         this.yieldSources.port.accounts.vaultPortRewardToken,
+                    portRewardTokenMint:
+                        this.yieldSources.port.accounts.stakingRewardTokenMint,
+                    vaultReserveToken: this.vaultState.vaultReserveToken,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .instruction()
+        );
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
+    /**
+     * Sells the claimed Port staking sub-rewards for the reserve token
+     *
+     * @param market registered Orca legacy market for the sub-reward token
+     */
+    async sellPortSubReward(
+        market: OrcaLegacySwap
+    ): Promise<TransactionSignature> {
+        const port = this.yieldSources.port.accounts;
+        const tx = new Transaction().add(
+            await this.program.methods
+                .sellPortSubReward(market.accounts.marketId)
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    portAdditionalStates: port.vaultPortAdditionalStates,
+                    portSubRewardTokenOracle: port.stakingSubRewardOracle,
+                    dexStates: this.dex.dexStates,
+                    orcaLegacyMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    orcaSwapState: market.accounts.swapProgram,
+                    orcaSwapAuthority: market.accounts.swapAuthority,
+                    orcaInputTokenAccount: market.accounts.tokenAccountA,
+                    orcaOutputTokenAccount: market.accounts.tokenAccountB,
+                    orcaSwapTokenMint: market.accounts.poolTokenMint,
+                    orcaFeeAccount: market.accounts.feeAccount,
+                    orcaSwapProgram: market.accounts.programId,
+                    vaultPortSubRewardToken: port.vaultPortSubRewardToken,
+                    portSubRewardTokenMint: port.stakingSubRewardTokenMint,
+                    vaultReserveToken: this.vaultState.vaultReserveToken,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .instruction()
+        );
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
+    /**
+     * Registers an Orca legacy market that sells the given token for the reserve token
+     *
+     * @param market mock or loaded Orca legacy market, registered at its market id
+     * @param outputTokenMint defaults to the reserve token, other tokens are
+     *                        intermediate hops of routes
+     */
+    async addOrcaLegacyMarket(
+        wallet: anchor.Wallet,
+        owner: Keypair | anchor.WalletAdaptor,
+        market: OrcaLegacySwap,
+        inputTokenMint: PublicKey,
+        outputTokenMint: PublicKey = this.vaultState.reserveTokenMint
+    ) {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .addDexMarket({ orcaLegacy: {} }, market.accounts.marketId)
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    dexAccounts:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
+                    market: market.accounts.swapProgram,
+                    inputTokenMint: inputTokenMint,
+                    outputTokenMint: outputTokenMint,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        await this.program.provider.sendAndConfirm(tx, [owner, wallet.payer]);
+    }
+
+    /**
+     * Replaces the Orca legacy market registered at the given market's id
+     *
+     * @param market mock or loaded Orca legacy market, registered at its market id
+     * @param outputTokenMint defaults to the reserve token, other tokens are
+     *                        intermediate hops of routes
+     */
+    async replaceOrcaLegacyMarket(
+        owner: Keypair | anchor.WalletAdaptor,
+        market: OrcaLegacySwap,
+        inputTokenMint: PublicKey,
+        outputTokenMint: PublicKey = this.vaultState.reserveTokenMint
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .replaceDexMarket({ orcaLegacy: {} }, market.accounts.marketId)
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    dexAccounts:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyAccount,
+                    market: market.accounts.swapProgram,
+                    inputTokenMint: inputTokenMint,
+                    outputTokenMint: outputTokenMint,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        return this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    /**
+     * Unregisters the Orca legacy market at the given market id,
+     * the market id can then be reused
+     */
+    async removeOrcaLegacyMarket(
+        owner: Keypair | anchor.WalletAdaptor,
+        marketId: number
+    ): Promise<TransactionSignature> {
+        const tx = new Transaction().add(
+            await this.program.methods
+                .removeDexMarket({ orcaLegacy: {} }, marketId)
+                .accounts({
+                    vault: this.vaultId,
+                    dexStates: this.dex.dexStates,
+                    dexMarkets:
+                        this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets,
+                    owner: owner.publicKey,
+                })
+                .instruction()
+        );
+
+        return this.program.provider.sendAndConfirm(tx, [owner]);
+    }
+
+    /**
+     * Claims the Port staking rewards and sells them for the reserve token
+     *
+     * @param subRewardMarket registered Orca legacy market that sells the sub-reward token,
+     *                        required if the staking pool has sub-rewards
+     */
+    async harvest(
+        subRewardMarket?: OrcaLegacySwap
+    ): Promise<TransactionSignature> {
+        const port = this.yieldSources.port.accounts;
+        const orca = this.dex.orcaLegacy.accounts;
+        const subRewardAvailable = port.stakingSubRewardPool != undefined;
+
+        const remainingAccounts: AccountMeta[] = [
+            this.vaultState.feeReceiver,
+            this.vaultState.referralFeeReceiver,
+        ].map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }));
+
+        const subRewardHops = [];
+        if (subRewardAvailable) {
+            const market = subRewardMarket.accounts;
+            subRewardHops.push({
+                dex: { orcaLegacy: {} },
+                marketId: market.marketId,
+            });
+            remainingAccounts.push(
+                ...[
+                    [port.stakingSubRewardOracle, false],
+                    [port.stakingSubRewardTokenMint, false],
+                    [orca.vaultOrcaLegacyMarkets, false],
+                    [market.programId, false],
+                    [market.swapProgram, false],
+                    [market.swapAuthority, false],
+                    [market.tokenAccountA, true],
+                    [market.tokenAccountB, true],
+                    [market.poolTokenMint, true],
+                    [market.feeAccount, true],
+                ].map(([pubkey, isWritable]: [PublicKey, boolean]) => ({
+                    pubkey,
+                    isSigner: false,
+                    isWritable,
+                }))
+            );
+        }
+
+        const tx = new Transaction().add(
+            await this.program.methods
+                .harvest(orca.marketId, subRewardHops)
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    portAdditionalStates: port.vaultPortAdditionalStates,
+                    portRewardTokenOracle: port.stakingRewardOracle,
+                    vaultPortStakeAccount: port.vaultPortStakeAccount,
+                    vaultPortRewardToken: port.vaultPortRewardToken,
+                    vaultPortSubRewardToken: port.vaultPortSubRewardToken,
+                    portRewardTokenMint: port.stakingRewardTokenMint,
+                    portStakingPool: port.stakingPool,
+                    portLendProgram: port.program,
+                    portStakeProgram: port.stakingProgram,
+                    portStakingRewardPool: port.stakingRewardPool,
+                    portStakingSubRewardPool: subRewardAvailable
+                        ? port.stakingSubRewardPool
+                        : Keypair.generate().publicKey,
+                    portStakingAuthority: port.stakingProgamAuthority,
+                    dexStates: this.dex.dexStates,
+                    orcaLegacyMarkets: orca.vaultOrcaLegacyMarkets,
+                    orcaSwapState: orca.swapProgram,
+                    orcaSwapAuthority: orca.swapAuthority,
+                    orcaInputTokenAccount: orca.tokenAccountA,
+                    orcaOutputTokenAccount: orca.tokenAccountB,
+                    orcaSwapTokenMint: orca.poolTokenMint,
+                    orcaFeeAccount: orca.feeAccount,
+                    orcaSwapProgram: orca.programId,
+                    vaultReserveToken: this.vaultState.vaultReserveToken,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
+                    lpTokenMint: this.vaultState.lpTokenMint,
+                    clock: SYSVAR_CLOCK_PUBKEY,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .remainingAccounts(remainingAccounts)
+                .instruction()
+        );
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
+    /**
+     * Sells the claimed Solend liquidity mining rewards for the reserve token
+     *
+     * @param market registered Orca legacy market that sells the Solend reward token
+     */
+    async sellSolendReward(
+        market: OrcaLegacySwap
+    ): Promise<TransactionSignature> {
+        const solend = this.yieldSources.solend.accounts;
+        const [solendAdditionalStates] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_additional_state"),
+            ],
+            this.program.programId
+        );
+        const [vaultSolendRewardToken] = await PublicKey.findProgramAddress(
+            [
+                this.vaultId.toBuffer(),
+                anchor.utils.bytes.utf8.encode("solend_reward"),
+            ],
+            this.program.programId
+        );
+
+        const tx = new Transaction().add(
+            await this.program.methods
+                .sellSolendReward([
+                    {
+                        dex: { orcaLegacy: {} },
+                        marketId: market.accounts.marketId,
+                    },
+                ])
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    solendAdditionalStates: solendAdditionalStates,
+                    solendRewardTokenOracle: solend.rewardTokenOracle,
+                    dexStates: this.dex.dexStates,
+                    vaultSolendRewardToken: vaultSolendRewardToken,
+                    solendRewardTokenMint: solend.rewardTokenMint,
+                    vaultReserveToken: this.vaultState.vaultReserveToken,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .remainingAccounts(
+                    this.getOrcaLegacyRouteAccounts([market], [])
+                )
+                .instruction()
+        );
+        return this.program.provider.sendAndConfirm(tx);
+    }
+
+    /**
+     * Sells the claimed Port staking rewards for the reserve token
+     * through a route of registered Orca legacy markets
+     *
+     * @param markets markets of each hop of the route, in order
+     * @param intermediateTokenAccounts vault-owned token accounts that receive
+     *                                  the output of each hop but the last
+     */
+    async sellPortRewardRoute(
+        markets: OrcaLegacySwap[],
+        intermediateTokenAccounts: PublicKey[]
+    ): Promise<TransactionSignature> {
+        const port = this.yieldSources.port.accounts;
+        const tx = new Transaction().add(
+            await this.program.methods
+                .sellPortRewardRoute(
+                    markets.map((market) => ({
+                        dex: { orcaLegacy: {} },
+                        marketId: market.accounts.marketId,
+                    }))
+                )
+                .accounts({
+                    vault: this.vaultId,
+                    vaultAuthority: this.vaultState.vaultAuthority,
+                    portAdditionalStates: port.vaultPortAdditionalStates,
+                    portRewardTokenOracle: port.stakingRewardOracle,
+                    dexStates: this.dex.dexStates,
+                    vaultPortRewardToken: port.vaultPortRewardToken,
+                    portRewardTokenMint: port.stakingRewardTokenMint,
                     vaultReserveToken: this.vaultState.vaultReserveToken,
+                    reserveTokenMint: this.vaultState.reserveTokenMint,
                     tokenProgram: TOKEN_PROGRAM_ID,
                 })
+                .remainingAccounts(
+                    this.getOrcaLegacyRouteAccounts(
+                        markets,
+                        intermediateTokenAccounts
+                    )
+                )
                 .instruction()
         );
         return this.program.provider.sendAndConfirm(tx);
     }
 
+    /**
+     * @returns Accounts of each hop of a route through Orca legacy markets,
+     *          each followed by the token account that receives its output,
+     *          except for the last hop
+     */
+    private getOrcaLegacyRouteAccounts(
+        markets: OrcaLegacySwap[],
+        intermediateTokenAccounts: PublicKey[]
+    ): AccountMeta[] {
+        const routeAccounts: [PublicKey, boolean][] = [];
+        markets.forEach((market, i) => {
+            routeAccounts.push(
+                [this.dex.orcaLegacy.accounts.vaultOrcaLegacyMarkets, false],
+                [market.accounts.programId, false],
+                [market.accounts.swapProgram, false],
+                [market.accounts.swapAuthority, false],
+                [market.accounts.tokenAccountA, true],
+                [market.accounts.tokenAccountB, true],
+                [market.accounts.poolTokenMint, true],
+                [market.accounts.feeAccount, true]
+            );
+            if (i < markets.length - 1) {
+                routeAccounts.push([intermediateTokenAccounts[i], true]);
+            }
+        });
+        return routeAccounts.map(([pubkey, isWritable]) => ({
+            pubkey,
+            isSigner: false,
+            isWritable,
+        }));
+    }
+
     async emergencyBrake(): Promise<TransactionSignature[]> {
         const value = new anchor.BN(
             (await this.getTotalValue()).lamports.toString()
@@ -1627,6 +3039,10 @@ export class VaultClient {
     getYieldSourceFlags(): YieldSourceFlags {
         return this.vaultState.yieldSourceFlags;
     }
+
+    getWithdrawOnlyFlags(): YieldSourceFlags {
+        return this.vaultState.withdrawOnlyFlags;
+    }
 }
 
 const createAta = (
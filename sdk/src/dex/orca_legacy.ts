@@ -25,6 +25,7 @@ export interface OrcaLegacyAccounts {
     tokenAccountA: PublicKey;
     tokenAccountB: PublicKey;
     vaultOrcaLegacyAccount?: PublicKey;
+    vaultOrcaLegacyMarkets?: PublicKey;
 }
 
 export class OrcaLegacySwap {
@@ -31,6 +31,14 @@ export interface Vault {
     lpTokenSupply: BN;
     vaultPortAdditionalStateBump: number;
     dexStatesBump: number;
+    withdrawOnlyFlags: number;
+    vaultSolendAdditionalStateBump: number;
+    solendObligationEnabled: boolean;
+    realizedLosses: RealizedLosses;
+    insuranceReserve: PublicKey;
+    uncoveredLosses: BN;
+    lastHarvestTimestamp: BN;
+    strategyWeights: StrategyWeights;
 }
 
 export interface VaultPortAdditionalState {
@@ -40,8 +48,27 @@ export interface VaultPortAdditionalState {
     vaultPortSubRewardTokenBump: number;
 }
 
+export interface VaultSolendAdditionalState {
+    vaultSolendObligationBump: number;
+    vaultSolendRewardTokenBump: number;
+    solendRewardTokenOracle: PublicKey;
+    solendRewardDistributor: PublicKey;
+    solendRewardDistributorProgram: PublicKey;
+    rewardRatePerSlot: BN;
+}
+
+export interface VaultRegistry {
+    reserveTokenMint: PublicKey;
+    owner: PublicKey;
+    numVaults: BN;
+    vaults: PublicKey[];
+}
+
 export interface DexStates {
     orcaLegacyAccountsBump: number;
+    whirlpoolAccountsBump: number;
+    orcaLegacyMarketsBump: number;
+    whirlpoolMarketsBump: number;
 }
 
 export interface VaultConfig {
@@ -52,6 +79,26 @@ export interface VaultConfig {
     allocationCapPct?: number;
     rebalanceMode?: { [x: string]: {} };
     strategyType?: { [x: string]: {} };
+    lossHaltBps?: number;
+    insuranceFeePct?: number;
+    insuranceTarget?: BN;
+    idleBufferPct?: number;
+    idleBufferMinPct?: number;
+    idleBufferMaxPct?: number;
+    autoDeployMax?: BN;
+    maxReconcileMove?: BN;
+    minReconcileMove?: BN;
+    minReconcileMoveBps?: number;
+    permissioned?: boolean;
+    perUserDepositCap?: BN;
+    lockupSeconds?: BN;
+    earlyWithdrawFeeBps?: number;
+    swapSlippageBps?: number;
+    oracleMaxConfidenceBps?: number;
+    oracleMaxAge?: BN;
+    minHarvestInterval?: BN;
+    valueRewards?: boolean;
+    rewardValueHaircutBps?: number;
 }
 
 export interface LastUpdate {
@@ -69,6 +116,17 @@ export interface Allocations {
     port: SlotTrackedValue;
 }
 
+export interface StrategyWeights {
+    solend: number;
+    port: number;
+    lastUpdate: LastUpdate;
+}
+
+export interface RealizedLosses {
+    solend: BN;
+    port: BN;
+}
+
 export interface ProposedWeightsBps {
     solend: number;
     port: number;
@@ -82,8 +140,23 @@ export interface RebalanceDataEvent {
 export enum VaultFlags {
     HaltReconciles = 1 << 0,
     HaltRefreshes = 1 << 1,
-    HaltDepositsWithdraws = 1 << 2,
-    HaltAll = HaltReconciles | HaltRefreshes | HaltDepositsWithdraws,
+    HaltDeposits = 1 << 3,
+    HaltWithdraws = 1 << 4,
+    HaltRebalances = 1 << 5,
+    HaltRewardClaims = 1 << 6,
+    HaltSwaps = 1 << 7,
+    HaltDepositsWithdraws = HaltDeposits | HaltWithdraws,
+    HaltOnLoss = HaltReconciles |
+        HaltDeposits |
+        HaltWithdraws |
+        HaltRebalances,
+    HaltAll = HaltReconciles |
+        HaltRefreshes |
+        HaltDeposits |
+        HaltWithdraws |
+        HaltRebalances |
+        HaltRewardClaims |
+        HaltSwaps,
 }
 
 export enum YieldSourceFlags {
@@ -1,9 +1,10 @@
 import { assert } from "chai";
 import * as anchor from "@castlefinance/anchor";
-import { TOKEN_PROGRAM_ID, Token } from "@solana/spl-token";
+import { TOKEN_PROGRAM_ID, Token, u64 } from "@solana/spl-token";
 import {
     Keypair,
     PublicKey,
+    SystemProgram,
     Transaction,
     TransactionSignature,
 } from "@solana/web3.js";
@@ -17,6 +18,7 @@ import {
     ProposedWeightsBps,
     VaultConfig,
     VaultFlags,
+    YieldSourceFlags,
 } from "../sdk/src/index";
 import {
     DeploymentEnvs,
@@ -55,6 +57,8 @@ describe("castle-vault", () => {
     const referralFeeOwner = Keypair.generate().publicKey;
     const vaultDepositCap = 10 * 10 ** 9;
     const vaultAllocationCap = 76;
+    // The cloned pyth prices are never updated on localnet
+    const oracleMaxAge = new anchor.BN(10 * 365 * 24 * 60 * 60);
 
     let reserveToken: Token;
 
@@ -285,21 +289,40 @@ describe("castle-vault", () => {
         );
     }
 
+    /**
+     *
+     * @param nonce if set, the vault is initialized at the PDA derived from it
+     */
     async function initializeVault(
         config: VaultConfig,
         solendAvailable: boolean = true,
-        portAvailable: boolean = true
+        portAvailable: boolean = true,
+        nonce?: anchor.BN
     ) {
-        vaultClient = await VaultClient.initialize(
-            provider,
-            provider.wallet as anchor.Wallet,
-            DeploymentEnvs.devnetStaging,
-            reserveToken.publicKey,
-            owner.publicKey,
-            referralFeeOwner,
-            config,
-            program
-        );
+        config = { oracleMaxAge, ...config };
+        vaultClient =
+            nonce == null
+                ? await VaultClient.initialize(
+                      provider,
+                      provider.wallet as anchor.Wallet,
+                      DeploymentEnvs.devnetStaging,
+                      reserveToken.publicKey,
+                      owner.publicKey,
+                      referralFeeOwner,
+                      config,
+                      program
+                  )
+                : await VaultClient.initializePda(
+                      provider,
+                      provider.wallet as anchor.Wallet,
+                      DeploymentEnvs.devnetStaging,
+                      reserveToken.publicKey,
+                      owner,
+                      referralFeeOwner,
+                      nonce,
+                      config,
+                      program
+                  );
 
         await Promise.all([
             solendAvailable
@@ -688,6 +711,42 @@ describe("castle-vault", () => {
             assert.equal(flags, vaultClient.getHaltFlags());
         });
 
+        it("Halt deposits but keep withdrawals open", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "HaltedVault")
+                .code.toString(16);
+
+            const qty = 1000;
+            await mintReserveToken(userReserveTokenAccount, 2 * qty);
+            await depositToVault(qty);
+
+            const tx = await vaultClient.updateHaltFlags(
+                owner,
+                VaultFlags.HaltDeposits
+            );
+            await provider.connection.confirmTransaction(tx, "singleGossip");
+
+            try {
+                await depositToVault(qty);
+                assert.fail("Deposit should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+
+            const userLpBalance = await getUserLpTokenBalance();
+            await withdrawFromVault(userLpBalance);
+            assert.equal(await getUserLpTokenBalance(), 0);
+
+            await vaultClient.updateHaltFlags(owner, 0);
+        });
+
         it("Update yield source flags", async function () {
             const flags = 1;
             const tx = await vaultClient.updateYieldSourceFlags(owner, flags);
@@ -706,7 +765,7 @@ describe("castle-vault", () => {
             const oldFlags = vaultClient.getHaltFlags();
 
             try {
-                const tx = await vaultClient.updateHaltFlags(owner, 23);
+                const tx = await vaultClient.updateHaltFlags(owner, 1 << 8);
                 await provider.connection.confirmTransaction(
                     tx,
                     "singleGossip"
@@ -974,104 +1033,167 @@ describe("castle-vault", () => {
         });
     }
 
-    async function sleep(t: number) {
-        return new Promise((res) => setTimeout(res, t));
-    }
-
-    function testFees(
-        feeCarryBps: number = 0,
-        feeMgmtBps: number = 0,
-        referalPct: number = 0
-    ) {
-        it("Collect fees", async function () {
-            const qty1 = 5.47 * 10 ** 9;
-            await mintReserveToken(userReserveTokenAccount, qty1);
-            const txs1 = await depositToVault(qty1);
-            const slots0 = await fetchSlots(txs1);
+    function testRefreshAll() {
+        const depositQty = 1000001;
 
-            const vaultTotalValue = new anchor.BN(await getVaultTotalValue());
-            const lpTokenSupply = new anchor.BN(await getLpTokenSupply());
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
 
-            await sleep(1000);
+        it("Refresh all yield sources in one instruction", async function () {
+            const txSig = await vaultClient.refreshAll();
+            const [slot] = await fetchSlots([txSig]);
 
-            // This is needed to trigger refresh and fee collection
-            // Consider adding an API to client library to trigger refresh
-            const qty2 = 10;
-            await mintReserveToken(userReserveTokenAccount, qty2);
-            const txs2 = await depositToVault(qty2);
-            const slots1 = await fetchSlots(txs2);
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            assert.equal(vaultState.value.lastUpdate.slot.toNumber(), slot);
+            assert.equal(
+                vaultState.actualAllocations.solend.lastUpdate.slot.toNumber(),
+                slot
+            );
+            assert.equal(
+                vaultState.actualAllocations.port.lastUpdate.slot.toNumber(),
+                slot
+            );
 
-            const expectedFees = await calculateFees(
-                vaultTotalValue,
-                lpTokenSupply,
-                slots0[slots0.length - 1],
-                slots1[slots1.length - 1],
-                feeCarryBps,
-                feeMgmtBps,
-                referalPct
+            const maxDiffAllowed = 1;
+            assert.isAtMost(
+                Math.abs(vaultState.value.value.toNumber() - depositQty),
+                maxDiffAllowed
+            );
+            assert.isAtMost(
+                Math.abs(
+                    vaultState.actualAllocations.solend.value.toNumber() +
+                        vaultState.actualAllocations.port.value.toNumber() -
+                        depositQty
+                ),
+                maxDiffAllowed
             );
+        });
+    }
 
-            const referralAccountInfo =
-                await vaultClient.getReferralFeeReceiverAccountInfo();
-            const feeAccountInfo =
-                await vaultClient.getFeeReceiverAccountInfo();
+    function testDepositAndWithdrawWithRefresh() {
+        const depositQty = 1000001;
 
-            const actualReferralFees = referralAccountInfo.amount.toNumber();
-            const actualMgmtFees = feeAccountInfo.amount.toNumber();
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
 
-            // TODO why are management fees specifically off by 2 tokens?
-            const maxDiffAllowed = 2;
+        it("Deposit with refresh in a single instruction", async function () {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            const userLpBalanceBefore = await getUserLpTokenBalance();
+
+            await provider.sendAndConfirm(
+                new Transaction().add(
+                    await vaultClient.getDepositWithRefreshIx(
+                        new anchor.BN(depositQty),
+                        wallet.publicKey,
+                        await vaultClient.getUserLpTokenAccount(
+                            wallet.publicKey
+                        ),
+                        userReserveTokenAccount
+                    )
+                )
+            );
 
+            const userLpBalanceAfter = await getUserLpTokenBalance();
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    reserveToken.publicKey,
+                    userReserveTokenAccount
+                ),
+                0
+            );
+            assert.isAtLeast(
+                userLpBalanceAfter - userLpBalanceBefore,
+                depositQty - 1
+            );
             assert.isAtMost(
-                Math.abs(actualMgmtFees - expectedFees.primary.toNumber()),
-                maxDiffAllowed
+                Math.abs((await getVaultTotalValue()) - 2 * depositQty),
+                1
+            );
+        });
+
+        it("Withdraw with refresh in a single instruction", async function () {
+            const userLpBalanceBefore = await getUserLpTokenBalance();
+            const withdrawQty = Math.floor(userLpBalanceBefore / 4);
+
+            await provider.sendAndConfirm(
+                new Transaction().add(
+                    await vaultClient.getWithdrawWithRefreshIx(
+                        new anchor.BN(withdrawQty),
+                        wallet.publicKey,
+                        await vaultClient.getUserLpTokenAccount(
+                            wallet.publicKey
+                        ),
+                        userReserveTokenAccount
+                    )
+                )
+            );
+
+            assert.equal(
+                await getUserLpTokenBalance(),
+                userLpBalanceBefore - withdrawQty
             );
             assert.isAtMost(
-                Math.abs(actualReferralFees - expectedFees.referral.toNumber()),
-                maxDiffAllowed
+                Math.abs(
+                    (await getSplTokenAccountBalance(
+                        reserveToken.publicKey,
+                        userReserveTokenAccount
+                    )) - withdrawQty
+                ),
+                1
             );
         });
+    }
 
-        it("Update fee rates", async function () {
-            const oldConfig = vaultClient.getVaultConfig();
+    function testRealizedLosses() {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
+
+        it("Reconciles are not recognized as losses", async function () {
+            const userLpBalance = await getUserLpTokenBalance();
+            await withdrawFromVault(userLpBalance / 2);
+            await vaultClient.refreshAll();
+
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            assert.equal(vaultState.realizedLosses.solend.toNumber(), 0);
+            assert.equal(vaultState.realizedLosses.port.toNumber(), 0);
+            assert.equal(vaultState.uncoveredLosses.toNumber(), 0);
+            assert.equal(vaultClient.getHaltFlags(), 0);
+        });
+
+        it("Update loss halt threshold", async function () {
             const newConfig = {
-                ...oldConfig,
-                feeCarryBps: oldConfig.feeCarryBps / 2,
-                feeMgmtBps: oldConfig.feeMgmtBps / 2,
-                referralFeePct: oldConfig.referralFeePct / 2,
+                ...vaultClient.getVaultConfig(),
+                lossHaltBps: 500,
             };
             const txSig = await vaultClient.updateConfig(owner, newConfig);
             await provider.connection.confirmTransaction(txSig, "singleGossip");
 
             await vaultClient.reload();
-            assert.equal(
-                newConfig.feeCarryBps,
-                vaultClient.getCarryFee().asBps().toNumber()
-            );
-            assert.equal(
-                newConfig.feeMgmtBps,
-                vaultClient.getManagementFee().asBps().toNumber()
-            );
-            assert.equal(
-                newConfig.referralFeePct,
-                vaultClient.getReferralFeeSplit().asPercent().toNumber()
-            );
+            assert.equal(vaultClient.getVaultConfig().lossHaltBps, 500);
         });
 
-        it("Reject invalid fee rates", async function () {
+        it("Reject loss halt threshold over 100%", async function () {
             suppressLogs();
 
             const errorCode = program.idl.errors
-                .find((e) => e.name == "InvalidReferralFeeConfig")
+                .find((e) => e.name == "InvalidArgument")
                 .code.toString(16);
 
             const oldConfig = vaultClient.getVaultConfig();
-            const newConfig = {
-                ...oldConfig,
-                feeCarryBps: oldConfig.feeCarryBps / 2,
-                feeMgmtBps: oldConfig.feeMgmtBps / 2,
-                referralFeePct: 110,
-            };
+            const newConfig = { ...oldConfig, lossHaltBps: 10001 };
             try {
                 const txSig = await vaultClient.updateConfig(owner, newConfig);
                 await provider.connection.confirmTransaction(
@@ -1088,99 +1210,1392 @@ describe("castle-vault", () => {
 
             await vaultClient.reload();
             assert.equal(
-                oldConfig.feeCarryBps,
-                vaultClient.getCarryFee().asBps().toNumber()
-            );
-            assert.equal(
-                oldConfig.feeMgmtBps,
-                vaultClient.getManagementFee().asBps().toNumber()
-            );
-            assert.equal(
-                oldConfig.referralFeePct,
-                vaultClient.getReferralFeeSplit().asPercent().toNumber()
+                vaultClient.getVaultConfig().lossHaltBps,
+                oldConfig.lossHaltBps
             );
 
             restoreLogs();
         });
     }
 
-    function testPortRewardClaiming(subReward: boolean) {
-        const depositQty = 3025004087;
+    function testInsuranceReserve(insuranceTarget: number) {
+        let insuranceReserve: PublicKey;
 
-        before(async () => {
+        it("Initialize the insurance reserve", async function () {
+            await vaultClient.initializeInsuranceReserve(
+                provider.wallet as anchor.Wallet,
+                owner
+            );
+
+            insuranceReserve = vaultClient.getVaultState().insuranceReserve;
+            assert.isFalse(insuranceReserve.equals(PublicKey.default));
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    reserveToken.publicKey,
+                    insuranceReserve
+                ),
+                0
+            );
+        });
+
+        it("Collect a share of fees into the insurance reserve", async function () {
+            const depositQty = 5.47 * 10 ** 9;
             await mintReserveToken(userReserveTokenAccount, depositQty);
             await depositToVault(depositQty);
-        });
 
-        it("Stake port LP token when rebalancing", async () => {
-            await performRebalance({
-                solend: 0,
-                port: 10000,
-            });
+            await sleep(1000);
+            await vaultClient.refreshAll();
 
-            const maxDiffAllowed = 1;
-            const totalValue = await getVaultTotalValue();
-            assert.isAtMost(
-                Math.abs(totalValue - Math.floor(depositQty)),
-                maxDiffAllowed
-            );
-            const portValue = (
-                await vaultClient.getVaultPortLpTokenAccountValue()
-            ).lamports.toNumber();
-            assert.isAtMost(
-                Math.abs(portValue - Math.floor(depositQty)),
-                maxDiffAllowed
+            const insuranceBalance = await getSplTokenAccountBalance(
+                reserveToken.publicKey,
+                insuranceReserve
             );
+            assert.isAbove(insuranceBalance, insuranceTarget);
+            const feeAccountInfo =
+                await vaultClient.getFeeReceiverAccountInfo();
+            assert.isAbove(feeAccountInfo.amount.toNumber(), 0);
 
-            // Check that all allocations to port are staked for earning rewards
-            const stakingAccountRaw = await provider.connection.getAccountInfo(
-                new PublicKey(port.accounts.vaultPortStakeAccount)
-            );
-            const stakingAccount = StakeAccount.fromRaw({
-                pubkey: port.accounts.vaultPortStakeAccount,
-                account: stakingAccountRaw,
-            });
-            const reserve = await port.client.getReserve(port.accounts.reserve);
-            const exchangeRate = reserve.getExchangeRatio();
-            const stakedTokenValue = AssetPrice.of(
-                MintId.of(port.accounts.stakingRewardTokenMint),
-                stakingAccount.getDepositAmount().toU64().toNumber()
-            ).divide(exchangeRate.getUnchecked());
-            assert.isAtMost(
-                Math.abs(stakedTokenValue.getRaw().toNumber() - depositQty),
-                maxDiffAllowed
+            // Insurance fees are taken out of the vault value
+            await vaultClient.reload();
+            assert.equal(
+                vaultClient.getVaultState().value.value.toNumber(),
+                depositQty - insuranceBalance
             );
         });
 
-        it("Withdraws", async function () {
-            const oldVaultValue = await getVaultTotalValue();
-            const oldLpTokenSupply = await getLpTokenSupply();
-
-            const withdrawQty = 922051;
-            await withdrawFromVault(withdrawQty);
-
-            const newUserReserveBalance = await getUserReserveTokenBalance();
-            const newVaultValue = await getVaultTotalValue();
-            const newLpTokenSupply = await getLpTokenSupply();
+        it("Reject unauthorized insurance surplus withdrawal", async function () {
+            suppressLogs();
 
-            // Make sure that the correct amount is un-staked when the user withdraws
-            let maxDiffAllowed = 3;
-            const stakingAccountRaw = await provider.connection.getAccountInfo(
-                new PublicKey(port.accounts.vaultPortStakeAccount)
+            const errorCode = "0x7d1";
+            const noPermissionUser = Keypair.generate();
+            const destination = await reserveToken.createAccount(
+                noPermissionUser.publicKey
+            );
+            const insuranceBalance = await getSplTokenAccountBalance(
+                reserveToken.publicKey,
+                insuranceReserve
             );
-            const stakingAccount = StakeAccount.fromRaw({
-                pubkey: port.accounts.vaultPortStakeAccount,
-                account: stakingAccountRaw,
-            });
-            const reserve = await port.client.getReserve(port.accounts.reserve);
-            const exchangeRate = reserve.getExchangeRatio();
-            const stakedTokenValue = AssetPrice.of(
-                MintId.of(port.accounts.stakingRewardTokenMint),
-                stakingAccount.getDepositAmount().toU64().toNumber()
-            ).divide(exchangeRate.getUnchecked());
 
-            assert.isAtMost(
-                Math.abs(
+            try {
+                const txSig = await vaultClient.withdrawInsuranceSurplus(
+                    noPermissionUser,
+                    destination
+                );
+                await provider.connection.confirmTransaction(
+                    txSig,
+                    "singleGossip"
+                );
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    reserveToken.publicKey,
+                    insuranceReserve
+                ),
+                insuranceBalance
+            );
+
+            restoreLogs();
+        });
+
+        it("Withdraw insurance surplus above the target", async function () {
+            const destination = await reserveToken.createAccount(
+                owner.publicKey
+            );
+            const insuranceBalance = await getSplTokenAccountBalance(
+                reserveToken.publicKey,
+                insuranceReserve
+            );
+
+            const txSig = await vaultClient.withdrawInsuranceSurplus(
+                owner,
+                destination
+            );
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    reserveToken.publicKey,
+                    insuranceReserve
+                ),
+                insuranceTarget
+            );
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    reserveToken.publicKey,
+                    destination
+                ),
+                insuranceBalance - insuranceTarget
+            );
+        });
+    }
+
+    function testAutoDeploy(autoDeployMax: number) {
+        const initialDepositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, initialDepositQty);
+            await depositToVault(initialDepositQty);
+            await performRebalance();
+        });
+
+        it("Deploy a deposit into the most underweight yield source", async function () {
+            const depositQty = 100000;
+            const vaultReserveBefore = await getVaultReserveTokenBalance();
+            await vaultClient.reload();
+            const portValueBefore = vaultClient
+                .getVaultState()
+                .actualAllocations.port.value.toNumber();
+
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+
+            // Both yield sources are short of half the deposit, ties go to port
+            const deployedQty = depositQty / 2;
+            await vaultClient.reload();
+            assert.equal(
+                await getVaultReserveTokenBalance(),
+                vaultReserveBefore + depositQty - deployedQty
+            );
+            assert.isAtMost(
+                Math.abs(
+                    vaultClient
+                        .getVaultState()
+                        .actualAllocations.port.value.toNumber() -
+                        portValueBefore -
+                        deployedQty
+                ),
+                1
+            );
+        });
+
+        it("Leave a deposit above the threshold idle", async function () {
+            const depositQty = autoDeployMax + 1;
+            const vaultReserveBefore = await getVaultReserveTokenBalance();
+
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+
+            assert.equal(
+                await getVaultReserveTokenBalance(),
+                vaultReserveBefore + depositQty
+            );
+        });
+    }
+
+    function testReconcileAll() {
+        const depositQty = 1000001;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+        });
+
+        async function assertAllocations(solendQty: number, portQty: number) {
+            const solendValue = (
+                await vaultClient.getVaultSolendLpTokenAccountValue()
+            ).lamports.toNumber();
+            const portValue = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+
+            // Allow for rounding in the exchange rates of the yield sources
+            const maxDiffAllowed = 2;
+            assert.isAtMost(Math.abs(solendValue - solendQty), maxDiffAllowed);
+            assert.isAtMost(Math.abs(portValue - portQty), maxDiffAllowed);
+
+            // Targets are reset once reached
+            const targets = vaultClient.getVaultState().targetAllocations;
+            assert.equal(targets.solend.value.toNumber(), 0);
+            assert.equal(targets.port.value.toNumber(), 0);
+        }
+
+        it("Reconcile every yield source in one instruction", async function () {
+            await performRebalance(undefined, true);
+            await vaultClient.reconcileAll();
+
+            await vaultClient.reload();
+            await assertAllocations(depositQty / 2, depositQty / 2);
+        });
+
+        it("Redeem before depositing in one instruction", async function () {
+            // Only port has a non-zero APY
+            const txSig = await vaultClient.updateConfig(owner, {
+                ...vaultClient.getVaultConfig(),
+                strategyType: { [StrategyTypes.maxYield]: {} },
+            });
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+            await vaultClient.reload();
+
+            await performRebalance(undefined, true);
+            await vaultClient.reconcileAll();
+
+            await vaultClient.reload();
+            await assertAllocations(0, depositQty);
+        });
+    }
+
+    function testPartialReconciles(maxReconcileMove: number) {
+        const depositQty = 1000001;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+        });
+
+        async function assertAllocations(qty: number) {
+            const solendValue = (
+                await vaultClient.getVaultSolendLpTokenAccountValue()
+            ).lamports.toNumber();
+            const portValue = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+
+            const maxDiffAllowed = 2;
+            assert.isAtMost(Math.abs(solendValue - qty), maxDiffAllowed);
+            assert.isAtMost(Math.abs(portValue - qty), maxDiffAllowed);
+        }
+
+        it("Limit each reconcile to the max move", async function () {
+            await performRebalance();
+
+            await vaultClient.reload();
+            await assertAllocations(maxReconcileMove);
+
+            // The rest of the move stays pending
+            const targets = vaultClient.getVaultState().targetAllocations;
+            assert.isAbove(targets.solend.value.toNumber(), maxReconcileMove);
+            assert.isAbove(targets.port.value.toNumber(), maxReconcileMove);
+        });
+
+        it("Step through the pending target over several reconciles", async function () {
+            const targetQty = Math.floor(depositQty / 2);
+
+            await vaultClient.reconcileAll();
+            await vaultClient.reload();
+            await assertAllocations(2 * maxReconcileMove);
+
+            await vaultClient.reconcileAll();
+            await vaultClient.reload();
+            await assertAllocations(targetQty);
+
+            // Targets are reset once reached
+            const targets = vaultClient.getVaultState().targetAllocations;
+            assert.equal(targets.solend.value.toNumber(), 0);
+            assert.equal(targets.port.value.toNumber(), 0);
+        });
+    }
+
+    function testDustReconciles(minReconcileMove: number) {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
+
+        async function testSkippedMove(qty: number) {
+            const solendValueBefore = (
+                await vaultClient.getVaultSolendLpTokenAccountValue()
+            ).lamports.toNumber();
+            const portValueBefore = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+            const vaultReserveBefore = await getVaultReserveTokenBalance();
+
+            await mintReserveToken(userReserveTokenAccount, qty);
+            await depositToVault(qty);
+            await performRebalance();
+
+            const solendValueAfter = (
+                await vaultClient.getVaultSolendLpTokenAccountValue()
+            ).lamports.toNumber();
+            const portValueAfter = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+
+            // Allow for interest accrued in the meantime
+            const maxDiffAllowed = 1;
+            assert.isAtMost(
+                Math.abs(solendValueAfter - solendValueBefore),
+                maxDiffAllowed
+            );
+            assert.isAtMost(
+                Math.abs(portValueAfter - portValueBefore),
+                maxDiffAllowed
+            );
+            assert.equal(
+                await getVaultReserveTokenBalance(),
+                vaultReserveBefore + qty
+            );
+
+            // Skipped moves count as reaching the target
+            await vaultClient.reload();
+            const targets = vaultClient.getVaultState().targetAllocations;
+            assert.equal(targets.solend.value.toNumber(), 0);
+            assert.equal(targets.port.value.toNumber(), 0);
+        }
+
+        it("Skip moves below the min reconcile move", async function () {
+            // Each yield source is short of half the deposit
+            await testSkippedMove(minReconcileMove);
+        });
+
+        it("Skip moves below the min share of the allocation", async function () {
+            const txSig = await vaultClient.updateConfig(owner, {
+                ...vaultClient.getVaultConfig(),
+                minReconcileMove: new anchor.BN(0),
+                minReconcileMoveBps: 100,
+            });
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+            await vaultClient.reload();
+
+            // 1% of each allocation is about 5000
+            await testSkippedMove(8000);
+        });
+    }
+
+    function testWhirlpoolRegistry() {
+        const whirlpoolProgram = new PublicKey(
+            "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc"
+        );
+
+        it("Initialize the whirlpool registry", async function () {
+            await vaultClient.initializeDexWhirlpool(wallet, owner);
+
+            const [whirlpoolAccounts] = await PublicKey.findProgramAddress(
+                [
+                    vaultClient.vaultId.toBuffer(),
+                    anchor.utils.bytes.utf8.encode("dex_whirlpool"),
+                ],
+                program.programId
+            );
+            const registry = await program.account.whirlpoolAccounts.fetch(
+                whirlpoolAccounts
+            );
+            assert.isTrue(registry.whirlpoolProgram.equals(whirlpoolProgram));
+        });
+
+        it("Reject a market that is not a whirlpool", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "InvalidAccount")
+                .code.toString(16);
+
+            try {
+                await vaultClient.addWhirlpoolMarket(
+                    owner,
+                    orca.accounts.swapProgram,
+                    0,
+                    port.accounts.stakingRewardTokenMint
+                );
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        });
+    }
+
+    function testDexMarketRegistry() {
+        let market: OrcaLegacySwap;
+        let otherMint: PublicKey;
+
+        before(async function () {
+            market = await OrcaLegacySwap.initialize(
+                provider,
+                wallet.payer,
+                new Token(
+                    program.provider.connection,
+                    port.accounts.stakingRewardTokenMint,
+                    TOKEN_PROGRAM_ID,
+                    owner
+                ),
+                reserveToken,
+                owner,
+                owner
+            );
+            market.accounts.marketId = 1;
+
+            otherMint = (
+                await Token.createMint(
+                    program.provider.connection,
+                    wallet.payer,
+                    owner.publicKey,
+                    null,
+                    6,
+                    TOKEN_PROGRAM_ID
+                )
+            ).publicKey;
+        });
+
+        async function fetchMarket(marketId: number) {
+            return (
+                await program.account.dexMarkets.fetch(
+                    orca.accounts.vaultOrcaLegacyMarkets
+                )
+            ).markets[marketId];
+        }
+
+        async function expectDexMarketError(
+            errorName: string,
+            update: () => Promise<unknown>
+        ) {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == errorName)
+                .code.toString(16);
+
+            try {
+                await update();
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        }
+
+        it("Reject registering a market with other mints", async function () {
+            await expectDexMarketError("InvalidDexMarketMints", () =>
+                vaultClient.addOrcaLegacyMarket(
+                    wallet,
+                    owner,
+                    market,
+                    otherMint
+                )
+            );
+
+            const entry = await fetchMarket(market.accounts.marketId);
+            assert.isTrue(entry.market.equals(PublicKey.default));
+        });
+
+        it("Reject replacing a market with other mints", async function () {
+            const entryBefore = await fetchMarket(orca.accounts.marketId);
+
+            await expectDexMarketError("InvalidDexMarketMints", () =>
+                vaultClient.replaceOrcaLegacyMarket(
+                    owner,
+                    orca,
+                    port.accounts.stakingRewardTokenMint,
+                    otherMint
+                )
+            );
+
+            const entryAfter = await fetchMarket(orca.accounts.marketId);
+            assert.isTrue(entryAfter.market.equals(entryBefore.market));
+        });
+
+        it("Replace a registered market", async function () {
+            const replacement = await OrcaLegacySwap.initialize(
+                provider,
+                wallet.payer,
+                new Token(
+                    program.provider.connection,
+                    port.accounts.stakingRewardTokenMint,
+                    TOKEN_PROGRAM_ID,
+                    owner
+                ),
+                reserveToken,
+                owner,
+                owner
+            );
+            replacement.accounts.marketId = orca.accounts.marketId;
+            await vaultClient.replaceOrcaLegacyMarket(
+                owner,
+                replacement,
+                port.accounts.stakingRewardTokenMint
+            );
+
+            const entry = await fetchMarket(orca.accounts.marketId);
+            assert.isTrue(
+                entry.market.equals(replacement.accounts.swapProgram)
+            );
+
+            await vaultClient.replaceOrcaLegacyMarket(
+                owner,
+                orca,
+                port.accounts.stakingRewardTokenMint
+            );
+        });
+
+        it("Remove a market and reuse its market id", async function () {
+            const marketId = market.accounts.marketId;
+            await vaultClient.addOrcaLegacyMarket(
+                wallet,
+                owner,
+                market,
+                port.accounts.stakingRewardTokenMint
+            );
+            await expectDexMarketError("DexMarketAlreadyRegistered", () =>
+                vaultClient.addOrcaLegacyMarket(
+                    wallet,
+                    owner,
+                    market,
+                    port.accounts.stakingRewardTokenMint
+                )
+            );
+
+            await vaultClient.removeOrcaLegacyMarket(owner, marketId);
+
+            const removed = await fetchMarket(marketId);
+            assert.isTrue(removed.market.equals(PublicKey.default));
+            assert.isTrue(removed.inputMint.equals(PublicKey.default));
+            assert.isTrue(removed.outputMint.equals(PublicKey.default));
+
+            // The freed market id can be registered again
+            await vaultClient.addOrcaLegacyMarket(
+                wallet,
+                owner,
+                market,
+                port.accounts.stakingRewardTokenMint
+            );
+
+            const entry = await fetchMarket(marketId);
+            assert.isTrue(entry.market.equals(market.accounts.swapProgram));
+        });
+    }
+
+    function testRewardValuation() {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
+
+        // Vault value, and value of the reserve tokens held and lent out
+        async function refreshValues(): Promise<[number, number]> {
+            await vaultClient.refreshAll();
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            return [
+                vaultState.value.value.toNumber(),
+                (await getVaultReserveTokenBalance()) +
+                    vaultState.actualAllocations.port.value.toNumber(),
+            ];
+        }
+
+        it("Count unsold rewards in the vault value", async function () {
+            const rewardAmount = 1000000;
+            await new Token(
+                program.provider.connection,
+                port.accounts.stakingRewardTokenMint,
+                TOKEN_PROGRAM_ID,
+                owner
+            ).mintTo(
+                port.accounts.vaultPortRewardToken,
+                owner,
+                [],
+                rewardAmount
+            );
+
+            const [vaultValue, assetsValue] = await refreshValues();
+            assert.isAbove(vaultValue, assetsValue);
+        });
+
+        it("Exclude rewards when reward valuation is disabled", async function () {
+            const txSig = await vaultClient.updateConfig(owner, {
+                ...vaultClient.getVaultConfig(),
+                valueRewards: false,
+            });
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+            await vaultClient.reload();
+
+            const [vaultValue, assetsValue] = await refreshValues();
+            assert.equal(vaultValue, assetsValue);
+        });
+    }
+
+    function testSubRewardRebalance() {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+        });
+
+        it("Reject a rebalance with the wrong sub-reward oracle", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "InvalidAccount")
+                .code.toString(16);
+
+            const preRefresh = (await vaultClient.getPreRefreshTxs()).map(
+                (tx) => {
+                    return { tx: tx, signers: [] };
+                }
+            );
+            const rebalanceTx = await vaultClient.getRebalanceTx({
+                solend: 0,
+                port: 10000,
+            });
+            const rebalanceIx =
+                rebalanceTx.instructions[rebalanceTx.instructions.length - 1];
+            rebalanceIx.keys
+                .filter((k) =>
+                    k.pubkey.equals(port.accounts.stakingSubRewardOracle)
+                )
+                .forEach((k) => (k.pubkey = port.accounts.stakingRewardOracle));
+
+            try {
+                await provider.sendAll([
+                    ...preRefresh,
+                    { tx: rebalanceTx, signers: [] },
+                ]);
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        });
+
+        it("Rebalance with the sub-reward APR", async function () {
+            await performRebalance({
+                solend: 0,
+                port: 10000,
+            });
+
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            assert.equal(
+                vaultState.targetAllocations.port.value.toNumber(),
+                depositQty
+            );
+            const portValue = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+            assert.isAtMost(Math.abs(portValue - depositQty), 1);
+        });
+    }
+
+    function testWithdrawOnly() {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+            await performRebalance();
+        });
+
+        async function updateWithdrawOnlyFlags(flags: number) {
+            const txSig = await vaultClient.updateWithdrawOnlyFlags(
+                owner,
+                flags
+            );
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+            await vaultClient.reload();
+        }
+
+        it("Keep counting the value of a withdraw-only yield source", async function () {
+            await updateWithdrawOnlyFlags(YieldSourceFlags.Port);
+            assert.equal(
+                vaultClient.getWithdrawOnlyFlags(),
+                YieldSourceFlags.Port
+            );
+
+            await vaultClient.refreshAll();
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            assert.isAbove(
+                vaultState.actualAllocations.port.value.toNumber(),
+                0
+            );
+            assert.isAtMost(
+                Math.abs(vaultState.value.value.toNumber() - depositQty),
+                1
+            );
+        });
+
+        it("Redeem everything from a withdraw-only yield source", async function () {
+            await performRebalance(undefined, true);
+            await vaultClient.reload();
+            const targets = vaultClient.getVaultState().targetAllocations;
+            assert.equal(targets.port.value.toNumber(), 0);
+            assert.isAtMost(
+                Math.abs(targets.solend.value.toNumber() - depositQty),
+                1
+            );
+
+            await vaultClient.reconcileAll();
+
+            const maxDiffAllowed = 2;
+            const solendValue = (
+                await vaultClient.getVaultSolendLpTokenAccountValue()
+            ).lamports.toNumber();
+            const portValue = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+            assert.isAtMost(Math.abs(solendValue - depositQty), maxDiffAllowed);
+            assert.isAtMost(portValue, maxDiffAllowed);
+        });
+
+        it("Reject deposits into a withdraw-only yield source", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "WithdrawOnlyYieldSource")
+                .code.toString(16);
+
+            // Set a port target while port is active, then wind it down
+            await updateWithdrawOnlyFlags(0);
+            await performRebalance(undefined, true);
+            await updateWithdrawOnlyFlags(YieldSourceFlags.Port);
+
+            try {
+                await vaultClient.reconcileAll();
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        });
+    }
+
+    function testMigrate() {
+        it("Leave an up-to-date vault unchanged", async function () {
+            await vaultClient.reload();
+            const stateBefore = vaultClient.getVaultState();
+            const lenBefore = (
+                await provider.connection.getAccountInfo(vaultClient.vaultId)
+            ).data.length;
+
+            await vaultClient.migrate(wallet, owner);
+
+            const stateAfter = vaultClient.getVaultState();
+            const lenAfter = (
+                await provider.connection.getAccountInfo(vaultClient.vaultId)
+            ).data.length;
+            assert.equal(lenAfter, lenBefore);
+            assert.deepEqual(stateAfter.version, stateBefore.version);
+            assert.equal(stateAfter.haltFlags, stateBefore.haltFlags);
+            assert.equal(
+                stateAfter.lpTokenSupply.toNumber(),
+                stateBefore.lpTokenSupply.toNumber()
+            );
+            assert.equal(
+                stateAfter.config.swapSlippageBps,
+                stateBefore.config.swapSlippageBps
+            );
+        });
+
+        it("Reject a vault older than the supported version", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "UnsupportedVaultVersion")
+                .code.toString(16);
+
+            // Loaded into the test validator from tests/fixtures
+            const oldVault = new PublicKey(
+                "9hSR6S7WPtxmTojgo6GG3k4yDPecgJY292j7xrsUGWBu"
+            );
+            const oldVaultOwner = Keypair.fromSeed(new Uint8Array(32).fill(1));
+
+            try {
+                await provider.sendAndConfirm(
+                    new Transaction().add(
+                        await program.methods
+                            .updateHaltFlags(0)
+                            .accounts({
+                                vault: oldVault,
+                                owner: oldVaultOwner.publicKey,
+                            })
+                            .instruction()
+                    ),
+                    [oldVaultOwner]
+                );
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        });
+    }
+
+    function testDepositors(perUserDepositCap: number) {
+        async function expectDepositError(qty: number, errorName: string) {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == errorName)
+                .code.toString(16);
+
+            await mintReserveToken(userReserveTokenAccount, qty);
+            try {
+                await depositToVault(qty);
+                assert.fail("Deposit should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+        }
+
+        it("Reject deposits from a wallet that is not allowlisted", async function () {
+            await expectDepositError(1000, "DepositorNotInitialized");
+
+            await vaultClient.initializeDepositor(wallet);
+            await expectDepositError(1000, "DepositorNotAllowed");
+
+            assert.equal(await getUserLpTokenBalance(), 0);
+        });
+
+        it("Enforce the per-user deposit cap", async function () {
+            const txSig = await vaultClient.addDepositor(
+                owner,
+                wallet.publicKey
+            );
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            await mintReserveToken(userReserveTokenAccount, perUserDepositCap);
+            await depositToVault(perUserDepositCap);
+            assert.equal(await getUserLpTokenBalance(), perUserDepositCap);
+
+            await expectDepositError(1, "UserDepositCapError");
+        });
+
+        it("Block deposits once a depositor is removed", async function () {
+            const txSig = await vaultClient.removeDepositor(
+                owner,
+                wallet.publicKey
+            );
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            const depositor = await program.account.depositor.fetch(
+                await vaultClient.getDepositorAddress(wallet.publicKey)
+            );
+            assert.isFalse(depositor.allowed);
+
+            await expectDepositError(1000, "DepositorNotAllowed");
+
+            // Removed depositors can still withdraw
+            const userLpBalance = await getUserLpTokenBalance();
+            await withdrawFromVault(userLpBalance);
+            assert.equal(await getUserLpTokenBalance(), 0);
+        });
+    }
+
+    function testEarlyWithdrawFee(earlyWithdrawFeeBps: number) {
+        const depositQty = 1000000;
+
+        before(async () => {
+            await vaultClient.initializeDepositor(wallet);
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+        });
+
+        it("Charge the early withdrawal fee during the lockup", async function () {
+            const vaultReserveBefore = await getVaultReserveTokenBalance();
+            const userReserveBefore = await getReserveTokenBalance(
+                userReserveTokenAccount
+            );
+            const withdrawQty = (await getUserLpTokenBalance()) / 2;
+
+            const txSigs = await withdrawFromVault(withdrawQty);
+            const tx = await provider.connection.getTransaction(
+                txSigs[txSigs.length - 1],
+                { commitment: "confirmed" }
+            );
+            let earlyWithdrawFee: number;
+            const parser = new anchor.EventParser(
+                program.programId,
+                program.coder
+            );
+            parser.parseLogs(tx.meta.logMessages, (event) => {
+                if (event.name == "WithdrawEvent") {
+                    const fee = event.data.earlyWithdrawFee as anchor.BN;
+                    earlyWithdrawFee = fee.toNumber();
+                }
+            });
+
+            // Every lp token is worth one reserve token
+            assert.isAbove(earlyWithdrawFee, 0);
+            assert.equal(
+                earlyWithdrawFee,
+                Math.floor((withdrawQty * earlyWithdrawFeeBps) / 10000)
+            );
+
+            // The fee stays in the vault
+            assert.equal(
+                await getReserveTokenBalance(userReserveTokenAccount),
+                userReserveBefore + withdrawQty - earlyWithdrawFee
+            );
+            assert.equal(
+                await getVaultReserveTokenBalance(),
+                vaultReserveBefore - withdrawQty + earlyWithdrawFee
+            );
+        });
+    }
+
+    const solendRewardDistributorProgram = new PublicKey(
+        "MRKGLMizK9XSTaD1d1jbVkdHZbQVCSnPpYiTw9aKQv8"
+    );
+
+    // The distributor state is only read by the distributor program
+    // when claiming, the vault only checks which program owns it
+    async function createSolendRewardDistributor(
+        programId: PublicKey = solendRewardDistributorProgram
+    ): Promise<PublicKey> {
+        const distributor = Keypair.generate();
+        const lamports =
+            await provider.connection.getMinimumBalanceForRentExemption(0);
+        const tx = new Transaction().add(
+            SystemProgram.createAccount({
+                fromPubkey: wallet.publicKey,
+                newAccountPubkey: distributor.publicKey,
+                lamports,
+                space: 0,
+                programId,
+            })
+        );
+        await provider.sendAndConfirm(tx, [distributor]);
+        return distributor.publicKey;
+    }
+
+    function testSolendObligation() {
+        it("Hold solend lp tokens in the obligation", async function () {
+            await vaultClient.reload();
+            const vaultState = vaultClient.getVaultState();
+            assert.isTrue(vaultState.solendObligationEnabled);
+
+            // Deposits and redeems go through the obligation,
+            // so the vault lp token account is left empty
+            const solendLpTokenAmount = await getSplTokenAccountBalance(
+                solend.accounts.collateralMint,
+                vaultState.vaultSolendLpToken
+            );
+            assert.equal(solendLpTokenAmount, 0);
+        });
+
+        it("Sell Solend reward", async function () {
+            const [vaultSolendRewardToken] = await PublicKey.findProgramAddress(
+                [
+                    vaultClient.vaultId.toBuffer(),
+                    anchor.utils.bytes.utf8.encode("solend_reward"),
+                ],
+                program.programId
+            );
+
+            // Rewards are sent to the vault by the Solend reward distributor,
+            // which has no distribution on the local validator
+            const rewardAmount = 1000000;
+            await new Token(
+                program.provider.connection,
+                solend.accounts.rewardTokenMint,
+                TOKEN_PROGRAM_ID,
+                owner
+            ).mintTo(vaultSolendRewardToken, owner, [], rewardAmount);
+
+            const oldReserveBalance = await getVaultReserveTokenBalance();
+
+            await vaultClient.sellSolendReward(orca);
+
+            const remainingAmount = await getSplTokenAccountBalance(
+                solend.accounts.rewardTokenMint,
+                vaultSolendRewardToken
+            );
+            const newReserveBalance = await getVaultReserveTokenBalance();
+
+            assert.isAtMost(remainingAmount, 1);
+            assert.isAtLeast(
+                newReserveBalance - oldReserveBalance,
+                rewardAmount
+            );
+        });
+
+        // Highest reward rate that does not distribute more than the supply
+        // of the reward token in a year
+        async function getMaxSolendRewardRate(): Promise<anchor.BN> {
+            const mintInfo = await new Token(
+                program.provider.connection,
+                solend.accounts.rewardTokenMint,
+                TOKEN_PROGRAM_ID,
+                owner
+            ).getMintInfo();
+            return mintInfo.supply.div(new anchor.BN(slotsPerYear));
+        }
+
+        async function fetchSolendAdditionalState() {
+            const [solendAdditionalStates] = await PublicKey.findProgramAddress(
+                [
+                    vaultClient.vaultId.toBuffer(),
+                    anchor.utils.bytes.utf8.encode("solend_additional_state"),
+                ],
+                program.programId
+            );
+            return program.account.vaultSolendAdditionalState.fetch(
+                solendAdditionalStates
+            );
+        }
+
+        it("Update the Solend reward rate", async function () {
+            const rewardRatePerSlot = await getMaxSolendRewardRate();
+            await vaultClient.updateSolendRewards(owner, rewardRatePerSlot);
+
+            const state = await fetchSolendAdditionalState();
+            assert.isTrue(state.rewardRatePerSlot.eq(rewardRatePerSlot));
+            assert.isTrue(
+                state.solendRewardDistributor.equals(
+                    solend.accounts.rewardDistributor
+                )
+            );
+            assert.isTrue(
+                state.solendRewardDistributorProgram.equals(
+                    solendRewardDistributorProgram
+                )
+            );
+
+            await vaultClient.updateSolendRewards(owner, new anchor.BN(0));
+        });
+
+        it("Reject a reward rate over the reward token supply", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "RewardRateTooHigh")
+                .code.toString(16);
+
+            const rewardRatePerSlot = (await getMaxSolendRewardRate()).addn(1);
+            try {
+                await vaultClient.updateSolendRewards(owner, rewardRatePerSlot);
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            const state = await fetchSolendAdditionalState();
+            assert.isTrue(state.rewardRatePerSlot.eqn(0));
+
+            restoreLogs();
+        });
+
+        it("Reject a distributor of another program", async function () {
+            suppressLogs();
+
+            // ConstraintOwner
+            const errorCode = "0x7d4";
+
+            const distributor = solend.accounts.rewardDistributor;
+            solend.accounts.rewardDistributor =
+                await createSolendRewardDistributor(TOKEN_PROGRAM_ID);
+            try {
+                await vaultClient.updateSolendRewards(owner, new anchor.BN(0));
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+            solend.accounts.rewardDistributor = distributor;
+
+            const state = await fetchSolendAdditionalState();
+            assert.isTrue(state.solendRewardDistributor.equals(distributor));
+
+            restoreLogs();
+        });
+    }
+
+    async function sleep(t: number) {
+        return new Promise((res) => setTimeout(res, t));
+    }
+
+    function testFees(
+        feeCarryBps: number = 0,
+        feeMgmtBps: number = 0,
+        referalPct: number = 0
+    ) {
+        it("Collect fees", async function () {
+            const qty1 = 5.47 * 10 ** 9;
+            await mintReserveToken(userReserveTokenAccount, qty1);
+            const txs1 = await depositToVault(qty1);
+            const slots0 = await fetchSlots(txs1);
+
+            const vaultTotalValue = new anchor.BN(await getVaultTotalValue());
+            const lpTokenSupply = new anchor.BN(await getLpTokenSupply());
+
+            await sleep(1000);
+
+            // This is needed to trigger refresh and fee collection
+            // Consider adding an API to client library to trigger refresh
+            const qty2 = 10;
+            await mintReserveToken(userReserveTokenAccount, qty2);
+            const txs2 = await depositToVault(qty2);
+            const slots1 = await fetchSlots(txs2);
+
+            const expectedFees = await calculateFees(
+                vaultTotalValue,
+                lpTokenSupply,
+                slots0[slots0.length - 1],
+                slots1[slots1.length - 1],
+                feeCarryBps,
+                feeMgmtBps,
+                referalPct
+            );
+
+            const referralAccountInfo =
+                await vaultClient.getReferralFeeReceiverAccountInfo();
+            const feeAccountInfo =
+                await vaultClient.getFeeReceiverAccountInfo();
+
+            const actualReferralFees = referralAccountInfo.amount.toNumber();
+            const actualMgmtFees = feeAccountInfo.amount.toNumber();
+
+            // TODO why are management fees specifically off by 2 tokens?
+            const maxDiffAllowed = 2;
+
+            assert.isAtMost(
+                Math.abs(actualMgmtFees - expectedFees.primary.toNumber()),
+                maxDiffAllowed
+            );
+            assert.isAtMost(
+                Math.abs(actualReferralFees - expectedFees.referral.toNumber()),
+                maxDiffAllowed
+            );
+        });
+
+        it("Update fee rates", async function () {
+            const oldConfig = vaultClient.getVaultConfig();
+            const newConfig = {
+                ...oldConfig,
+                feeCarryBps: oldConfig.feeCarryBps / 2,
+                feeMgmtBps: oldConfig.feeMgmtBps / 2,
+                referralFeePct: oldConfig.referralFeePct / 2,
+            };
+            const txSig = await vaultClient.updateConfig(owner, newConfig);
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            await vaultClient.reload();
+            assert.equal(
+                newConfig.feeCarryBps,
+                vaultClient.getCarryFee().asBps().toNumber()
+            );
+            assert.equal(
+                newConfig.feeMgmtBps,
+                vaultClient.getManagementFee().asBps().toNumber()
+            );
+            assert.equal(
+                newConfig.referralFeePct,
+                vaultClient.getReferralFeeSplit().asPercent().toNumber()
+            );
+        });
+
+        it("Reject invalid fee rates", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "InvalidReferralFeeConfig")
+                .code.toString(16);
+
+            const oldConfig = vaultClient.getVaultConfig();
+            const newConfig = {
+                ...oldConfig,
+                feeCarryBps: oldConfig.feeCarryBps / 2,
+                feeMgmtBps: oldConfig.feeMgmtBps / 2,
+                referralFeePct: 110,
+            };
+            try {
+                const txSig = await vaultClient.updateConfig(owner, newConfig);
+                await provider.connection.confirmTransaction(
+                    txSig,
+                    "singleGossip"
+                );
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            await vaultClient.reload();
+            assert.equal(
+                oldConfig.feeCarryBps,
+                vaultClient.getCarryFee().asBps().toNumber()
+            );
+            assert.equal(
+                oldConfig.feeMgmtBps,
+                vaultClient.getManagementFee().asBps().toNumber()
+            );
+            assert.equal(
+                oldConfig.referralFeePct,
+                vaultClient.getReferralFeeSplit().asPercent().toNumber()
+            );
+
+            restoreLogs();
+        });
+    }
+
+    function testPortRewardClaiming(subReward: boolean) {
+        const depositQty = 3025004087;
+
+        before(async () => {
+            await mintReserveToken(userReserveTokenAccount, depositQty);
+            await depositToVault(depositQty);
+        });
+
+        if (!subReward) {
+            it("Reject a rebalance with a sub-reward oracle placeholder", async function () {
+                suppressLogs();
+
+                const errorCode = program.idl.errors
+                    .find((e) => e.name == "InvalidAccount")
+                    .code.toString(16);
+
+                // Pools without a sub-reward only pass the reward oracle
+                const remainingAccounts =
+                    await vaultClient.getRebalanceRemainingAccounts();
+                assert.isFalse(
+                    remainingAccounts.some((k) =>
+                        k.pubkey.equals(port.accounts.stakingSubRewardOracle)
+                    )
+                );
+
+                const preRefresh = (await vaultClient.getPreRefreshTxs()).map(
+                    (tx) => {
+                        return { tx: tx, signers: [] };
+                    }
+                );
+                const rebalanceTx = await vaultClient.getRebalanceTx({
+                    solend: 0,
+                    port: 10000,
+                });
+                const rebalanceIx =
+                    rebalanceTx.instructions[
+                        rebalanceTx.instructions.length - 1
+                    ];
+                const stakingPoolIndex = rebalanceIx.keys.findIndex((k) =>
+                    k.pubkey.equals(port.accounts.stakingPool)
+                );
+                rebalanceIx.keys.splice(stakingPoolIndex, 0, {
+                    pubkey: port.accounts.stakingSubRewardOracle,
+                    isSigner: false,
+                    isWritable: false,
+                });
+
+                try {
+                    await provider.sendAll([
+                        ...preRefresh,
+                        { tx: rebalanceTx, signers: [] },
+                    ]);
+                    assert.fail("Transaction should be rejected but was not.");
+                } catch (err) {
+                    assert.isTrue(
+                        err.message.includes(errorCode),
+                        `Error code ${errorCode} not included in error message: ${err}`
+                    );
+                }
+
+                restoreLogs();
+            });
+        }
+
+        it("Stake port LP token when rebalancing", async () => {
+            await performRebalance({
+                solend: 0,
+                port: 10000,
+            });
+
+            const maxDiffAllowed = 1;
+            const totalValue = await getVaultTotalValue();
+            assert.isAtMost(
+                Math.abs(totalValue - Math.floor(depositQty)),
+                maxDiffAllowed
+            );
+            const portValue = (
+                await vaultClient.getVaultPortLpTokenAccountValue()
+            ).lamports.toNumber();
+            assert.isAtMost(
+                Math.abs(portValue - Math.floor(depositQty)),
+                maxDiffAllowed
+            );
+
+            // Check that all allocations to port are staked for earning rewards
+            const stakingAccountRaw = await provider.connection.getAccountInfo(
+                new PublicKey(port.accounts.vaultPortStakeAccount)
+            );
+            const stakingAccount = StakeAccount.fromRaw({
+                pubkey: port.accounts.vaultPortStakeAccount,
+                account: stakingAccountRaw,
+            });
+            const reserve = await port.client.getReserve(port.accounts.reserve);
+            const exchangeRate = reserve.getExchangeRatio();
+            const stakedTokenValue = AssetPrice.of(
+                MintId.of(port.accounts.stakingRewardTokenMint),
+                stakingAccount.getDepositAmount().toU64().toNumber()
+            ).divide(exchangeRate.getUnchecked());
+            assert.isAtMost(
+                Math.abs(stakedTokenValue.getRaw().toNumber() - depositQty),
+                maxDiffAllowed
+            );
+        });
+
+        it("Withdraws", async function () {
+            const oldVaultValue = await getVaultTotalValue();
+            const oldLpTokenSupply = await getLpTokenSupply();
+
+            const withdrawQty = 922051;
+            await withdrawFromVault(withdrawQty);
+
+            const newUserReserveBalance = await getUserReserveTokenBalance();
+            const newVaultValue = await getVaultTotalValue();
+            const newLpTokenSupply = await getLpTokenSupply();
+
+            // Make sure that the correct amount is un-staked when the user withdraws
+            let maxDiffAllowed = 3;
+            const stakingAccountRaw = await provider.connection.getAccountInfo(
+                new PublicKey(port.accounts.vaultPortStakeAccount)
+            );
+            const stakingAccount = StakeAccount.fromRaw({
+                pubkey: port.accounts.vaultPortStakeAccount,
+                account: stakingAccountRaw,
+            });
+            const reserve = await port.client.getReserve(port.accounts.reserve);
+            const exchangeRate = reserve.getExchangeRatio();
+            const stakedTokenValue = AssetPrice.of(
+                MintId.of(port.accounts.stakingRewardTokenMint),
+                stakingAccount.getDepositAmount().toU64().toNumber()
+            ).divide(exchangeRate.getUnchecked());
+
+            assert.isAtMost(
+                Math.abs(
                     depositQty -
                         withdrawQty -
                         stakedTokenValue.getRaw().toNumber()
@@ -1227,35 +2642,427 @@ describe("castle-vault", () => {
             );
             assert.isAtLeast(claimedRewardAmount, accumulatedRewardAmount);
 
-            if (subReward) {
+            if (subReward) {
+                const claimedSubRewardAmount = await getSplTokenAccountBalance(
+                    port.accounts.stakingSubRewardTokenMint,
+                    port.accounts.vaultPortSubRewardToken
+                );
+                assert.isAtLeast(claimedSubRewardAmount, 1);
+            }
+        });
+
+        it("Migrate legacy Orca markets", async function () {
+            const marketId = orca.accounts.marketId;
+            const marketsAddress = orca.accounts.vaultOrcaLegacyMarkets;
+            const marketBefore = (
+                await program.account.dexMarkets.fetch(marketsAddress)
+            ).markets[marketId];
+
+            // No legacy entries are set, the registered market is kept
+            await vaultClient.migrateOrcaLegacyMarkets(owner);
+
+            const marketAfter = (
+                await program.account.dexMarkets.fetch(marketsAddress)
+            ).markets[marketId];
+            assert.isTrue(marketAfter.market.equals(marketBefore.market));
+            assert.isTrue(
+                marketAfter.inputMint.equals(
+                    port.accounts.stakingRewardTokenMint
+                )
+            );
+            assert.isTrue(
+                marketAfter.outputMint.equals(
+                    vaultClient.getReserveTokenMint()
+                )
+            );
+        });
+
+        it("Reject reward sale if swaps are halted", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "HaltedVault")
+                .code.toString(16);
+
+            const claimedRewardAmount = await getSplTokenAccountBalance(
+                port.accounts.stakingRewardTokenMint,
+                port.accounts.vaultPortRewardToken
+            );
+            const tx = await vaultClient.updateHaltFlags(
+                owner,
+                VaultFlags.HaltSwaps
+            );
+            await provider.connection.confirmTransaction(tx, "singleGossip");
+
+            try {
+                await vaultClient.sellPortReward();
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    port.accounts.stakingRewardTokenMint,
+                    port.accounts.vaultPortRewardToken
+                ),
+                claimedRewardAmount
+            );
+
+            await vaultClient.updateHaltFlags(owner, 0);
+
+            restoreLogs();
+        });
+
+        it("Sell reward", async function () {
+            const claimedRewardAmount = await getSplTokenAccountBalance(
+                port.accounts.stakingRewardTokenMint,
+                port.accounts.vaultPortRewardToken
+            );
+            const oldReserveBalance = await getVaultReserveTokenBalance();
+
+            await vaultClient.sellPortReward();
+
+            const remainingAmount = await getSplTokenAccountBalance(
+                port.accounts.stakingRewardTokenMint,
+                port.accounts.vaultPortRewardToken
+            );
+            const newReserveBalance = await getVaultReserveTokenBalance();
+
+            assert.isAtMost(remainingAmount, 1);
+            assert.isAtLeast(
+                newReserveBalance - oldReserveBalance,
+                claimedRewardAmount
+            );
+        });
+
+        it("Sell reward through a multi-hop route", async function () {
+            const rewardToken = new Token(
+                program.provider.connection,
+                port.accounts.stakingRewardTokenMint,
+                TOKEN_PROGRAM_ID,
+                owner
+            );
+
+            // There is no direct market, so the reward is sold
+            // through an intermediate token
+            const intermediateToken = await Token.createMint(
+                program.provider.connection,
+                wallet.payer,
+                owner.publicKey,
+                null,
+                6,
+                TOKEN_PROGRAM_ID
+            );
+            const markets = [
+                await OrcaLegacySwap.initialize(
+                    provider,
+                    wallet.payer,
+                    rewardToken,
+                    intermediateToken,
+                    owner,
+                    owner
+                ),
+                await OrcaLegacySwap.initialize(
+                    provider,
+                    wallet.payer,
+                    intermediateToken,
+                    reserveToken,
+                    owner,
+                    owner
+                ),
+            ];
+            markets[0].accounts.marketId = 2;
+            markets[1].accounts.marketId = 3;
+            await vaultClient.addOrcaLegacyMarket(
+                wallet,
+                owner,
+                markets[0],
+                rewardToken.publicKey,
+                intermediateToken.publicKey
+            );
+            await vaultClient.addOrcaLegacyMarket(
+                wallet,
+                owner,
+                markets[1],
+                intermediateToken.publicKey
+            );
+            const intermediateTokenAccount =
+                await intermediateToken.createAccount(
+                    vaultClient.getVaultState().vaultAuthority
+                );
+
+            const rewardAmount = 1000000;
+            await rewardToken.mintTo(
+                port.accounts.vaultPortRewardToken,
+                owner,
+                [],
+                rewardAmount
+            );
+            const oldReserveBalance = await getVaultReserveTokenBalance();
+
+            await vaultClient.sellPortRewardRoute(markets, [
+                intermediateTokenAccount,
+            ]);
+
+            const remainingAmount = await getSplTokenAccountBalance(
+                port.accounts.stakingRewardTokenMint,
+                port.accounts.vaultPortRewardToken
+            );
+            const intermediateAmount = await getSplTokenAccountBalance(
+                intermediateToken.publicKey,
+                intermediateTokenAccount
+            );
+            const newReserveBalance = await getVaultReserveTokenBalance();
+
+            assert.isAtMost(remainingAmount, 1);
+            assert.equal(intermediateAmount, 0);
+            assert.isAtLeast(
+                newReserveBalance - oldReserveBalance,
+                rewardAmount
+            );
+        });
+
+        it("Reject reward sale below the oracle minimum", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "SwapQuoteBelowMinimum")
+                .code.toString(16);
+
+            const rewardToken = new Token(
+                program.provider.connection,
+                port.accounts.stakingRewardTokenMint,
+                TOKEN_PROGRAM_ID,
+                owner
+            );
+            const market = await OrcaLegacySwap.initialize(
+                provider,
+                wallet.payer,
+                rewardToken,
+                reserveToken,
+                owner,
+                owner
+            );
+            market.accounts.marketId = 4;
+            await vaultClient.addOrcaLegacyMarket(
+                wallet,
+                owner,
+                market,
+                rewardToken.publicKey
+            );
+
+            // Flood the pool with reward tokens so that it quotes
+            // far below the oracle price
+            await rewardToken.mintTo(
+                market.accounts.tokenAccountA,
+                owner,
+                [],
+                new u64("1000000000000000000")
+            );
+
+            const rewardAmount = 1000000000;
+            await rewardToken.mintTo(
+                port.accounts.vaultPortRewardToken,
+                owner,
+                [],
+                rewardAmount
+            );
+            const oldReserveBalance = await getVaultReserveTokenBalance();
+
+            try {
+                await vaultClient.sellPortRewardRoute([market], []);
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            assert.equal(
+                await getSplTokenAccountBalance(
+                    port.accounts.stakingRewardTokenMint,
+                    port.accounts.vaultPortRewardToken
+                ),
+                rewardAmount
+            );
+            assert.equal(
+                await getVaultReserveTokenBalance(),
+                oldReserveBalance
+            );
+
+            restoreLogs();
+        });
+
+        it("Reject reward sale with a stale oracle price", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "StaleOraclePrice")
+                .code.toString(16);
+
+            const oldConfig = vaultClient.getVaultConfig();
+            const txSig = await vaultClient.updateConfig(owner, {
+                ...oldConfig,
+                oracleMaxAge: new anchor.BN(1),
+            });
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            // The cloned oracle is never updated on localnet
+            await sleep(2000);
+
+            try {
+                await vaultClient.sellPortReward();
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            await vaultClient.updateConfig(owner, oldConfig);
+
+            restoreLogs();
+        });
+
+        it("Reject reward sale with a low-confidence oracle price", async function () {
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "OracleConfidenceTooWide")
+                .code.toString(16);
+
+            // Aggregate price and confidence of the Pyth price account
+            const oracle = await provider.connection.getAccountInfo(
+                port.accounts.stakingRewardOracle
+            );
+            const price = new anchor.BN(oracle.data.slice(208, 216), "le");
+            const conf = new anchor.BN(oracle.data.slice(216, 224), "le");
+            const confidenceBps = conf.muln(10000).div(price).toNumber();
+            if (confidenceBps < 2) {
+                restoreLogs();
+                this.skip();
+            }
+
+            const oldConfig = vaultClient.getVaultConfig();
+            const txSig = await vaultClient.updateConfig(owner, {
+                ...oldConfig,
+                oracleMaxConfidenceBps: confidenceBps - 1,
+            });
+            await provider.connection.confirmTransaction(txSig, "singleGossip");
+
+            try {
+                await vaultClient.sellPortReward();
+                assert.fail("Transaction should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            await vaultClient.updateConfig(owner, oldConfig);
+
+            restoreLogs();
+        });
+
+        let subRewardOrca: OrcaLegacySwap = undefined;
+        if (subReward) {
+            it("Sell sub-reward", async function () {
+                subRewardOrca = await OrcaLegacySwap.initialize(
+                    provider,
+                    wallet.payer,
+                    new Token(
+                        program.provider.connection,
+                        port.accounts.stakingSubRewardTokenMint,
+                        TOKEN_PROGRAM_ID,
+                        owner
+                    ),
+                    reserveToken,
+                    owner,
+                    owner
+                );
+                subRewardOrca.accounts.marketId = 1;
+                await vaultClient.addOrcaLegacyMarket(
+                    wallet,
+                    owner,
+                    subRewardOrca,
+                    port.accounts.stakingSubRewardTokenMint
+                );
+
                 const claimedSubRewardAmount = await getSplTokenAccountBalance(
                     port.accounts.stakingSubRewardTokenMint,
                     port.accounts.vaultPortSubRewardToken
                 );
-                assert.isAtLeast(claimedSubRewardAmount, 1);
-            }
-        });
+                const oldReserveBalance = await getVaultReserveTokenBalance();
 
-        it("Sell reward", async function () {
-            const claimedRewardAmount = await getSplTokenAccountBalance(
-                port.accounts.stakingRewardTokenMint,
-                port.accounts.vaultPortRewardToken
-            );
+                await vaultClient.sellPortSubReward(subRewardOrca);
+
+                const remainingAmount = await getSplTokenAccountBalance(
+                    port.accounts.stakingSubRewardTokenMint,
+                    port.accounts.vaultPortSubRewardToken
+                );
+                const newReserveBalance = await getVaultReserveTokenBalance();
+
+                assert.isAtMost(remainingAmount, 1);
+                assert.isAtLeast(
+                    newReserveBalance - oldReserveBalance,
+                    claimedSubRewardAmount
+                );
+            });
+        }
+
+        it("Harvest rewards", async function () {
             const oldReserveBalance = await getVaultReserveTokenBalance();
 
-            await vaultClient.sellPortReward();
+            await vaultClient.harvest(subRewardOrca);
 
             const remainingAmount = await getSplTokenAccountBalance(
                 port.accounts.stakingRewardTokenMint,
                 port.accounts.vaultPortRewardToken
             );
+            assert.isAtMost(remainingAmount, 1);
+            if (subReward) {
+                const remainingSubRewardAmount =
+                    await getSplTokenAccountBalance(
+                        port.accounts.stakingSubRewardTokenMint,
+                        port.accounts.vaultPortSubRewardToken
+                    );
+                assert.isAtMost(remainingSubRewardAmount, 1);
+            }
+
             const newReserveBalance = await getVaultReserveTokenBalance();
+            assert.isAbove(newReserveBalance, oldReserveBalance);
 
-            assert.isAtMost(remainingAmount, 1);
-            assert.isAtLeast(
-                newReserveBalance - oldReserveBalance,
-                claimedRewardAmount
+            await vaultClient.reload();
+            assert.isAbove(
+                vaultClient.getVaultState().lastHarvestTimestamp.toNumber(),
+                0
             );
+
+            // Harvests are rate limited by the min harvest interval
+            suppressLogs();
+
+            const errorCode = program.idl.errors
+                .find((e) => e.name == "HarvestTooSoon")
+                .code.toString(16);
+
+            try {
+                await vaultClient.harvest(subRewardOrca);
+                assert.fail("Harvest should be rejected but was not.");
+            } catch (err) {
+                assert.isTrue(
+                    err.message.includes(errorCode),
+                    `Error code ${errorCode} not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
         });
     }
 
@@ -1567,6 +3374,331 @@ describe("castle-vault", () => {
         });
     });
 
+    describe("PDA vault", () => {
+        const config = {
+            rebalanceMode: { [RebalanceModes.calculator]: {} },
+            strategyType: { [StrategyTypes.equalAllocation]: {} },
+        };
+
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault(config, true, true, new anchor.BN(0));
+        });
+
+        it("Registers the vault at its PDA address", async function () {
+            const [vaultId] = await PublicKey.findProgramAddress(
+                [
+                    anchor.utils.bytes.utf8.encode("vault"),
+                    reserveToken.publicKey.toBuffer(),
+                    owner.publicKey.toBuffer(),
+                    new anchor.BN(0).toArrayLike(Buffer, "le", 8),
+                ],
+                program.programId
+            );
+            assert.isTrue(vaultClient.vaultId.equals(vaultId));
+
+            const registeredVaults = await VaultClient.getRegisteredVaults(
+                program,
+                reserveToken.publicKey,
+                owner.publicKey
+            );
+            assert.equal(registeredVaults.length, 1);
+            assert.isTrue(registeredVaults[0].equals(vaultId));
+        });
+
+        testDepositAndWithdrawal();
+
+        it("Reject a vault with a used nonce", async function () {
+            suppressLogs();
+
+            try {
+                await VaultClient.initializePda(
+                    provider,
+                    wallet,
+                    DeploymentEnvs.devnetStaging,
+                    reserveToken.publicKey,
+                    owner,
+                    referralFeeOwner,
+                    new anchor.BN(0),
+                    config,
+                    program
+                );
+                assert.fail("Vault initialization should fail but did not");
+            } catch (err) {
+                // The vault account is already in use
+                assert.isTrue(
+                    err.message.includes("0x0"),
+                    `Error code 0x0 not included in error message: ${err}`
+                );
+            }
+
+            restoreLogs();
+
+            const registeredVaults = await VaultClient.getRegisteredVaults(
+                program,
+                reserveToken.publicKey,
+                owner.publicKey
+            );
+            assert.equal(registeredVaults.length, 1);
+        });
+    });
+
+    describe("Refresh all", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testRefreshAll();
+    });
+
+    describe("Deposit and withdraw with refresh", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testDepositAndWithdrawWithRefresh();
+    });
+
+    describe("Realized losses", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testRealizedLosses();
+    });
+
+    describe("Insurance reserve", () => {
+        const insuranceTarget = 10;
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                feeCarryBps: 10000,
+                feeMgmtBps: 10000,
+                referralFeePct: 20,
+                insuranceFeePct: 50,
+                insuranceTarget: new anchor.BN(insuranceTarget),
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testInsuranceReserve(insuranceTarget);
+    });
+
+    describe("Auto-deploy", () => {
+        const autoDeployMax = 200000;
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                autoDeployMax: new anchor.BN(autoDeployMax),
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testAutoDeploy(autoDeployMax);
+    });
+
+    describe("Reconcile all", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testReconcileAll();
+    });
+
+    describe("Partial reconciles", () => {
+        const maxReconcileMove = 200000;
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                maxReconcileMove: new anchor.BN(maxReconcileMove),
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testPartialReconciles(maxReconcileMove);
+    });
+
+    describe("Dust reconciles", () => {
+        const minReconcileMove = 1000;
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                minReconcileMove: new anchor.BN(minReconcileMove),
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testDustReconciles(minReconcileMove);
+    });
+
+    describe("Whirlpool registry", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testWhirlpoolRegistry();
+    });
+
+    describe("DEX market registry", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testDexMarketRegistry();
+    });
+
+    describe("Reward valuation", () => {
+        before(async function () {
+            await initLendingMarkets(false);
+        });
+        before(async function () {
+            await initializeVault(
+                {
+                    allocationCapPct: 100,
+                    valueRewards: true,
+                    rewardValueHaircutBps: 1000,
+                    rebalanceMode: { [RebalanceModes.calculator]: {} },
+                    strategyType: { [StrategyTypes.equalAllocation]: {} },
+                },
+                false,
+                true
+            );
+        });
+
+        testRewardValuation();
+    });
+
+    describe("Sub-reward rebalance", () => {
+        before(async function () {
+            await initLendingMarkets(true);
+        });
+        before(async function () {
+            await initializeVault(
+                {
+                    allocationCapPct: 100,
+                    rebalanceMode: { [RebalanceModes.calculator]: {} },
+                    strategyType: { [StrategyTypes.maxYield]: {} },
+                },
+                false,
+                true
+            );
+        });
+
+        testSubRewardRebalance();
+    });
+
+    describe("Withdraw-only yield sources", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                allocationCapPct: 100,
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testWithdrawOnly();
+    });
+
+    describe("Migration", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testMigrate();
+    });
+
+    describe("Depositors", () => {
+        const perUserDepositCap = 1000000;
+
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                permissioned: true,
+                perUserDepositCap: new anchor.BN(perUserDepositCap),
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testDepositors(perUserDepositCap);
+    });
+
+    describe("Early withdrawal fee", () => {
+        const earlyWithdrawFeeBps = 100;
+
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                lockupSeconds: new anchor.BN(3600),
+                earlyWithdrawFeeBps: earlyWithdrawFeeBps,
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+        });
+
+        testEarlyWithdrawFee(earlyWithdrawFeeBps);
+    });
+
+    describe("Solend obligation", () => {
+        before(initLendingMarkets);
+        before(async function () {
+            await initializeVault({
+                rebalanceMode: { [RebalanceModes.calculator]: {} },
+                strategyType: { [StrategyTypes.equalAllocation]: {} },
+            });
+
+            // The Port reward token and its Orca pool stand in for
+            // the Solend liquidity mining reward
+            solend.accounts.rewardTokenMint =
+                port.accounts.stakingRewardTokenMint;
+            solend.accounts.rewardTokenOracle =
+                port.accounts.stakingRewardOracle;
+            solend.accounts.rewardDistributor =
+                await createSolendRewardDistributor();
+            await vaultClient.initializeSolendRewardAccounts(
+                wallet,
+                owner,
+                new anchor.BN(0)
+            );
+        });
+
+        testRebalanceWithdraw(1 / 2, 1 / 2);
+        testSolendObligation();
+    });
+
     describe("Orca swap", () => {
         it("Find market mainnet", () => {
             const portToken = new PublicKey(